@@ -1,3 +1,4 @@
+use cache::invalidation::CacheInvalidationTransport;
 use envconfig::Envconfig;
 use osentities::{cache::CacheConfig, environment::Environment};
 use osentities::{database::DatabaseConfig, secrets::SecretsConfig};
@@ -6,6 +7,7 @@ use std::{
     net::SocketAddr,
 };
 use strum::{AsRefStr, EnumString};
+use unified::domain::CommonModelValidation;
 
 #[derive(Envconfig, Clone)]
 pub struct ConnectionsConfig {
@@ -33,6 +35,26 @@ pub struct ConnectionsConfig {
     pub connection_model_definition_cache_ttl_secs: u64,
     #[envconfig(from = "SECRET_CACHE_TTL_SECS", default = "300")]
     pub secret_cache_ttl_secs: u64,
+    /// How long "not found" lookups are remembered, per cache. Zero disables
+    /// negative caching.
+    #[envconfig(from = "ACCESS_KEY_NEGATIVE_CACHE_TTL_SECS", default = "10")]
+    pub access_key_negative_cache_ttl_secs: u64,
+    #[envconfig(from = "CONNECTION_NEGATIVE_CACHE_TTL_SECS", default = "10")]
+    pub connection_negative_cache_ttl_secs: u64,
+    #[envconfig(from = "CONNECTION_DEFINITION_NEGATIVE_CACHE_TTL_SECS", default = "60")]
+    pub connection_definition_negative_cache_ttl_secs: u64,
+    #[envconfig(
+        from = "CONNECTION_OAUTH_DEFINITION_NEGATIVE_CACHE_TTL_SECS",
+        default = "60"
+    )]
+    pub connection_oauth_definition_negative_cache_ttl_secs: u64,
+    #[envconfig(
+        from = "CONNECTION_MODEL_DEFINITION_NEGATIVE_CACHE_TTL_SECS",
+        default = "60"
+    )]
+    pub connection_model_definition_negative_cache_ttl_secs: u64,
+    #[envconfig(from = "REMOTE_CACHE_ENABLED", default = "false")]
+    pub remote_cache_enabled: bool,
     #[envconfig(
         from = "EVENT_ACCESS_PASSWORD",
         default = "32KFFT_i4UpkJmyPwY2TGzgHpxfXs7zS"
@@ -77,6 +99,33 @@ pub struct ConnectionsConfig {
     pub cache_config: CacheConfig,
     #[envconfig(from = "RATE_LIMIT_ENABLED", default = "true")]
     pub rate_limit_enabled: bool,
+    /// An ownership's throughput is the number of requests it may make per window
+    #[envconfig(from = "RATE_LIMIT_WINDOW_SECS", default = "60")]
+    pub rate_limit_window_secs: u64,
+    /// Extra requests a bucket can hold on top of its limit, as a percentage
+    #[envconfig(from = "RATE_LIMIT_BURST_PERCENT", default = "0")]
+    pub rate_limit_burst_percent: u64,
+    /// Requests per window for a single connection key, 0 disables the scope
+    #[envconfig(from = "RATE_LIMIT_CONNECTION_LIMIT", default = "0")]
+    pub rate_limit_connection_limit: u64,
+    /// Requests per window for an ownership on a single platform, 0 disables the scope
+    #[envconfig(from = "RATE_LIMIT_PLATFORM_LIMIT", default = "0")]
+    pub rate_limit_platform_limit: u64,
+    /// While redis is unreachable each replica enforces this share of every limit
+    #[envconfig(from = "RATE_LIMIT_REPLICAS", default = "1")]
+    pub rate_limit_replicas: u64,
+    #[envconfig(from = "RATE_LIMIT_RECONNECT_INTERVAL_SECS", default = "10")]
+    pub rate_limit_reconnect_interval_secs: u64,
+    #[envconfig(from = "QUOTA_ENABLED", default = "true")]
+    pub quota_enabled: bool,
+    /// Applies to ownerships without quotas in their billing, 0 disables it
+    #[envconfig(from = "QUOTA_DAILY_LIMIT", default = "0")]
+    pub quota_daily_limit: u64,
+    /// Applies to ownerships without quotas in their billing, 0 disables it
+    #[envconfig(from = "QUOTA_MONTHLY_LIMIT", default = "0")]
+    pub quota_monthly_limit: u64,
+    #[envconfig(from = "QUOTA_CACHE_TTL_SECS", default = "300")]
+    pub quota_cache_ttl_secs: u64,
     #[envconfig(from = "ENVIRONMENT", default = "development")]
     pub environment: Environment,
     #[envconfig(from = "DATABASE_CONNECTION_DOCKER_IMAGE", default = "pica-database")]
@@ -89,6 +138,14 @@ pub struct ConnectionsConfig {
     pub k8s_mode: K8sMode,
     #[envconfig(from = "OTLP_ENDPOINT")]
     pub otlp_endpoint: Option<String>,
+    /// Whether mapped unified responses are checked against their common model
+    /// (`off`, `lenient` or `strict`)
+    #[envconfig(from = "COMMON_MODEL_VALIDATION", default = "off")]
+    pub common_model_validation: CommonModelValidation,
+    /// How replicas tell each other to evict stale cache entries after a
+    /// write (`none`, `redis` or `mongo`)
+    #[envconfig(from = "CACHE_INVALIDATION_TRANSPORT", default = "none")]
+    pub cache_invalidation_transport: CacheInvalidationTransport,
 }
 
 impl Display for ConnectionsConfig {
@@ -129,6 +186,32 @@ impl Display for ConnectionsConfig {
             "CONNECTION_OAUTH_DEFINITION_CACHE_TTL_SECS: {}",
             self.connection_oauth_definition_cache_ttl_secs
         )?;
+        writeln!(
+            f,
+            "ACCESS_KEY_NEGATIVE_CACHE_TTL_SECS: {}",
+            self.access_key_negative_cache_ttl_secs
+        )?;
+        writeln!(
+            f,
+            "CONNECTION_NEGATIVE_CACHE_TTL_SECS: {}",
+            self.connection_negative_cache_ttl_secs
+        )?;
+        writeln!(
+            f,
+            "CONNECTION_DEFINITION_NEGATIVE_CACHE_TTL_SECS: {}",
+            self.connection_definition_negative_cache_ttl_secs
+        )?;
+        writeln!(
+            f,
+            "CONNECTION_OAUTH_DEFINITION_NEGATIVE_CACHE_TTL_SECS: {}",
+            self.connection_oauth_definition_negative_cache_ttl_secs
+        )?;
+        writeln!(
+            f,
+            "CONNECTION_MODEL_DEFINITION_NEGATIVE_CACHE_TTL_SECS: {}",
+            self.connection_model_definition_negative_cache_ttl_secs
+        )?;
+        writeln!(f, "REMOTE_CACHE_ENABLED: {}", self.remote_cache_enabled)?;
         writeln!(
             f,
             "EVENT_SAVE_TIMEOUT_SECS: {}",
@@ -149,6 +232,42 @@ impl Display for ConnectionsConfig {
         writeln!(f, "{}", self.db_config)?;
         writeln!(f, "{}", self.cache_config)?;
         writeln!(f, "RATE_LIMIT_ENABLED: {}", self.rate_limit_enabled)?;
+        writeln!(f, "RATE_LIMIT_WINDOW_SECS: {}", self.rate_limit_window_secs)?;
+        writeln!(
+            f,
+            "RATE_LIMIT_BURST_PERCENT: {}",
+            self.rate_limit_burst_percent
+        )?;
+        writeln!(
+            f,
+            "RATE_LIMIT_CONNECTION_LIMIT: {}",
+            self.rate_limit_connection_limit
+        )?;
+        writeln!(
+            f,
+            "RATE_LIMIT_PLATFORM_LIMIT: {}",
+            self.rate_limit_platform_limit
+        )?;
+        writeln!(f, "RATE_LIMIT_REPLICAS: {}", self.rate_limit_replicas)?;
+        writeln!(
+            f,
+            "RATE_LIMIT_RECONNECT_INTERVAL_SECS: {}",
+            self.rate_limit_reconnect_interval_secs
+        )?;
+        writeln!(f, "QUOTA_ENABLED: {}", self.quota_enabled)?;
+        writeln!(f, "QUOTA_DAILY_LIMIT: {}", self.quota_daily_limit)?;
+        writeln!(f, "QUOTA_MONTHLY_LIMIT: {}", self.quota_monthly_limit)?;
+        writeln!(f, "QUOTA_CACHE_TTL_SECS: {}", self.quota_cache_ttl_secs)?;
+        writeln!(
+            f,
+            "COMMON_MODEL_VALIDATION: {}",
+            self.common_model_validation.as_ref()
+        )?;
+        writeln!(
+            f,
+            "CACHE_INVALIDATION_TRANSPORT: {}",
+            self.cache_invalidation_transport.as_ref()
+        )?;
         writeln!(f, "ENVIRONMENT: {}", self.environment)?;
         writeln!(
             f,
@@ -179,6 +298,12 @@ pub struct Headers {
     pub rate_limit_remaining: String,
     #[envconfig(from = "HEADER_RATE_LIMIT_REST", default = "x-pica-rate-limit-reset")]
     pub rate_limit_reset: String,
+    #[envconfig(from = "HEADER_QUOTA_LIMIT", default = "x-pica-quota-limit")]
+    pub quota_limit: String,
+    #[envconfig(from = "HEADER_QUOTA_REMAINING", default = "x-pica-quota-remaining")]
+    pub quota_remaining: String,
+    #[envconfig(from = "HEADER_QUOTA_RESET", default = "x-pica-quota-reset")]
+    pub quota_reset: String,
 }
 
 impl Headers {
@@ -202,7 +327,10 @@ impl Display for Headers {
             "HEADER_RATE_LIMIT_REMAINING: {}",
             self.rate_limit_remaining
         )?;
-        writeln!(f, "HEADER_RATE_LIMIT_RESET: {}", self.rate_limit_reset)
+        writeln!(f, "HEADER_RATE_LIMIT_RESET: {}", self.rate_limit_reset)?;
+        writeln!(f, "HEADER_QUOTA_LIMIT: {}", self.quota_limit)?;
+        writeln!(f, "HEADER_QUOTA_REMAINING: {}", self.quota_remaining)?;
+        writeln!(f, "HEADER_QUOTA_RESET: {}", self.quota_reset)
     }
 }
 
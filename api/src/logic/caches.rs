@@ -0,0 +1,121 @@
+use crate::{router::ServerResponse, server::AppState};
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use cache::{
+    invalidation::CacheInvalidation,
+    local::{CacheAdmin, CacheStats},
+};
+use osentities::{ApplicationError, PicaError};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NamedCacheStats {
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub stats: CacheStats,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EvictQuery {
+    /// Entries are addressed by their `CacheKey`. Those of the `event-access`
+    /// and `connection-headers` caches are keyed by the `secret_digest` of the
+    /// access key, which is never sent as is.
+    pub key: String,
+}
+
+fn named_caches(state: &AppState) -> [(&'static str, &dyn CacheAdmin); 10] {
+    let unified = &state.extractor_caller;
+
+    [
+        ("event-access", &state.event_access_cache),
+        ("connection-headers", &state.connections_cache),
+        (
+            "connection-definitions",
+            &state.connection_definitions_cache,
+        ),
+        (
+            "connection-oauth-definitions",
+            &state.connection_oauth_definitions_cache,
+        ),
+        ("connections", &unified.connections_cache),
+        (
+            "connection-model-definitions",
+            &unified.connection_model_definitions_cache,
+        ),
+        (
+            "connection-model-schemas",
+            &unified.connection_model_schemas_cache,
+        ),
+        ("common-models", &unified.common_models_cache),
+        ("secrets", &unified.secrets_cache),
+        ("jwt-assertion-tokens", &unified.jwt_assertion_tokens_cache),
+    ]
+}
+
+pub(crate) fn find_cache<'a>(
+    state: &'a AppState,
+    name: &str,
+) -> Result<&'a dyn CacheAdmin, PicaError> {
+    named_caches(state)
+        .into_iter()
+        .find_map(|(n, cache)| (n == name).then_some(cache))
+        .ok_or_else(|| ApplicationError::not_found(&format!("Cache {name} not found"), None))
+}
+
+pub async fn list_caches(
+    State(state): State<Arc<AppState>>,
+) -> Json<ServerResponse<Vec<NamedCacheStats>>> {
+    let mut caches = vec![];
+    for (name, cache) in named_caches(&state) {
+        caches.push(NamedCacheStats {
+            name,
+            stats: cache.stats().await,
+        });
+    }
+
+    Json(ServerResponse::new("caches", caches))
+}
+
+pub async fn flush_cache(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<Json<ServerResponse<Value>>, PicaError> {
+    find_cache(&state, &name)?.flush().await?;
+    // Other replicas hold their own copies
+    state
+        .app_stores
+        .cache_invalidator
+        .publish(CacheInvalidation::Cache { name: name.clone() })
+        .await?;
+
+    Ok(Json(ServerResponse::new(
+        "cache",
+        json!({ "name": name, "flushed": true }),
+    )))
+}
+
+pub async fn evict_cache_entry(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Query(query): Query<EvictQuery>,
+) -> Result<Json<ServerResponse<Value>>, PicaError> {
+    find_cache(&state, &name)?.evict(&query.key).await?;
+    state
+        .app_stores
+        .cache_invalidator
+        .publish(CacheInvalidation::CacheEntry {
+            name: name.clone(),
+            key: query.key.clone(),
+        })
+        .await?;
+
+    Ok(Json(ServerResponse::new(
+        "cache",
+        json!({ "name": name, "evicted": query.key }),
+    )))
+}
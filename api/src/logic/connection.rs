@@ -1,4 +1,4 @@
-use super::{delete, read, PublicExt, RequestExt};
+use super::{delete, read, HookExt, PublicExt, RequestExt};
 use crate::{
     helper::{DeploymentSpecParams, ServiceName, ServiceSpecParams},
     logic::event_access::{
@@ -13,27 +13,35 @@ use axum::{
     routing::{delete as axum_delete, get, patch, post},
     Extension, Json, Router,
 };
+use cache::{invalidation::CacheInvalidation, local::LocalCacheExt};
 use chrono::Utc;
 use envconfig::Envconfig;
-use http::HeaderMap;
+use http::{HeaderMap, HeaderValue};
 use k8s_openapi::{
     api::core::v1::{ContainerPort, EnvVar, EnvVarSource, SecretKeySelector, ServicePort},
     apimachinery::pkg::util::intstr::IntOrString,
 };
 use mongodb::bson::doc;
 use osentities::{
-    algebra::MongoStore,
+    algebra::{
+        client_credentials_grant, client_credentials_payload, oauth_expires_at, revoke_token,
+        MongoStore,
+    },
+    api_model_config::AuthMethod,
     connection_definition::{ConnectionDefinition, ConnectionDefinitionType},
+    connection_oauth_definition::OAuthGrantType,
     database::{DatabasePodConfig, PostgresConfig},
     database_secret::DatabaseConnectionSecret,
     domain::connection::SanitizedConnection,
+    environment::Environment,
     event_access::EventAccess,
     id::{prefix::IdPrefix, Id},
+    oauth_secret::OAuthSecret,
     record_metadata::RecordMetadata,
     settings::Settings,
-    ApplicationError, Connection, ConnectionIdentityType, ConnectionType, InternalError, PicaError,
-    Throughput, APP_LABEL, DATABASE_TYPE_LABEL, DEFAULT_NAMESPACE, JWT_SECRET_REF_KEY,
-    JWT_SECRET_REF_NAME,
+    ApplicationError, Connection, ConnectionIdentityType, ConnectionType, InternalError, OAuth,
+    PicaError, Throughput, Unit, APP_LABEL, DATABASE_TYPE_LABEL, DEFAULT_NAMESPACE,
+    JWT_SECRET_REF_KEY, JWT_SECRET_REF_NAME, OAUTH_CLIENT_ID_KEY, OAUTH_CLIENT_SECRET_KEY,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
@@ -52,6 +60,7 @@ pub fn get_router() -> Router<Arc<AppState>> {
         .route("/", get(read::<CreateConnectionPayload, Connection>))
         .route("/:id", patch(update_connection))
         .route("/:id", axum_delete(delete_connection))
+        .route("/:id/migrate", post(migrate_connection))
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Validate)]
@@ -154,6 +163,22 @@ impl PublicExt<Connection> for CreateConnectionPayload {
     }
 }
 
+impl HookExt<Connection> for CreateConnectionPayload {
+    async fn after_update_hook(record: &Connection, stores: &AppStores) -> Result<Unit, PicaError> {
+        stores
+            .cache_invalidator
+            .publish(CacheInvalidation::from(record))
+            .await
+    }
+
+    async fn after_delete_hook(record: &Connection, stores: &AppStores) -> Result<Unit, PicaError> {
+        stores
+            .cache_invalidator
+            .publish(CacheInvalidation::from(record))
+            .await
+    }
+}
+
 impl RequestExt for CreateConnectionPayload {
     type Output = Connection;
 
@@ -258,6 +283,18 @@ pub async fn create_connection(
         generate_k8s_specs_and_secret(&connection_id, &state, &connection_config, &auth_form_data)
             .await?;
 
+    let (secret_value, oauth) = match client_credentials_secret(
+        &state,
+        &connection_config,
+        access.environment,
+        &payload.auth_form_data,
+    )
+    .await?
+    {
+        Some((oauth_secret, oauth)) => (oauth_secret.as_json(), Some(oauth)),
+        None => (secret_value, None),
+    };
+
     if let (Some(service), Some(deployment)) = (service.clone(), deployment.clone()) {
         state.k8s_client.coordinator(service, deployment).await?;
     }
@@ -315,7 +352,7 @@ pub async fn create_connection(
             limit: throughput,
         },
         ownership: event_access.ownership,
-        oauth: None,
+        oauth,
         record_metadata: RecordMetadata::default(),
     };
 
@@ -331,6 +368,108 @@ pub async fn create_connection(
     Ok(Json(conn.into()))
 }
 
+async fn revoke_oauth_tokens(
+    state: &AppState,
+    connection_oauth_definition_id: &Id,
+    connection: &Connection,
+) -> Result<(), PicaError> {
+    let definition = state
+        .app_stores
+        .oauth_config
+        .get_one_by_id(&connection_oauth_definition_id.to_string())
+        .await?
+        .ok_or_else(|| ApplicationError::not_found("Connection OAuth definition", None))?;
+
+    let oauth_secret: OAuthSecret = state
+        .secrets_client
+        .get(&connection.secrets_service_id, &connection.ownership.id)
+        .await?
+        .decode()?;
+
+    revoke_token(
+        &state.http_client,
+        &definition,
+        &oauth_secret,
+        &state.template,
+    )
+    .await
+}
+
+/// Platforms using the client credentials grant get their token when the
+/// connection is created. The client ID and secret are taken from the auth form
+/// and the rest of the form is kept as the metadata the grant is rendered with.
+async fn client_credentials_secret(
+    state: &AppState,
+    connection_config: &ConnectionDefinition,
+    environment: Environment,
+    auth_form_data: &HashMap<String, String>,
+) -> Result<Option<(OAuthSecret, OAuth)>, PicaError> {
+    if !matches!(connection_config.auth_method, Some(AuthMethod::OAuth)) {
+        return Ok(None);
+    }
+
+    let definition = state
+        .app_stores
+        .oauth_config
+        .get_one(doc! { "connectionPlatform": &connection_config.platform })
+        .await?
+        .filter(|definition| definition.grant_type == OAuthGrantType::ClientCredentials);
+
+    let Some(definition) = definition else {
+        return Ok(None);
+    };
+
+    let mut metadata = auth_form_data.clone();
+    let client_id = metadata.remove(OAUTH_CLIENT_ID_KEY).ok_or_else(|| {
+        ApplicationError::bad_request(
+            &format!("Missing {OAUTH_CLIENT_ID_KEY} in auth form data"),
+            None,
+        )
+    })?;
+    let client_secret = metadata.remove(OAUTH_CLIENT_SECRET_KEY).ok_or_else(|| {
+        ApplicationError::bad_request(
+            &format!("Missing {OAUTH_CLIENT_SECRET_KEY} in auth form data"),
+            None,
+        )
+    })?;
+
+    let mut metadata = json!(metadata);
+    if let Some(metadata) = metadata.as_object_mut() {
+        metadata.insert(
+            "environment".to_string(),
+            Value::String(environment.to_string()),
+        );
+    }
+
+    let payload = client_credentials_payload(&client_id, &client_secret, metadata.clone());
+
+    let (decoded, response) =
+        client_credentials_grant(&state.http_client, &definition, &payload, &state.template)
+            .await
+            .map_err(|e| {
+                error!(
+                    "Error running client credentials grant for connection: {:?}",
+                    e
+                );
+
+                ApplicationError::bad_request(
+                    &format!("Invalid connection credentials: {}", e),
+                    None,
+                )
+            })?;
+
+    let oauth_secret =
+        OAuthSecret::from_init(decoded, client_id, client_secret, response, Some(metadata));
+
+    let oauth = OAuth::Enabled {
+        connection_oauth_definition_id: definition.id,
+        expires_in: Some(oauth_secret.expires_in),
+        expires_at: Some(oauth_expires_at(oauth_secret.expires_in)),
+    };
+
+    Ok(Some((oauth_secret, oauth)))
+}
+
 async fn generate_k8s_specs_and_secret(
     connection_id: &Id,
     state: &AppState,
@@ -609,12 +748,21 @@ pub async fn update_connection(
         )
         .await
     {
-        Ok(_) => Ok(Json(ServerResponse::new(
-            "connection",
-            json!({
-                id: connection.id,
-            }),
-        ))),
+        Ok(_) => {
+            CreateConnectionPayload::after_update_hook(&connection, &state.app_stores)
+                .await
+                .map_err(|e| {
+                    error!("Error running after update hook: {:?}", e);
+                })
+                .ok();
+
+            Ok(Json(ServerResponse::new(
+                "connection",
+                json!({
+                    id: connection.id,
+                }),
+            )))
+        }
         Err(e) => {
             error!("Error updating connection: {:?}", e);
 
@@ -623,6 +771,138 @@ pub async fn update_connection(
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrateConnectionPayload {
+    /// Defaults to the platform version of the connection definition
+    pub platform_version: Option<String>,
+}
+
+pub async fn migrate_connection(
+    Extension(event_access): Extension<Arc<EventAccess>>,
+    Path(id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<MigrateConnectionPayload>,
+) -> Result<Json<ServerResponse<Value>>, PicaError> {
+    let Some(connection) = state
+        .app_stores
+        .connection
+        .get_one(doc! { "_id": &id, "deleted": false })
+        .await?
+    else {
+        return Err(ApplicationError::not_found(
+            &format!("Connection with id {id} not found"),
+            None,
+        ));
+    };
+
+    if connection.ownership != event_access.ownership
+        || connection.environment != event_access.environment
+    {
+        return Err(ApplicationError::forbidden(
+            "You do not have permission to migrate this connection",
+            None,
+        ));
+    }
+
+    let platform_version = match req.platform_version {
+        Some(platform_version) => platform_version,
+        None => {
+            state
+                .app_stores
+                .connection_config
+                .get_one(doc! {
+                    "_id": connection.connection_definition_id.to_string(),
+                    "deleted": false
+                })
+                .await?
+                .ok_or_else(|| {
+                    ApplicationError::not_found("Connection definition not found", None)
+                })?
+                .platform_version
+        }
+    };
+
+    let definitions = state
+        .extractor_caller
+        .connection_model_definitions_store
+        .count(
+            doc! {
+                "connectionPlatform": connection.platform.as_ref(),
+                "platformVersion": &platform_version,
+                "supported": true
+            },
+            Some(1),
+        )
+        .await?;
+
+    if definitions == 0 {
+        return Err(ApplicationError::bad_request(
+            &format!(
+                "No supported model definitions found for {} version {platform_version}",
+                connection.platform
+            ),
+            None,
+        ));
+    }
+
+    let mut record_metadata = connection.record_metadata.clone();
+    record_metadata.mark_updated(&event_access.ownership.id);
+
+    state
+        .app_stores
+        .connection
+        .collection
+        .update_one(
+            doc! {
+                "_id": &id,
+                "deleted": false,
+                "ownership.buildableId": event_access.ownership.id.as_ref(),
+                "environment": event_access.environment.to_string(),
+            },
+            doc! {
+                "$set": {
+                    "platformVersion": &platform_version,
+                    "updatedAt": record_metadata.updated_at,
+                    "updated": record_metadata.updated,
+                    "version": record_metadata.version.to_string(),
+                    "lastModifiedBy": &record_metadata.last_modified_by,
+                }
+            },
+        )
+        .await
+        .inspect_err(|e| error!("Error migrating connection {id}: {:?}", e))?;
+
+    // Cached connections would otherwise keep resolving the previous version
+    // until they expire
+    if let Ok(key) = HeaderValue::from_str(&connection.key) {
+        let _ = state
+            .connections_cache
+            .remove(&(connection.ownership.id.clone(), key))
+            .await;
+    }
+    let _ = state
+        .extractor_caller
+        .connections_cache
+        .remove(&connection.key)
+        .await;
+    CreateConnectionPayload::after_update_hook(&connection, &state.app_stores)
+        .await
+        .map_err(|e| {
+            error!("Error running after update hook: {:?}", e);
+        })
+        .ok();
+
+    Ok(Json(ServerResponse::new(
+        "connection",
+        json!({
+            "id": connection.id,
+            "previousPlatformVersion": connection.platform_version,
+            "platformVersion": platform_version,
+        }),
+    )))
+}
+
 pub async fn delete_connection(
     Extension(access): Extension<Arc<EventAccess>>,
     Path(id): Path<String>,
@@ -644,6 +924,37 @@ pub async fn delete_connection(
         _ => (),
     }
 
+    if let Some(OAuth::Enabled {
+        connection_oauth_definition_id,
+        ..
+    }) = &connection.args.oauth
+    {
+        // Revocation is best effort, the connection is gone either way and its
+        // secret is deleted below
+        if let Err(e) =
+            revoke_oauth_tokens(&state, connection_oauth_definition_id, &connection.args).await
+        {
+            error!(
+                "Error revoking oauth tokens for connection {}: {:?}",
+                connection.args.id, e
+            );
+        }
+    }
+
+    if let Err(e) = state
+        .secrets_client
+        .delete(
+            &connection.args.secrets_service_id,
+            &connection.args.ownership.id,
+        )
+        .await
+    {
+        error!(
+            "Error deleting secret for connection {}: {:?}",
+            connection.args.id, e
+        );
+    }
+
     Ok(Json(ServerResponse::new(
         "connection",
         json!({
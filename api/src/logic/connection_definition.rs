@@ -11,6 +11,7 @@ use axum::{
     routing::{patch, post},
     Json, Router,
 };
+use cache::invalidation::CacheInvalidation;
 use fake::Dummy;
 use mongodb::bson::doc;
 use osentities::{
@@ -24,7 +25,7 @@ use osentities::{
     id::{prefix::IdPrefix, Id},
     record_metadata::RecordMetadata,
     settings::Settings,
-    ApplicationError, PicaError,
+    ApplicationError, PicaError, Unit,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
@@ -75,7 +76,27 @@ pub struct CreateRequest {
     pub markdown: Option<String>,
 }
 
-impl HookExt<ConnectionDefinition> for CreateRequest {}
+impl HookExt<ConnectionDefinition> for CreateRequest {
+    async fn after_update_hook(
+        record: &ConnectionDefinition,
+        stores: &AppStores,
+    ) -> Result<Unit, PicaError> {
+        stores
+            .cache_invalidator
+            .publish(CacheInvalidation::ConnectionDefinition { id: record.id })
+            .await
+    }
+
+    async fn after_delete_hook(
+        record: &ConnectionDefinition,
+        stores: &AppStores,
+    ) -> Result<Unit, PicaError> {
+        stores
+            .cache_invalidator
+            .publish(CacheInvalidation::ConnectionDefinition { id: record.id })
+            .await
+    }
+}
 impl PublicExt<ConnectionDefinition> for CreateRequest {}
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Dummy)]
@@ -11,6 +11,7 @@ use axum::{
     routing::{patch, post},
     Extension, Json, Router,
 };
+use cache::invalidation::CacheInvalidation;
 use chrono::Utc;
 use fake::Dummy;
 use mongodb::bson::doc;
@@ -25,7 +26,7 @@ use osentities::{
     },
     event_access::EventAccess,
     id::{prefix::IdPrefix, Id},
-    ApplicationError, InternalError, PicaError,
+    ApplicationError, InternalError, PicaError, Unit,
 };
 use semver::Version;
 use serde::{Deserialize, Serialize};
@@ -313,7 +314,37 @@ pub struct CreateRequest {
     pub tags: Option<Vec<String>>,
 }
 
-impl HookExt<ConnectionModelDefinition> for CreateRequest {}
+impl HookExt<ConnectionModelDefinition> for CreateRequest {
+    async fn after_create_hook(
+        record: &ConnectionModelDefinition,
+        stores: &AppStores,
+    ) -> Result<Unit, PicaError> {
+        stores
+            .cache_invalidator
+            .publish(CacheInvalidation::from(record))
+            .await
+    }
+
+    async fn after_update_hook(
+        record: &ConnectionModelDefinition,
+        stores: &AppStores,
+    ) -> Result<Unit, PicaError> {
+        stores
+            .cache_invalidator
+            .publish(CacheInvalidation::from(record))
+            .await
+    }
+
+    async fn after_delete_hook(
+        record: &ConnectionModelDefinition,
+        stores: &AppStores,
+    ) -> Result<Unit, PicaError> {
+        stores
+            .cache_invalidator
+            .publish(CacheInvalidation::from(record))
+            .await
+    }
+}
 impl PublicExt<ConnectionModelDefinition> for CreateRequest {}
 
 impl RequestExt for CreateRequest {
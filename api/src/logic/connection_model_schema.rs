@@ -9,6 +9,7 @@ use axum::{
     routing::{patch, post},
     Extension, Json, Router,
 };
+use cache::invalidation::CacheInvalidation;
 use fake::Dummy;
 use futures::try_join;
 use mongodb::bson::doc;
@@ -20,7 +21,7 @@ use osentities::{
     event_access::EventAccess,
     id::{prefix::IdPrefix, Id},
     json_schema::JsonSchema,
-    ApplicationError, PicaError, StringExt,
+    ApplicationError, PicaError, StringExt, Unit,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
@@ -178,7 +179,27 @@ pub struct CreateRequest {
     pub mapping: Option<Mappings>,
 }
 
-impl HookExt<ConnectionModelSchema> for CreateRequest {}
+impl HookExt<ConnectionModelSchema> for CreateRequest {
+    async fn after_update_hook(
+        record: &ConnectionModelSchema,
+        stores: &AppStores,
+    ) -> Result<Unit, PicaError> {
+        stores
+            .cache_invalidator
+            .publish(CacheInvalidation::ConnectionModelSchema { id: record.id })
+            .await
+    }
+
+    async fn after_delete_hook(
+        record: &ConnectionModelSchema,
+        stores: &AppStores,
+    ) -> Result<Unit, PicaError> {
+        stores
+            .cache_invalidator
+            .publish(CacheInvalidation::ConnectionModelSchema { id: record.id })
+            .await
+    }
+}
 impl PublicExt<ConnectionModelSchema> for CreateRequest {}
 
 impl RequestExt for CreateRequest {
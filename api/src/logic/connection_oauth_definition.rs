@@ -4,6 +4,7 @@ use axum::{
     routing::{patch, post},
     Router,
 };
+use cache::invalidation::CacheInvalidation;
 use chrono::Utc;
 use mongodb::bson::doc;
 use osentities::{
@@ -11,9 +12,11 @@ use osentities::{
     api_model_config::{ApiModelConfig, Compute, Function, Lang},
     connection_oauth_definition::{
         ComputeRequest, ConnectionOAuthDefinition, Frontend, OAuthApiConfig, OAuthCompute,
+        OAuthGrantType, PkceMethod,
     },
     id::{prefix::IdPrefix, Id},
     record_metadata::RecordMetadata,
+    PicaError, Unit,
 };
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
@@ -45,12 +48,53 @@ pub struct CreateRequest {
     pub scopes: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub separator: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pkce: Option<PkceMethod>,
     pub init: RequestParams,
     pub refresh: RequestParams,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub revoke: Option<RevokeParams>,
     pub is_full_template_enabled: bool,
+    #[serde(default)]
+    pub grant_type: OAuthGrantType,
 }
 
-impl HookExt<ConnectionOAuthDefinition> for CreateRequest {}
+impl CreateRequest {
+    fn revoke_compute(&self) -> Option<Function> {
+        self.revoke
+            .as_ref()
+            .and_then(|revoke| revoke.compute.clone())
+            .map(|compute| {
+                Function(Compute {
+                    entry: "compute".to_string(),
+                    function: compute,
+                    language: Lang::JavaScript,
+                })
+            })
+    }
+}
+
+impl HookExt<ConnectionOAuthDefinition> for CreateRequest {
+    async fn after_update_hook(
+        record: &ConnectionOAuthDefinition,
+        stores: &AppStores,
+    ) -> Result<Unit, PicaError> {
+        stores
+            .cache_invalidator
+            .publish(CacheInvalidation::ConnectionOAuthDefinition { id: record.id })
+            .await
+    }
+
+    async fn after_delete_hook(
+        record: &ConnectionOAuthDefinition,
+        stores: &AppStores,
+    ) -> Result<Unit, PicaError> {
+        stores
+            .cache_invalidator
+            .publish(CacheInvalidation::ConnectionOAuthDefinition { id: record.id })
+            .await
+    }
+}
 impl PublicExt<ConnectionOAuthDefinition> for CreateRequest {}
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -62,6 +106,14 @@ pub struct RequestParams {
     pub response_compute: String,
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevokeParams {
+    pub configuration: ApiModelConfig,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub compute: Option<String>,
+}
+
 fn default_separator() -> Option<String> {
     Some(" ".to_string())
 }
@@ -78,8 +130,13 @@ impl RequestExt for CreateRequest {
             configuration: OAuthApiConfig {
                 init: self.init.configuration.clone(),
                 refresh: self.refresh.configuration.clone(),
+                revoke: self
+                    .revoke
+                    .as_ref()
+                    .map(|revoke| revoke.configuration.clone()),
             },
             is_full_template_enabled: self.is_full_template_enabled,
+            grant_type: self.grant_type,
             compute: OAuthCompute {
                 init: ComputeRequest {
                     response: Function(Compute {
@@ -119,6 +176,7 @@ impl RequestExt for CreateRequest {
                         language: Lang::JavaScript,
                     }),
                 },
+                revoke: self.revoke_compute(),
             },
             frontend: Frontend {
                 platform_redirect_uri: self.platform_redirect_uri.clone(),
@@ -126,6 +184,7 @@ impl RequestExt for CreateRequest {
                 ios_redirect_uri: self.ios_redirect_uri.clone(),
                 scopes: self.scopes.clone(),
                 separator: self.separator.clone(),
+                pkce: self.pkce,
             },
             record_metadata: Default::default(),
         })
@@ -138,8 +197,13 @@ impl RequestExt for CreateRequest {
         record.configuration = OAuthApiConfig {
             init: self.init.configuration.clone(),
             refresh: self.refresh.configuration.clone(),
+            revoke: self
+                .revoke
+                .as_ref()
+                .map(|revoke| revoke.configuration.clone()),
         };
         record.is_full_template_enabled = self.is_full_template_enabled;
+        record.grant_type = self.grant_type;
         record.compute = OAuthCompute {
             init: ComputeRequest {
                 computation: self
@@ -179,6 +243,7 @@ impl RequestExt for CreateRequest {
                     })
                     .next(),
             },
+            revoke: self.revoke_compute(),
         };
         record.frontend = Frontend {
             platform_redirect_uri: self.platform_redirect_uri.clone(),
@@ -186,6 +251,7 @@ impl RequestExt for CreateRequest {
             ios_redirect_uri: self.ios_redirect_uri.clone(),
             scopes: self.scopes.clone(),
             separator: self.separator.clone(),
+            pkce: self.pkce,
         };
         record.record_metadata.updated_at = Utc::now().timestamp_millis();
         record.record_metadata.updated = true;
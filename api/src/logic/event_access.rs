@@ -1,4 +1,4 @@
-use super::{delete, read, PublicExt, RequestExt};
+use super::{delete, read, HookExt, PublicExt, RequestExt};
 use crate::{
     domain::config::ConnectionsConfig,
     router::ServerResponse,
@@ -10,6 +10,7 @@ use axum::{
     routing::{delete as axum_delete, get, post},
     Extension, Json, Router,
 };
+use cache::invalidation::CacheInvalidation;
 use fake::Dummy;
 use mongodb::bson::doc;
 use osentities::{
@@ -23,7 +24,7 @@ use osentities::{
     id::{prefix::IdPrefix, Id},
     ownership::Ownership,
     record_metadata::RecordMetadata,
-    AccessKey, ApplicationError, InternalError, PicaError, DEFAULT_NAMESPACE,
+    AccessKey, ApplicationError, InternalError, PicaError, Unit, DEFAULT_NAMESPACE,
 };
 use rand::Rng;
 use serde::{Deserialize, Serialize};
@@ -62,6 +63,18 @@ impl RequestExt for CreateEventAccessRequest {
 }
 impl PublicExt<EventAccess> for CreateEventAccessRequest {}
 
+impl HookExt<EventAccess> for CreateEventAccessRequest {
+    async fn after_delete_hook(
+        record: &EventAccess,
+        stores: &AppStores,
+    ) -> Result<Unit, PicaError> {
+        stores
+            .cache_invalidator
+            .publish(CacheInvalidation::event_access(&record.access_key))
+            .await
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Validate, Dummy)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateEventAccessPayloadWithOwnership {
@@ -21,6 +21,7 @@ use std::{collections::BTreeMap, fmt::Debug, future::Future, sync::Arc};
 use tokio::try_join;
 use tracing::error;
 
+pub mod caches;
 pub mod common_enum;
 pub mod common_model;
 pub mod connection;
@@ -274,7 +275,7 @@ pub async fn delete<T, U>(
     State(state): State<Arc<AppState>>,
 ) -> Result<Json<ServerResponse<U>>, PicaError>
 where
-    T: RequestExt<Output = U> + 'static,
+    T: RequestExt<Output = U> + HookExt<U> + 'static,
     U: Serialize + DeserializeOwned + Unpin + Sync + Send + 'static,
 {
     let store = T::get_store(state.app_stores.clone());
@@ -313,7 +314,16 @@ where
         )
         .await
     {
-        Ok(_) => Ok(Json(ServerResponse::new("delete", res))),
+        Ok(_) => {
+            T::after_delete_hook(&res, &state.app_stores)
+                .await
+                .map_err(|e| {
+                    error!("Error running after delete hook: {:?}", e);
+                })
+                .ok();
+
+            Ok(Json(ServerResponse::new("delete", res)))
+        }
         Err(e) => {
             error!("Could not update record in store: {e}");
             Err(e)
@@ -5,19 +5,18 @@ use axum::{
     routing::post,
     Extension, Json, Router,
 };
-use chrono::{Duration, Utc};
+use chrono::Utc;
 use fake::Dummy;
-use http::{HeaderMap, HeaderName, HeaderValue};
 use mongodb::bson::doc;
 use osentities::{
-    algebra::{MongoStore, TemplateExt},
-    api_model_config::ContentType,
+    algebra::{oauth_expires_at, token_request, MongoStore, TemplateExt},
     connection_definition::ConnectionDefinition,
     connection_oauth_definition::{
-        Computation, ConnectionOAuthDefinition, OAuthResponse, PlatformSecret, Settings,
+        Computation, ConnectionOAuthDefinition, OAuthResponse, PkceMethod, PlatformSecret, Settings,
     },
     event_access::EventAccess,
     id::{prefix::IdPrefix, Id},
+    oauth_authorization::OAuthAuthorization,
     oauth_secret::OAuthSecret,
     ownership::Ownership,
     ApplicationError, Connection, ConnectionIdentityType, ErrorMeta, InternalError, OAuth,
@@ -25,17 +24,15 @@ use osentities::{
 };
 use reqwest::Request;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use serde_json::{to_string_pretty, Value};
-use std::{
-    collections::{BTreeMap, HashMap},
-    str::FromStr,
-    sync::Arc,
-};
+use serde_json::{json, Value};
+use std::sync::Arc;
 use tracing::{debug, error};
 use uuid::Uuid;
 
 pub fn get_router() -> Router<Arc<AppState>> {
-    Router::new().route("/:platform", post(oauth_handler))
+    Router::new()
+        .route("/:platform", post(oauth_handler))
+        .route("/:platform/authorize", post(authorize_handler))
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Dummy)]
@@ -51,6 +48,9 @@ struct OAuthRequest {
     group: Option<String>,
     identity: Option<String>,
     identity_type: Option<ConnectionIdentityType>,
+    /// The `state` returned by the authorize endpoint, required for PKCE platforms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Dummy)]
@@ -59,6 +59,8 @@ struct OAuthPayload {
     client_id: String,
     client_secret: String,
     metadata: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code_verifier: Option<String>,
 }
 
 impl OAuthPayload {
@@ -112,10 +114,24 @@ async fn oauth_handler(
         e
     })?;
 
+    let code_verifier = match conn_oauth_definition.pkce() {
+        Some(_) => Some(
+            get_code_verifier(
+                &state,
+                &platform,
+                &user_event_access.ownership,
+                payload.state.as_deref(),
+            )
+            .await?,
+        ),
+        None => None,
+    };
+
     let mut oauth_payload = OAuthPayload {
         metadata: payload.payload.clone().unwrap_or(Value::Null),
         client_id: payload.client_id,
         client_secret: secret.client_secret,
+        code_verifier,
     };
 
     if let Some(metadata) = oauth_payload.metadata.as_object_mut() {
@@ -137,11 +153,16 @@ async fn oauth_handler(
         conn_oauth_definition
     };
 
-    let request =
-        request(&conn_oauth_definition, &oauth_payload, &state.template).map_err(|e| {
-            error!("Failed to create oauth request: {}", e);
-            e
-        })?;
+    let request = request(
+        &state.http_client,
+        &conn_oauth_definition,
+        &oauth_payload,
+        &state.template,
+    )
+    .map_err(|e| {
+        error!("Failed to create oauth request: {}", e);
+        e
+    })?;
 
     debug!("Request: {:?}", request);
     let response = state
@@ -262,14 +283,7 @@ async fn oauth_handler(
         oauth: Some(OAuth::Enabled {
             connection_oauth_definition_id: conn_oauth_definition.id,
             expires_in: Some(oauth_secret.expires_in),
-            expires_at: Some(
-                chrono::Utc::now()
-                    .checked_add_signed(Duration::seconds(oauth_secret.expires_in as i64))
-                    .unwrap_or_else(chrono::Utc::now)
-                    .checked_sub_signed(Duration::seconds(120))
-                    .unwrap_or_else(chrono::Utc::now)
-                    .timestamp(),
-            ),
+            expires_at: Some(oauth_expires_at(oauth_secret.expires_in)),
         }),
         record_metadata: Default::default(),
     };
@@ -287,11 +301,96 @@ async fn oauth_handler(
     Ok(Json(connection.into()))
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthorizeResponse {
+    state: String,
+    code_challenge: String,
+    code_challenge_method: PkceMethod,
+}
+
+/// Starts an authorization attempt for a platform that requires PKCE. The
+/// verifier is kept server side and the frontend adds the returned `state`
+/// and challenge to the authorization url.
+async fn authorize_handler(
+    state: State<Arc<AppState>>,
+    Extension(user_event_access): Extension<Arc<EventAccess>>,
+    Path(platform): Path<String>,
+) -> Result<Json<AuthorizeResponse>, PicaError> {
+    let conn_oauth_definition = get_conn_oauth_definition(&state, &platform).await?;
+    let method = conn_oauth_definition.pkce().ok_or_else(|| {
+        ApplicationError::bad_request("Connection OAuth definition does not use PKCE", None)
+    })?;
+
+    let authorizations = &state.app_stores.oauth_authorizations;
+
+    // Attempts that were never completed are cleaned up lazily
+    authorizations
+        .collection
+        .delete_many(doc! {
+            "ownership.buildableId": user_event_access.ownership.id.as_ref(),
+            "expiresAt": { "$lt": Utc::now().timestamp_millis() },
+        })
+        .await?;
+
+    let authorization =
+        OAuthAuthorization::new(&platform, user_event_access.ownership.clone(), method);
+
+    authorizations
+        .create_one(&authorization)
+        .await
+        .map_err(|e| {
+            error!("Failed to create oauth authorization: {}", e);
+            e
+        })?;
+
+    Ok(Json(AuthorizeResponse {
+        code_challenge: authorization.code_challenge(),
+        code_challenge_method: method,
+        state: authorization.id,
+    }))
+}
+
+async fn get_code_verifier(
+    state: &State<Arc<AppState>>,
+    platform: &str,
+    ownership: &Ownership,
+    authorization_state: Option<&str>,
+) -> Result<String, PicaError> {
+    let authorization_state = authorization_state.ok_or_else(|| {
+        ApplicationError::bad_request("A state is required for PKCE authorizations", None)
+    })?;
+
+    // Deleted on read so that a verifier can only be used for a single exchange
+    let authorization = state
+        .app_stores
+        .oauth_authorizations
+        .collection
+        .find_one_and_delete(doc! {
+            "_id": authorization_state,
+            "connectionPlatform": platform,
+            "ownership.buildableId": ownership.id.as_ref(),
+        })
+        .await?
+        .ok_or_else(|| ApplicationError::bad_request("Unknown PKCE authorization", None))?;
+
+    if authorization.is_expired() {
+        return Err(ApplicationError::bad_request(
+            "PKCE authorization has expired",
+            None,
+        ));
+    }
+
+    Ok(authorization.code_verifier)
+}
+
 fn request(
+    http_client: &reqwest::Client,
     oauth_definition: &ConnectionOAuthDefinition,
     payload: &OAuthPayload,
     template: &impl TemplateExt,
 ) -> Result<Request, PicaError> {
+    let code_verifier = payload.code_verifier.clone();
     let payload = serde_json::to_value(payload).map_err(|e| {
         error!("Failed to serialize oauth payload: {}", e);
         InternalError::serialize_error(&e.to_string(), None)
@@ -308,156 +407,50 @@ fn request(
             InternalError::script_error(e.message().as_ref(), None)
         })?;
 
-    let headers = header(oauth_definition, computation.as_ref(), template)?;
-    let query = query(oauth_definition, computation.as_ref(), template)?;
-    let body = body(&payload, computation.as_ref(), template)?;
-
-    let request = reqwest::Client::new()
-        .post(oauth_definition.configuration.init.uri())
-        .headers(headers);
-
-    let request = match oauth_definition.configuration.init.content {
-        Some(ContentType::Json) => request.json(&body).query(&query),
-        Some(ContentType::Form) => request.form(&body).query(&query),
-        _ => request.query(&query),
+    let computation = match code_verifier {
+        Some(code_verifier) => Some(with_code_verifier(computation, code_verifier)?),
+        None => computation,
     };
 
-    request.build().map_err(|e| {
-        error!("Failed to build static request: {}", e);
-        InternalError::unknown(&e.to_string(), None)
-    })
-}
-
-fn query(
-    oauth_definition: &ConnectionOAuthDefinition,
-    computation: Option<&Computation>,
-    template: &impl TemplateExt,
-) -> Result<Option<Value>, PicaError> {
-    let query_params = oauth_definition
-        .configuration
-        .init
-        .query_params
-        .as_ref()
-        .map(|query_params| {
-            let mut map = HashMap::new();
-            for (key, value) in query_params {
-                let key = key.to_string();
-                let value = value.as_str();
-
-                map.insert(key, value.to_string());
-            }
-            map
-        });
-
-    match query_params {
-        Some(query_params) => {
-            let payload = computation.and_then(|computation| computation.clone().query_params);
-
-            let query_params_str = to_string_pretty(&query_params).map_err(|e| {
-                error!("Failed to serialize query params: {}", e);
-                InternalError::serialize_error(&e.to_string(), None)
-            })?;
-
-            let query_params = template.render(&query_params_str, payload.as_ref())?;
-
-            let query_params: BTreeMap<String, String> = serde_json::from_str(&query_params)
-                .map_err(|e| {
-                    error!("Failed to deserialize query params: {}", e);
-                    InternalError::deserialize_error(&e.to_string(), None)
-                })?;
-
-            Ok(Some(serde_json::to_value(query_params).map_err(|e| {
-                error!("Failed to serialize query params: {}", e);
-                InternalError::serialize_error(&e.to_string(), None)
-            })?))
-        }
-        None => Ok(None),
-    }
+    token_request(
+        http_client,
+        &oauth_definition.configuration.init,
+        computation.as_ref(),
+        &payload,
+        template,
+    )
 }
 
-fn body(
-    payload: &Value,
-    computation: Option<&Computation>,
-    template: &impl TemplateExt,
-) -> Result<Option<Value>, PicaError> {
-    let body = computation.and_then(|computation| computation.clone().body);
-
-    match body {
-        Some(body) => {
-            let body_str = to_string_pretty(&body).map_err(|e| {
-                error!("Failed to serialize body: {}", e);
-                InternalError::serialize_error(&e.to_string(), None)
-            })?;
-
-            let body = template.render(&body_str, Some(payload))?;
-
-            Ok(Some(serde_json::from_str(&body).map_err(|e| {
-                error!("Failed to deserialize body: {}", e);
-                InternalError::deserialize_error(&e.to_string(), None)
-            })?))
-        }
-        None => Ok(None),
-    }
-}
+/// PKCE verifiers are sent in the token exchange body without the definition
+/// having to reference them in its computation, which must then compute the
+/// body as an object
+fn with_code_verifier(
+    computation: Option<Computation>,
+    code_verifier: String,
+) -> Result<Computation, PicaError> {
+    let mut computation = computation.unwrap_or(Computation {
+        headers: None,
+        query_params: None,
+        body: None,
+    });
+
+    let mut body = match computation.body.take() {
+        None | Some(Value::Null) => json!({}),
+        Some(body) => body,
+    };
+    let Some(fields) = body.as_object_mut() else {
+        error!("Token request body is not an object, cannot add the PKCE verifier: {body}");
+        return Err(InternalError::invalid_argument(
+            "The token request body of a PKCE platform must be an object",
+            None,
+        ));
+    };
+    fields
+        .entry("code_verifier")
+        .or_insert_with(|| Value::String(code_verifier));
+    computation.body = Some(body);
 
-fn header(
-    conn_oauth_definition: &ConnectionOAuthDefinition,
-    computation: Option<&Computation>,
-    template: &impl TemplateExt,
-) -> Result<HeaderMap, PicaError> {
-    let headers = conn_oauth_definition
-        .configuration
-        .init
-        .headers
-        .as_ref()
-        .and_then(|headers| {
-            let mut map = HashMap::new();
-            for (key, value) in headers {
-                let key = key.to_string();
-                let value = value.to_str().ok()?;
-
-                map.insert(key, value.to_string());
-            }
-            Some(map)
-        });
-
-    match headers {
-        Some(headers) => {
-            let payload = computation.and_then(|computation| computation.clone().headers);
-
-            let headers_str = to_string_pretty(&headers).map_err(|e| {
-                error!("Failed to serialize headers: {}", e);
-                InternalError::serialize_error(&e.to_string(), None)
-            })?;
-
-            let headers = template.render(&headers_str, payload.as_ref())?;
-
-            let headers: BTreeMap<String, String> =
-                serde_json::from_str(&headers).map_err(|e| {
-                    error!("Failed to deserialize headers: {}", e);
-                    InternalError::deserialize_error(&e.to_string(), None)
-                })?;
-
-            headers
-                .iter()
-                .try_fold(HeaderMap::new(), |mut header_map, (key, value)| {
-                    let key = HeaderName::from_str(key).map_err(|e| {
-                        error!("Failed to parse header name: {}", e);
-                        InternalError::invalid_argument(&e.to_string(), None)
-                    })?;
-
-                    let value = HeaderValue::from_str(value).map_err(|e| {
-                        error!("Failed to parse header value: {}", e);
-                        InternalError::invalid_argument(&e.to_string(), None)
-                    })?;
-
-                    header_map.insert(key, value);
-
-                    Ok(header_map)
-                })
-        }
-        None => Ok(HeaderMap::new()),
-    }
+    Ok(computation)
 }
 
 async fn get_conn_definition(
@@ -522,3 +515,126 @@ async fn get_secret<S: DeserializeOwned>(
 
     encoded_secret.decode::<S>()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use osentities::algebra::DefaultTemplate;
+
+    fn computation(body: Option<Value>) -> Option<Computation> {
+        Some(Computation {
+            headers: None,
+            query_params: None,
+            body,
+        })
+    }
+
+    #[test]
+    fn test_code_verifier_is_added_to_the_body() {
+        let computation = with_code_verifier(
+            computation(Some(json!({ "grant_type": "authorization_code" }))),
+            "verifier".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            computation.body,
+            Some(json!({ "grant_type": "authorization_code", "code_verifier": "verifier" }))
+        );
+    }
+
+    #[test]
+    fn test_code_verifier_without_computation() {
+        let computation = with_code_verifier(None, "verifier".to_string()).unwrap();
+
+        assert_eq!(
+            computation.body,
+            Some(json!({ "code_verifier": "verifier" }))
+        );
+    }
+
+    #[test]
+    fn test_code_verifier_keeps_a_computed_one() {
+        let computation = with_code_verifier(
+            computation(Some(json!({ "code_verifier": "computed" }))),
+            "verifier".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            computation.body,
+            Some(json!({ "code_verifier": "computed" }))
+        );
+    }
+
+    #[test]
+    fn test_code_verifier_needs_an_object_body() {
+        assert!(with_code_verifier(
+            computation(Some(json!("grant_type=authorization_code"))),
+            "verifier".to_string()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_token_request_sends_code_verifier() {
+        let oauth_definition: ConnectionOAuthDefinition = serde_json::from_value(json!({
+            "_id": Id::now(IdPrefix::ConnectionOAuthDefinition),
+            "connectionPlatform": "airtable",
+            "configuration": {
+                "init": token_config(),
+                "refresh": token_config(),
+            },
+            "compute": {
+                "init": { "computation": null, "response": identity() },
+                "refresh": { "computation": null, "response": identity() },
+            },
+            "frontend": {
+                "platformRedirectUri": "https://example.com/authorize",
+                "scopes": "",
+                "iosRedirectUri": "",
+                "pkce": "S256",
+            },
+        }))
+        .unwrap();
+        let payload = OAuthPayload {
+            client_id: "client-id".to_string(),
+            client_secret: "client-secret".to_string(),
+            metadata: Value::Null,
+            code_verifier: Some("verifier".to_string()),
+        };
+
+        let request = request(
+            &reqwest::Client::new(),
+            &oauth_definition,
+            &payload,
+            &DefaultTemplate::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            request.body().and_then(|body| body.as_bytes()),
+            Some("code_verifier=verifier".as_bytes())
+        );
+    }
+
+    fn token_config() -> Value {
+        json!({
+            "baseUrl": "https://example.com",
+            "path": "/oauth/token",
+            "authMethod": { "type": "None" },
+            "content": "form",
+            "schemas": {},
+            "samples": {},
+            "responses": []
+        })
+    }
+
+    fn identity() -> Value {
+        json!({
+            "entry": "compute",
+            "function": "function compute(payload) { return payload; }",
+            "language": "javascript",
+        })
+    }
+}
@@ -1,65 +1,549 @@
-use super::{create, delete, read, update, HookExt, PublicExt, RequestExt};
-use crate::server::{AppState, AppStores};
+use super::{create, delete, read, update, HookExt, PublicExt, ReadResponse, RequestExt};
+use crate::{
+    router::ServerResponse,
+    server::{AppState, AppStores},
+};
 use axum::{
-    routing::{patch, post},
-    Router,
+    extract::{Path, Query, State},
+    routing::{get, patch, post},
+    Extension, Json, Router,
 };
-use chrono::Utc;
+use bson::doc;
+use chrono::{DateTime, Utc};
 use fake::Dummy;
+use http::{HeaderMap, Method};
 use osentities::{
-    event_access::EventAccess, prefix::IdPrefix, record_metadata::RecordMetadata, task::Task, Id,
+    destination::Action,
+    event_access::EventAccess,
+    prefix::IdPrefix,
+    record_metadata::RecordMetadata,
+    settle_dependents, settle_waiting_task,
+    task::{Backoff, Task, TaskAction, TaskLogEntry, TaskSchedule, TaskSecretRef, TaskState},
+    ApplicationError, Id, InternalError, PicaError, Unit, ENVIRONMENT_FILTER, OWNERSHIP_FILTER,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::sync::Arc;
+use serde_json::{json, Value};
+use std::{collections::HashMap, sync::Arc};
+
+const DEFAULT_UPCOMING_COUNT: usize = 5;
+const MAX_UPCOMING_COUNT: usize = 100;
+const DEFAULT_LOGS_LIMIT: u64 = 20;
+const MAX_LOGS_LIMIT: u64 = 1_000;
 
 pub fn get_router() -> Router<Arc<AppState>> {
     Router::new()
-        .route(
-            "/",
-            post(create::<CreateRequest, Task>).get(read::<CreateRequest, Task>),
-        )
+        .route("/", post(create_task).get(read::<CreateRequest, Task>))
         .route(
             "/:id",
             patch(update::<CreateRequest, Task>).delete(delete::<CreateRequest, Task>),
         )
+        .route("/:id/cancel", post(cancel_task))
+        .route("/:id/pause", post(pause_task))
+        .route("/:id/resume", post(resume_task))
+        .route("/:id/upcoming", get(get_upcoming_fire_times))
+        .route("/:id/logs", get(get_task_logs))
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Dummy)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateRequest {
     pub start_time: i64,
+    /// Unused by tasks that run an action
+    #[serde(default)]
     pub endpoint: String,
     pub payload: Value,
+    #[serde(default)]
+    pub action: Option<CreateActionRequest>,
+    #[serde(with = "http_serde_ext_ios::method", default = "default_method")]
+    pub method: Method,
+    #[serde(with = "http_serde_ext_ios::header_map", default)]
+    pub headers: HeaderMap,
+    /// A secret of the caller's ownership to sign deliveries with
+    #[serde(default)]
+    pub secret_id: Option<String>,
     #[serde(rename = "await")]
     pub r#await: bool,
+    #[serde(default)]
+    pub max_retries: u32,
+    #[serde(default)]
+    pub backoff: Backoff,
+    #[serde(default)]
+    pub schedule: Option<TaskSchedule>,
+    /// Tasks of the caller's ownership that must succeed before this one runs
+    #[serde(default)]
+    pub depends_on: Vec<Id>,
+    /// Whether the task dies when a dependency dies or is cancelled. Without
+    /// it the task stays waiting until it is cancelled.
+    #[serde(default)]
+    pub cascade_failure: bool,
 }
 
-impl RequestExt for CreateRequest {
-    type Output = Task;
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Dummy)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateActionRequest {
+    pub connection_key: String,
+    pub action: Action,
+    #[serde(default)]
+    pub query_params: HashMap<String, String>,
+}
+
+fn default_method() -> Method {
+    Method::POST
+}
+
+impl CreateRequest {
+    /// Secrets and connections are only resolved within the caller's ownership
+    fn task(&self, access: Option<&EventAccess>) -> Option<Task> {
+        if access.is_none() && (self.secret_id.is_some() || self.action.is_some()) {
+            return None;
+        }
+
+        let buildable_id = access.map(|access| access.ownership.id.as_ref());
+        let id = Id::now(IdPrefix::Task);
+        let start_time = match &self.schedule {
+            Some(schedule) => schedule
+                .first_at(DateTime::from_timestamp_millis(self.start_time)?)
+                .ok()??
+                .timestamp_millis(),
+            None => self.start_time,
+        };
 
-    fn from(&self) -> Option<Task> {
         Some(Task {
-            id: Id::now(IdPrefix::Task),
-            start_time: Utc::now().timestamp_millis(),
+            id,
+            ownership: access
+                .map(|access| access.ownership.clone())
+                .unwrap_or_default(),
+            environment: access.map(|access| access.environment),
+            start_time,
             worker_id: 0,
             end_time: None,
             payload: self.payload.clone(),
             endpoint: self.endpoint.clone(),
+            method: self.method.clone(),
+            headers: self.headers.clone(),
+            action: self
+                .action
+                .as_ref()
+                .zip(buildable_id)
+                .map(|(action, buildable_id)| TaskAction {
+                    connection_key: action.connection_key.as_str().into(),
+                    buildable_id: buildable_id.to_string(),
+                    action: action.action.clone(),
+                    query_params: action.query_params.clone(),
+                }),
+            result: None,
+            secret: self
+                .secret_id
+                .as_ref()
+                .zip(buildable_id)
+                .map(|(id, buildable_id)| TaskSecretRef {
+                    id: id.clone(),
+                    buildable_id: buildable_id.to_string(),
+                }),
             status: None,
             r#await: self.r#await,
-            log_trail: vec![],
-            metadata: RecordMetadata::default(),
+            logs: vec![],
+            state: if self.depends_on.is_empty() {
+                TaskState::Pending
+            } else {
+                TaskState::Waiting
+            },
+            max_retries: self.max_retries,
+            backoff: self.backoff,
+            attempts: vec![],
+            schedule: self.schedule.clone(),
+            series_id: self.schedule.as_ref().map(|_| id),
+            depends_on: self.depends_on.clone(),
+            cascade_failure: self.cascade_failure,
+            lease: None,
+            metadata: RecordMetadata {
+                active: self.depends_on.is_empty(),
+                ..Default::default()
+            },
         })
     }
+}
+
+impl RequestExt for CreateRequest {
+    type Output = Task;
+
+    fn from(&self) -> Option<Task> {
+        self.task(None)
+    }
 
     fn get_store(stores: AppStores) -> osentities::MongoStore<Self::Output> {
         stores.tasks
     }
 
-    fn access(&self, _: Arc<EventAccess>) -> Option<Self::Output> {
-        self.from()
+    fn access(&self, event_access: Arc<EventAccess>) -> Option<Self::Output> {
+        self.task(Some(event_access.as_ref()))
+    }
+}
+impl HookExt<Task> for CreateRequest {
+    /// Dependencies may have finished before the task was created
+    async fn after_create_hook(record: &Task, stores: &AppStores) -> Result<Unit, PicaError> {
+        if settle_waiting_task(&stores.tasks, record).await? == Some(TaskState::Dead) {
+            settle_dependents(&stores.tasks, record.id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Deleted tasks that haven't finished are cancelled, so that their worker
+    /// lets go of them and their dependents are settled
+    async fn after_delete_hook(record: &Task, stores: &AppStores) -> Result<Unit, PicaError> {
+        let cancelled = stores
+            .tasks
+            .collection
+            .update_one(
+                doc! {
+                    "_id": record.id.to_string(),
+                    "state": {
+                        "$in": [
+                            TaskState::Pending.to_string(),
+                            TaskState::Waiting.to_string(),
+                            TaskState::Running.to_string(),
+                        ]
+                    },
+                },
+                doc! {
+                    "$set": {
+                        "state": TaskState::Cancelled.to_string(),
+                        "active": false,
+                        "endTime": Utc::now().timestamp_millis(),
+                    },
+                    "$unset": { "lease": "" },
+                },
+            )
+            .await?;
+
+        if cancelled.modified_count > 0 {
+            settle_dependents(&stores.tasks, record.id).await?;
+        }
+
+        Ok(())
     }
 }
-impl HookExt<Task> for CreateRequest {}
 impl PublicExt<Task> for CreateRequest {}
+
+async fn create_task(
+    access: Option<Extension<Arc<EventAccess>>>,
+    state: State<Arc<AppState>>,
+    Json(mut payload): Json<CreateRequest>,
+) -> Result<Json<ServerResponse<Value>>, PicaError> {
+    match (&payload.action, &access) {
+        (Some(action), Some(Extension(access))) => {
+            state
+                .app_stores
+                .connection
+                .get_one(doc! {
+                    "key": &action.connection_key,
+                    "ownership.buildableId": access.ownership.id.as_ref(),
+                    "deleted": false,
+                })
+                .await?
+                .ok_or_else(|| ApplicationError::not_found("Connection", None))?;
+        }
+        (None, _) if payload.endpoint.is_empty() => {
+            return Err(ApplicationError::bad_request(
+                "Tasks need either an endpoint or an action",
+                None,
+            ));
+        }
+        _ => {}
+    }
+    if let Some(schedule) = &payload.schedule {
+        schedule.validate()?;
+    }
+
+    payload.depends_on.sort_by_key(|id| id.to_string());
+    payload.depends_on.dedup();
+    if !payload.depends_on.is_empty() {
+        if payload.schedule.is_some() {
+            return Err(ApplicationError::bad_request(
+                "Recurring tasks can't depend on other tasks",
+                None,
+            ));
+        }
+
+        let dependency_ids = payload
+            .depends_on
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>();
+        let mut filter = doc! { "_id": { "$in": dependency_ids }, "deleted": false };
+        if let Some(Extension(access)) = &access {
+            filter.extend(ownership_filter(access));
+        }
+        let found = state.app_stores.tasks.count(filter, None).await?;
+
+        if found != payload.depends_on.len() as u64 {
+            return Err(ApplicationError::bad_request(
+                "Some of the tasks depended on don't exist",
+                None,
+            ));
+        }
+    }
+
+    create::<CreateRequest, Task>(access, state, Json(payload)).await
+}
+
+/// Tasks are only visible to the ownership and environment that created them
+fn ownership_filter(access: &EventAccess) -> bson::Document {
+    doc! {
+        OWNERSHIP_FILTER: access.ownership.id.as_ref(),
+        ENVIRONMENT_FILTER: access.environment.to_string(),
+    }
+}
+
+fn task_filter(access: &EventAccess, id: &str) -> bson::Document {
+    let mut filter = doc! { "_id": id, "deleted": false };
+    filter.extend(ownership_filter(access));
+    filter
+}
+
+/// Cancels a task that hasn't finished. A running task loses its lease, so
+/// its worker aborts the delivery at its next heartbeat.
+async fn cancel_task(
+    Extension(access): Extension<Arc<EventAccess>>,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ServerResponse<Value>>, PicaError> {
+    let tasks = &state.app_stores.tasks;
+    let mut filter = task_filter(&access, &id);
+    filter.insert(
+        "state",
+        doc! {
+            "$in": [
+                TaskState::Pending.to_string(),
+                TaskState::Waiting.to_string(),
+                TaskState::Running.to_string(),
+            ]
+        },
+    );
+    let task = tasks
+        .collection
+        .find_one_and_update(
+            filter,
+            doc! {
+                "$set": {
+                    "state": TaskState::Cancelled.to_string(),
+                    "active": false,
+                    "endTime": Utc::now().timestamp_millis(),
+                },
+                "$unset": { "lease": "" },
+            },
+        )
+        .await?;
+
+    let Some(task) = task else {
+        return match tasks.get_one(task_filter(&access, &id)).await? {
+            Some(task) => Err(ApplicationError::conflict(
+                &format!("Task {id} is already {}", task.state),
+                None,
+            )),
+            None => Err(ApplicationError::not_found(
+                &format!("Task {id} not found"),
+                None,
+            )),
+        };
+    };
+
+    settle_dependents(tasks, task.id).await?;
+
+    Ok(Json(ServerResponse::new(
+        "task",
+        json!({ "id": id, "state": TaskState::Cancelled, "previousState": task.state }),
+    )))
+}
+
+async fn get_recurring_task(
+    state: &AppState,
+    access: &EventAccess,
+    id: &str,
+) -> Result<(Task, TaskSchedule), PicaError> {
+    let task = state
+        .app_stores
+        .tasks
+        .get_one(task_filter(access, id))
+        .await?
+        .ok_or_else(|| ApplicationError::not_found(&format!("Task {id} not found"), None))?;
+
+    let Some(schedule) = task.schedule.clone() else {
+        return Err(ApplicationError::bad_request(
+            &format!("Task {id} is not recurring"),
+            None,
+        ));
+    };
+
+    Ok((task, schedule))
+}
+
+/// Matches every occurrence of the task's series
+fn series_filter(access: &EventAccess, task: &Task) -> bson::Document {
+    let mut filter = doc! { "seriesId": task.series_id.unwrap_or(task.id).to_string() };
+    filter.extend(ownership_filter(access));
+    filter
+}
+
+async fn pause_task(
+    Extension(access): Extension<Arc<EventAccess>>,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ServerResponse<Value>>, PicaError> {
+    let (task, _) = get_recurring_task(&state, &access, &id).await?;
+    let tasks = &state.app_stores.tasks;
+
+    tasks
+        .update_many(
+            series_filter(&access, &task),
+            doc! { "$set": { "schedule.paused": true } },
+        )
+        .await?;
+
+    let mut pending = series_filter(&access, &task);
+    pending.insert("state", TaskState::Pending.to_string());
+    tasks
+        .update_many(pending, doc! { "$set": { "active": false } })
+        .await?;
+
+    Ok(Json(ServerResponse::new(
+        "task",
+        json!({ "id": id, "paused": true }),
+    )))
+}
+
+async fn resume_task(
+    Extension(access): Extension<Arc<EventAccess>>,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ServerResponse<Value>>, PicaError> {
+    let (task, schedule) = get_recurring_task(&state, &access, &id).await?;
+    let tasks = &state.app_stores.tasks;
+
+    tasks
+        .update_many(
+            series_filter(&access, &task),
+            doc! { "$set": { "schedule.paused": false } },
+        )
+        .await?;
+
+    // Occurrences missed while paused are skipped
+    let next_fire_time = schedule.next_after(Utc::now())?;
+    if let Some(next_fire_time) = next_fire_time {
+        let mut pending = series_filter(&access, &task);
+        pending.insert("state", TaskState::Pending.to_string());
+        tasks
+            .update_many(
+                pending,
+                doc! {
+                    "$set": {
+                        "active": true,
+                        "startTime": next_fire_time.timestamp_millis(),
+                    }
+                },
+            )
+            .await?;
+    }
+
+    Ok(Json(ServerResponse::new(
+        "task",
+        json!({
+            "id": id,
+            "paused": false,
+            "nextFireTime": next_fire_time.map(|time| time.timestamp_millis()),
+        }),
+    )))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpcomingQuery {
+    pub count: Option<usize>,
+}
+
+async fn get_upcoming_fire_times(
+    Extension(access): Extension<Arc<EventAccess>>,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<UpcomingQuery>,
+) -> Result<Json<ServerResponse<Value>>, PicaError> {
+    let (_, schedule) = get_recurring_task(&state, &access, &id).await?;
+    let count = query
+        .count
+        .unwrap_or(DEFAULT_UPCOMING_COUNT)
+        .min(MAX_UPCOMING_COUNT);
+
+    let upcoming = schedule
+        .upcoming(Utc::now(), count)?
+        .into_iter()
+        .map(|time| time.timestamp_millis())
+        .collect::<Vec<_>>();
+
+    Ok(Json(ServerResponse::new(
+        "task",
+        json!({
+            "id": id,
+            "paused": schedule.paused,
+            "timezone": schedule.timezone,
+            "upcoming": upcoming,
+        }),
+    )))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogsQuery {
+    pub skip: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LogsPage {
+    total: u64,
+    logs: Vec<TaskLogEntry>,
+}
+
+async fn get_task_logs(
+    Extension(access): Extension<Arc<EventAccess>>,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<LogsQuery>,
+) -> Result<Json<ServerResponse<ReadResponse<TaskLogEntry>>>, PicaError> {
+    let skip = query.skip.unwrap_or(0);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LOGS_LIMIT)
+        .clamp(1, MAX_LOGS_LIMIT);
+
+    // Only the requested page of the log trail is read from the database
+    let page = state
+        .app_stores
+        .tasks
+        .aggregate(vec![
+            doc! { "$match": task_filter(&access, &id) },
+            doc! {
+                "$project": {
+                    "total": { "$size": { "$ifNull": ["$logs", []] } },
+                    "logs": {
+                        "$slice": [{ "$ifNull": ["$logs", []] }, skip as i64, limit as i64]
+                    },
+                }
+            },
+        ])
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| ApplicationError::not_found(&format!("Task {id} not found"), None))?;
+
+    let page: LogsPage = bson::from_document(page)
+        .map_err(|e| InternalError::deserialize_error(&e.to_string(), None))?;
+
+    Ok(Json(ServerResponse::new(
+        "task",
+        ReadResponse {
+            rows: page.logs,
+            total: page.total,
+            skip,
+            limit,
+        },
+    )))
+}
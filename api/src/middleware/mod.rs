@@ -2,6 +2,7 @@ pub mod header_auth;
 pub mod header_blocker;
 pub mod header_passthrough;
 pub mod jwt_auth;
+pub mod quota;
 pub mod rate_limiter;
 
 pub use header_auth::header_auth_middleware;
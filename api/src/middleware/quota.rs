@@ -0,0 +1,346 @@
+use crate::server::AppState;
+use axum::{
+    body::Body,
+    extract::State,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use bson::{doc, Bson, Document};
+use cache::local::{LocalCacheExt, QuotaCache};
+use chrono::{DateTime, Utc};
+use http::{header::RETRY_AFTER, HeaderMap, HeaderName, Request};
+use osentities::{
+    event_access::EventAccess,
+    user::{Quota, QuotaApiType, QuotaPeriod},
+    ApplicationError, PicaError, Store,
+};
+use std::sync::Arc;
+use tracing::warn;
+
+/// A quota along with how much of it has been used in the current period
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub quota: Quota,
+    pub used: u64,
+    pub reset_at: DateTime<Utc>,
+}
+
+impl QuotaUsage {
+    pub fn exceeded(&self) -> bool {
+        self.used >= self.quota.limit
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.quota.limit.saturating_sub(self.used)
+    }
+
+    /// Picks the quota the client is bound by: an exceeded quota that resets
+    /// last, or else the quota with the fewest requests left
+    pub fn most_restrictive(usages: impl IntoIterator<Item = QuotaUsage>) -> Option<QuotaUsage> {
+        usages.into_iter().min_by_key(|usage| {
+            (
+                !usage.exceeded(),
+                DateTime::<Utc>::MAX_UTC - usage.reset_at,
+                usage.remaining(),
+            )
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct QuotaLimiter {
+    state: Arc<AppState>,
+    quotas_cache: QuotaCache,
+    key_header_name: HeaderName,
+    limit_header_name: HeaderName,
+    remaining_header_name: HeaderName,
+    reset_header_name: HeaderName,
+}
+
+impl QuotaLimiter {
+    pub fn from_state(state: Arc<AppState>) -> Option<Self> {
+        if !state.config.quota_enabled {
+            return None;
+        }
+
+        let key_header_name =
+            HeaderName::from_lowercase(state.config.headers.connection_header.as_bytes()).unwrap();
+
+        let limit_header_name =
+            HeaderName::from_lowercase(state.config.headers.quota_limit.as_bytes()).unwrap();
+
+        let remaining_header_name =
+            HeaderName::from_lowercase(state.config.headers.quota_remaining.as_bytes()).unwrap();
+
+        let reset_header_name =
+            HeaderName::from_lowercase(state.config.headers.quota_reset.as_bytes()).unwrap();
+
+        Some(QuotaLimiter {
+            quotas_cache: QuotaCache::new(
+                state.config.cache_size,
+                state.config.quota_cache_ttl_secs,
+            ),
+            state,
+            key_header_name,
+            limit_header_name,
+            remaining_header_name,
+            reset_header_name,
+        })
+    }
+
+    /// The quotas from the ownership's billing, or the configured defaults
+    async fn quotas(&self, ownership_id: Arc<str>) -> Result<Vec<Quota>, PicaError> {
+        let state = self.state.clone();
+        self.quotas_cache
+            .get_or_insert_with_fn(&ownership_id.clone(), || async move {
+                let client = state
+                    .app_stores
+                    .clients
+                    .get_one(doc! { "buildableId": ownership_id.as_ref() })
+                    .await?;
+
+                let quotas = client
+                    .and_then(|client| client.billing)
+                    .map(|billing| billing.quotas)
+                    .unwrap_or_default();
+
+                if !quotas.is_empty() {
+                    return Ok(quotas);
+                }
+
+                Ok([
+                    (QuotaPeriod::Daily, state.config.quota_daily_limit),
+                    (QuotaPeriod::Monthly, state.config.quota_monthly_limit),
+                ]
+                .into_iter()
+                .filter(|(_, limit)| *limit > 0)
+                .map(|(period, limit)| Quota {
+                    period,
+                    limit,
+                    platform: None,
+                    api_type: None,
+                })
+                .collect())
+            })
+            .await
+    }
+
+    async fn usage(
+        &self,
+        client_id: &str,
+        quotas: Vec<Quota>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<QuotaUsage>, PicaError> {
+        let projection = quotas
+            .iter()
+            .flat_map(|quota| quota.metric_paths(now))
+            .map(|path| (path, Bson::Int32(1)))
+            .collect::<Document>();
+
+        let metrics = self
+            .state
+            .app_stores
+            .db
+            .collection::<Document>(&Store::Metrics.to_string())
+            .find_one(doc! { "clientId": client_id })
+            .projection(projection)
+            .await?
+            .unwrap_or_default();
+
+        Ok(quotas
+            .into_iter()
+            .map(|quota| QuotaUsage {
+                used: quota
+                    .metric_paths(now)
+                    .iter()
+                    .map(|path| count_at(&metrics, path))
+                    .sum(),
+                reset_at: quota.period.reset_at(now),
+                quota,
+            })
+            .collect())
+    }
+
+    /// The usage of the quotas that apply to a request, `None` if the request
+    /// is not metered or no quota applies to it
+    async fn check(
+        &self,
+        event_access: &EventAccess,
+        api_type: QuotaApiType,
+        platform: Option<&str>,
+    ) -> Result<Option<QuotaUsage>, PicaError> {
+        let quotas = self
+            .quotas(event_access.ownership.id.clone())
+            .await?
+            .into_iter()
+            .filter(|quota| quota.applies_to(platform, api_type))
+            .collect::<Vec<_>>();
+
+        if quotas.is_empty() {
+            return Ok(None);
+        }
+
+        let usage = self
+            .usage(&event_access.ownership.client_id, quotas, Utc::now())
+            .await?;
+
+        Ok(QuotaUsage::most_restrictive(usage))
+    }
+
+    fn write_headers(&self, usage: &QuotaUsage, headers: &mut HeaderMap) {
+        let reset_secs = (usage.reset_at - Utc::now()).num_seconds().max(0) as u64;
+
+        headers.insert(self.limit_header_name.clone(), usage.quota.limit.into());
+        headers.insert(self.remaining_header_name.clone(), usage.remaining().into());
+        headers.insert(self.reset_header_name.clone(), reset_secs.into());
+        if usage.exceeded() {
+            headers.insert(RETRY_AFTER, reset_secs.into());
+        }
+    }
+}
+
+/// Reads the counter at a dotted path of a metrics document
+fn count_at(metrics: &Document, path: &str) -> u64 {
+    let mut segments = path.split('.').peekable();
+    let mut document = metrics;
+
+    while let Some(segment) = segments.next() {
+        match (document.get(segment), segments.peek()) {
+            (Some(Bson::Document(inner)), Some(_)) => document = inner,
+            (Some(Bson::Int32(count)), None) => return (*count).max(0) as u64,
+            (Some(Bson::Int64(count)), None) => return (*count).max(0) as u64,
+            _ => return 0,
+        }
+    }
+
+    0
+}
+
+/// Only passthrough and unified requests are counted in the metrics store
+fn api_type(path: &str) -> Option<QuotaApiType> {
+    match path.trim_start_matches('/').split('/').next() {
+        Some("passthrough") => Some(QuotaApiType::Passthrough),
+        Some("unified") => Some(QuotaApiType::Unified),
+        _ => None,
+    }
+}
+
+pub async fn quota_middleware(
+    Extension(event_access): Extension<Arc<EventAccess>>,
+    State(state): State<Arc<QuotaLimiter>>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, Response> {
+    let Some(api_type) = api_type(req.uri().path()) else {
+        return Ok(next.run(req).await);
+    };
+
+    // Connection keys look like `{environment}::{platform}::{namespace}::{suffix}`
+    let platform = req
+        .headers()
+        .get(&state.key_header_name)
+        .and_then(|key| key.to_str().ok())
+        .and_then(|key| key.split("::").nth(1));
+
+    let usage = match state.check(&event_access, api_type, platform).await {
+        Ok(Some(usage)) => usage,
+        Ok(None) => return Ok(next.run(req).await),
+        Err(e) => {
+            warn!("Could not check quotas: {e}");
+            return Ok(next.run(req).await);
+        }
+    };
+
+    if usage.exceeded() {
+        let mut res = ApplicationError::too_many_requests(
+            &format!("The {} quota has been exceeded", usage.quota.period),
+            Some("QuotaExceeded"),
+        )
+        .into_response();
+        state.write_headers(&usage, res.headers_mut());
+
+        Err(res)
+    } else {
+        let mut res = next.run(req).await;
+        state.write_headers(&usage, res.headers_mut());
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn usage(period: QuotaPeriod, limit: u64, used: u64) -> QuotaUsage {
+        let now = Utc.with_ymd_and_hms(2025, 3, 4, 12, 0, 0).unwrap();
+
+        QuotaUsage {
+            quota: Quota {
+                period,
+                limit,
+                platform: None,
+                api_type: None,
+            },
+            used,
+            reset_at: period.reset_at(now),
+        }
+    }
+
+    #[test]
+    fn test_count_at() {
+        let metrics = doc! {
+            "unified": {
+                "daily": { "2025-03-04": 7 },
+                "platforms": { "stripe": { "monthly": { "2025-03": 3_i64 } } },
+            }
+        };
+
+        assert_eq!(count_at(&metrics, "unified.daily.2025-03-04"), 7);
+        assert_eq!(
+            count_at(&metrics, "unified.platforms.stripe.monthly.2025-03"),
+            3
+        );
+        assert_eq!(count_at(&metrics, "unified.daily.2025-03-05"), 0);
+        assert_eq!(count_at(&metrics, "unified.daily"), 0);
+        assert_eq!(count_at(&metrics, "passthrough.daily.2025-03-04"), 0);
+    }
+
+    #[test]
+    fn test_api_type() {
+        assert_eq!(
+            api_type("/passthrough/customers"),
+            Some(QuotaApiType::Passthrough)
+        );
+        assert_eq!(
+            api_type("/unified/customers/1"),
+            Some(QuotaApiType::Unified)
+        );
+        assert_eq!(api_type("/connections"), None);
+    }
+
+    #[test]
+    fn test_most_restrictive_quota() {
+        let daily = usage(QuotaPeriod::Daily, 100, 10);
+        let monthly = usage(QuotaPeriod::Monthly, 1000, 995);
+
+        assert_eq!(
+            QuotaUsage::most_restrictive([daily.clone(), monthly.clone()]),
+            Some(monthly.clone())
+        );
+
+        let exceeded_daily = usage(QuotaPeriod::Daily, 100, 100);
+        let exceeded_monthly = usage(QuotaPeriod::Monthly, 1000, 1000);
+
+        assert_eq!(
+            QuotaUsage::most_restrictive([exceeded_daily.clone(), monthly]),
+            Some(exceeded_daily.clone())
+        );
+        assert_eq!(
+            QuotaUsage::most_restrictive([exceeded_daily, exceeded_monthly.clone()]),
+            Some(exceeded_monthly)
+        );
+    }
+}
@@ -8,19 +8,235 @@ use axum::{
     Extension,
 };
 use cache::remote::RedisCache;
-use http::{HeaderName, Request};
+use http::{header::RETRY_AFTER, HeaderMap, HeaderName, Request};
 use osentities::{event_access::EventAccess, ApplicationError};
-use redis::AsyncCommands;
-use std::sync::Arc;
-use tokio::sync::{
-    mpsc::{channel, Sender},
-    oneshot,
+use redis::Script;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
-use tracing::warn;
+use strum::AsRefStr;
+use tokio::sync::{mpsc::Sender, OnceCell};
+use tracing::{info, warn};
 
-#[derive(Debug, Clone)]
+/// Local buckets idle for an hour are dropped once there are more than this
+const MAX_LOCAL_BUCKETS: usize = 10_000;
+
+/// Refills every bucket in KEYS by the time elapsed since it was last seen and
+/// takes a token from all of them, or from none if any bucket is empty. ARGV
+/// holds the capacity and the refill rate per millisecond of each bucket, in
+/// the same order as KEYS. Returns whether the request is allowed along with
+/// the tokens left in each bucket.
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local allowed = 1
+local tokens = {}
+
+for i, key in ipairs(KEYS) do
+    local capacity = tonumber(ARGV[i * 2 - 1])
+    local rate = tonumber(ARGV[i * 2])
+    local bucket = redis.call('HMGET', key, 'tokens', 'ts')
+    local available = tonumber(bucket[1]) or capacity
+    local elapsed = math.max(0, now - (tonumber(bucket[2]) or now))
+
+    available = math.min(capacity, available + elapsed * rate)
+    if available < 1 then
+        allowed = 0
+    end
+    tokens[i] = available
+end
+
+for i, key in ipairs(KEYS) do
+    local capacity = tonumber(ARGV[i * 2 - 1])
+    local rate = tonumber(ARGV[i * 2])
+
+    if allowed == 1 then
+        tokens[i] = tokens[i] - 1
+    end
+    if rate > 0 then
+        redis.call('HSET', key, 'tokens', tokens[i], 'ts', now)
+        redis.call('PEXPIRE', key, math.ceil((capacity - tokens[i]) / rate) + 1000)
+    end
+    tokens[i] = tostring(tokens[i])
+end
+
+return { allowed, tokens }
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum RateLimitScope {
+    Ownership,
+    Connection,
+    Platform,
+}
+
+/// A token bucket holding up to `capacity` tokens, refilled at `limit` tokens
+/// per window
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket {
+    pub scope: RateLimitScope,
+    pub key: String,
+    pub limit: u64,
+    pub capacity: f64,
+    pub refill_per_ms: f64,
+    pub window_secs: u64,
+}
+
+impl Bucket {
+    pub fn new(
+        scope: RateLimitScope,
+        key: String,
+        limit: u64,
+        window_secs: u64,
+        burst_percent: u64,
+    ) -> Self {
+        let burst = (limit * burst_percent) as f64 / 100.0;
+
+        Self {
+            scope,
+            key,
+            limit,
+            capacity: limit as f64 + burst,
+            refill_per_ms: limit as f64 / (window_secs.max(1) * 1000) as f64,
+            window_secs,
+        }
+    }
+
+    /// The share of this bucket a single replica enforces on its own
+    pub fn per_replica(&self, replicas: u64) -> Self {
+        let replicas = replicas.max(1);
+
+        Self {
+            limit: self.limit.div_ceil(replicas),
+            capacity: self.capacity / replicas as f64,
+            refill_per_ms: self.refill_per_ms / replicas as f64,
+            ..self.clone()
+        }
+    }
+
+    /// Describes the bucket once it holds `tokens`
+    pub fn rate_limit(&self, tokens: f64, allowed: bool) -> RateLimit {
+        let secs_until = |target: f64| {
+            if tokens >= target {
+                0
+            } else if self.refill_per_ms > 0.0 {
+                ((target - tokens) / self.refill_per_ms / 1000.0).ceil() as u64
+            } else {
+                // Buckets without a limit never refill, so clients back off for a window
+                self.window_secs
+            }
+        };
+
+        RateLimit {
+            scope: self.scope,
+            allowed,
+            limit: self.limit,
+            remaining: tokens.max(0.0).floor() as u64,
+            reset_secs: secs_until(self.capacity),
+            retry_after_secs: (!allowed).then(|| secs_until(1.0)),
+        }
+    }
+}
+
+/// The outcome of a rate limit check, as reported to the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub scope: RateLimitScope,
+    pub allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Seconds until the bucket is full again
+    pub reset_secs: u64,
+    /// Seconds until the next request would be allowed
+    pub retry_after_secs: Option<u64>,
+}
+
+impl RateLimit {
+    /// Picks the limit the client is bound by: the rejecting bucket that takes
+    /// the longest to recover, or else the bucket with the fewest requests left
+    pub fn most_restrictive(limits: impl IntoIterator<Item = RateLimit>) -> Option<RateLimit> {
+        limits.into_iter().min_by_key(|limit| {
+            (
+                limit.allowed,
+                u64::MAX - limit.retry_after_secs.unwrap_or_default(),
+                limit.remaining,
+            )
+        })
+    }
+
+    fn write_headers(&self, state: &RateLimiter, headers: &mut HeaderMap) {
+        headers.insert(state.limit_header_name.clone(), self.limit.into());
+        headers.insert(state.remaining_header_name.clone(), self.remaining.into());
+        headers.insert(state.reset_header_name.clone(), self.reset_secs.into());
+        if let Some(retry_after) = self.retry_after_secs {
+            headers.insert(RETRY_AFTER, retry_after.into());
+        }
+    }
+}
+
+/// In-memory token buckets used while Redis is unreachable. Mirrors
+/// [`TOKEN_BUCKET_SCRIPT`] for a single replica.
+#[derive(Debug, Default)]
+pub struct LocalBuckets {
+    buckets: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+impl LocalBuckets {
+    pub fn acquire(&self, buckets: &[Bucket], now: Instant) -> Option<RateLimit> {
+        let mut state = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if state.len() > MAX_LOCAL_BUCKETS {
+            state.retain(|_, (_, last_seen)| {
+                now.duration_since(*last_seen) < Duration::from_secs(3600)
+            });
+        }
+
+        let tokens = buckets
+            .iter()
+            .map(|bucket| match state.get(&bucket.key) {
+                Some((tokens, last_seen)) => {
+                    let elapsed = now.duration_since(*last_seen).as_millis() as f64;
+                    bucket.capacity.min(tokens + elapsed * bucket.refill_per_ms)
+                }
+                None => bucket.capacity,
+            })
+            .collect::<Vec<_>>();
+        let allowed = tokens.iter().all(|tokens| *tokens >= 1.0);
+
+        let limits = buckets
+            .iter()
+            .zip(tokens)
+            .map(|(bucket, tokens)| {
+                let tokens = if allowed { tokens - 1.0 } else { tokens };
+                state.insert(bucket.key.clone(), (tokens, now));
+                bucket.rate_limit(tokens, allowed)
+            })
+            .collect::<Vec<_>>();
+
+        RateLimit::most_restrictive(limits)
+    }
+}
+
+#[derive(Clone)]
 pub struct RateLimiter {
-    tx: Sender<(Arc<str>, oneshot::Sender<u64>)>,
+    /// Set once a connection to Redis has been established
+    redis: Arc<OnceCell<RedisCache>>,
+    script: Arc<Script>,
+    local: Arc<LocalBuckets>,
+    /// Whether requests are currently limited by [`LocalBuckets`]
+    degraded: Arc<AtomicBool>,
+    replicas: u64,
+    prefix: String,
+    window_secs: u64,
+    burst_percent: u64,
+    connection_limit: u64,
+    platform_limit: u64,
     key_header_name: HeaderName,
     limit_header_name: HeaderName,
     remaining_header_name: HeaderName,
@@ -34,30 +250,24 @@ impl RateLimiter {
             return Err(anyhow::anyhow!("Rate limiting is disabled"));
         };
 
-        let mut redis = RedisCache::new(&state.config.cache_config)
-            .await
-            .inspect(|_| {
+        let redis = Arc::new(OnceCell::new());
+        match RedisCache::new(&state.config.cache_config).await {
+            Ok(cache) => {
                 tracing::info!("Connected to redis at {}", state.config.cache_config.url);
-            })
-            .context(format!(
-                "Could not connect to redis at {}",
-                state.config.cache_config.url
-            ))?;
-
-        let (tx, mut rx) = channel::<(Arc<str>, oneshot::Sender<u64>)>(1024);
-
-        let throughput_key = state.config.cache_config.api_throughput_key.clone();
-
-        tokio::spawn(async move {
-            while let Some((id, tx)) = rx.recv().await {
-                let count: u64 = redis
-                    .inner
-                    .hincr(&throughput_key, id.as_ref(), 1)
-                    .await
-                    .unwrap_or_default();
-                let _ = tx.send(count);
+                let _ = redis.set(cache);
             }
-        });
+            Err(e) => {
+                warn!(
+                    "Could not connect to redis at {}, rate limiting locally until it is reachable: {e}",
+                    state.config.cache_config.url
+                );
+                tokio::spawn(connect_redis(
+                    state.clone(),
+                    redis.clone(),
+                    Duration::from_secs(state.config.rate_limit_reconnect_interval_secs),
+                ));
+            }
+        }
 
         let key_header_name =
             HeaderName::from_lowercase(state.config.headers.connection_header.as_bytes()).unwrap();
@@ -73,7 +283,16 @@ impl RateLimiter {
             HeaderName::from_lowercase(state.config.headers.rate_limit_reset.as_bytes()).unwrap();
 
         Ok(RateLimiter {
-            tx,
+            redis,
+            script: Arc::new(Script::new(TOKEN_BUCKET_SCRIPT)),
+            local: Arc::new(LocalBuckets::default()),
+            degraded: Arc::new(AtomicBool::new(false)),
+            replicas: state.config.rate_limit_replicas,
+            prefix: state.config.cache_config.api_throughput_key.clone(),
+            window_secs: state.config.rate_limit_window_secs,
+            burst_percent: state.config.rate_limit_burst_percent,
+            connection_limit: state.config.rate_limit_connection_limit,
+            platform_limit: state.config.rate_limit_platform_limit,
             metric_tx: state.metric_tx.clone(),
             key_header_name,
             limit_header_name,
@@ -82,14 +301,120 @@ impl RateLimiter {
         })
     }
 
-    pub async fn get_request_count(&self, id: Arc<str>) -> u64 {
-        let (tx, rx) = oneshot::channel();
-        match self.tx.send((id, tx)).await {
-            Ok(()) => rx.await.unwrap_or_default(),
+    /// The buckets a request is counted against. Connection keys look like
+    /// `{environment}::{platform}::{namespace}::{suffix}`.
+    pub fn buckets(&self, event_access: &EventAccess, connection_key: Option<&str>) -> Vec<Bucket> {
+        let ownership_id = &event_access.ownership.id;
+        let bucket = |scope, key, limit| {
+            Bucket::new(scope, key, limit, self.window_secs, self.burst_percent)
+        };
+
+        let mut buckets = vec![bucket(
+            RateLimitScope::Ownership,
+            format!("{}:ownership:{ownership_id}", self.prefix),
+            event_access.throughput,
+        )];
+
+        if let Some(connection_key) = connection_key {
+            if self.connection_limit > 0 {
+                buckets.push(bucket(
+                    RateLimitScope::Connection,
+                    format!("{}:connection:{connection_key}", self.prefix),
+                    self.connection_limit,
+                ));
+            }
+
+            if let Some(platform) = connection_key.split("::").nth(1) {
+                if self.platform_limit > 0 {
+                    buckets.push(bucket(
+                        RateLimitScope::Platform,
+                        format!("{}:platform:{ownership_id}:{platform}", self.prefix),
+                        self.platform_limit,
+                    ));
+                }
+            }
+        }
+
+        buckets
+    }
+
+    /// Takes a token from every bucket, or from none of them if any is empty.
+    /// Falls back to this replica's share of the buckets if Redis is unreachable.
+    pub async fn acquire(&self, buckets: &[Bucket]) -> Option<RateLimit> {
+        let remote = match self.redis.get() {
+            Some(redis) => self.acquire_remote(redis, buckets).await,
+            None => Err(anyhow::anyhow!("Not connected to redis")),
+        };
+
+        match remote {
+            Ok(rate_limit) => {
+                if self.degraded.swap(false, Ordering::Relaxed) {
+                    info!(
+                        rate_limiter.degraded = false,
+                        "Redis is reachable again, rate limiting globally"
+                    );
+                }
+
+                rate_limit
+            }
             Err(e) => {
-                warn!("Could not send to redis task: {e}");
-                0
+                if !self.degraded.swap(true, Ordering::Relaxed) {
+                    warn!(
+                        rate_limiter.degraded = true,
+                        rate_limiter.replicas = self.replicas,
+                        "Rate limiting locally until redis is reachable: {e:?}"
+                    );
+                }
+
+                let buckets = buckets
+                    .iter()
+                    .map(|bucket| bucket.per_replica(self.replicas))
+                    .collect::<Vec<_>>();
+
+                self.local.acquire(&buckets, Instant::now())
+            }
+        }
+    }
+
+    async fn acquire_remote(
+        &self,
+        redis: &RedisCache,
+        buckets: &[Bucket],
+    ) -> Result<Option<RateLimit>> {
+        let mut invocation = self.script.prepare_invoke();
+        for bucket in buckets {
+            invocation
+                .key(&bucket.key)
+                .arg(bucket.capacity)
+                .arg(bucket.refill_per_ms);
+        }
+
+        let (allowed, tokens): (i64, Vec<String>) = invocation
+            .invoke_async(&mut redis.inner.clone())
+            .await
+            .context("Could not run the rate limiter script")?;
+
+        let limits = buckets.iter().zip(tokens).map(|(bucket, tokens)| {
+            bucket.rate_limit(tokens.parse().unwrap_or_default(), allowed == 1)
+        });
+
+        Ok(RateLimit::most_restrictive(limits))
+    }
+}
+
+/// Retries the connection to Redis until it succeeds, so that a replica that
+/// started during an outage eventually limits globally
+async fn connect_redis(state: Arc<AppState>, redis: Arc<OnceCell<RedisCache>>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+
+        match RedisCache::new(&state.config.cache_config).await {
+            Ok(cache) => {
+                info!("Connected to redis at {}", state.config.cache_config.url);
+                let _ = redis.set(cache);
+                return;
             }
+            Err(e) => warn!("Could not connect to redis: {e}"),
         }
     }
 }
@@ -100,40 +425,130 @@ pub async fn rate_limit_middleware(
     req: Request<Body>,
     next: Next,
 ) -> Result<Response, Response> {
-    let throughput = event_access.throughput;
+    let connection_key = req.headers().get(&state.key_header_name).cloned();
+    let buckets = state.buckets(
+        &event_access,
+        connection_key.as_ref().and_then(|key| key.to_str().ok()),
+    );
+
+    let Some(rate_limit) = state.acquire(&buckets).await else {
+        return Ok(next.run(req).await);
+    };
 
-    let count = state
-        .get_request_count(event_access.ownership.id.clone())
-        .await;
+    if rate_limit.allowed {
+        let mut res = next.run(req).await;
+        rate_limit.write_headers(&state, res.headers_mut());
 
-    if count >= throughput {
+        Ok(res)
+    } else {
         let _ = state
             .metric_tx
-            .send(Metric::rate_limited(
-                event_access.clone(),
-                req.headers().get(&state.key_header_name).cloned(),
-            ))
+            .send(Metric::rate_limited(event_access.clone(), connection_key))
             .await;
-        let mut res =
-            ApplicationError::too_many_requests("Rate limit exceeded", None).into_response();
+        let mut res = ApplicationError::too_many_requests(
+            &format!("Rate limit exceeded for {}", rate_limit.scope.as_ref()),
+            None,
+        )
+        .into_response();
+        rate_limit.write_headers(&state, res.headers_mut());
+
+        Err(res)
+    }
+}
 
-        let headers = res.headers_mut();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        headers.insert(state.limit_header_name.clone(), throughput.into());
-        headers.insert(state.remaining_header_name.clone(), 0.into());
-        headers.insert(state.reset_header_name.clone(), 60.into());
+    fn bucket(limit: u64, burst_percent: u64) -> Bucket {
+        Bucket::new(
+            RateLimitScope::Ownership,
+            "api_throughput:ownership:1".to_string(),
+            limit,
+            60,
+            burst_percent,
+        )
+    }
 
-        Err(res)
-    } else {
-        let mut res = next.run(req).await;
-        let headers = res.headers_mut();
+    #[test]
+    fn test_bucket_burst_and_refill() {
+        let bucket = bucket(120, 50);
+
+        assert_eq!(bucket.capacity, 180.0);
+        assert_eq!(bucket.refill_per_ms, 0.002);
+    }
+
+    #[test]
+    fn test_rate_limit_headers() {
+        let bucket = bucket(120, 0);
+
+        let allowed = bucket.rate_limit(59.5, true);
+        assert_eq!(allowed.remaining, 59);
+        assert_eq!(allowed.reset_secs, 31);
+        assert_eq!(allowed.retry_after_secs, None);
+
+        let rejected = bucket.rate_limit(0.2, false);
+        assert_eq!(rejected.remaining, 0);
+        assert_eq!(rejected.retry_after_secs, Some(1));
+        assert_eq!(rejected.reset_secs, 60);
+    }
+
+    #[test]
+    fn test_empty_bucket_never_refills() {
+        let rejected = bucket(0, 0).rate_limit(0.0, false);
+
+        assert_eq!(rejected.limit, 0);
+        assert_eq!(rejected.retry_after_secs, Some(60));
+    }
 
-        headers.insert(state.limit_header_name.clone(), throughput.into());
-        headers.insert(
-            state.remaining_header_name.clone(),
-            (throughput - count).into(),
+    #[test]
+    fn test_per_replica() {
+        let bucket = bucket(100, 20).per_replica(3);
+
+        assert_eq!(bucket.limit, 34);
+        assert_eq!(bucket.capacity, 40.0);
+    }
+
+    #[test]
+    fn test_local_buckets() {
+        let local = LocalBuckets::default();
+        let buckets = [bucket(2, 0)];
+        let now = Instant::now();
+
+        assert!(local.acquire(&buckets, now).unwrap().allowed);
+        assert!(local.acquire(&buckets, now).unwrap().allowed);
+
+        let rejected = local.acquire(&buckets, now).unwrap();
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.retry_after_secs, Some(30));
+
+        let refilled = local.acquire(&buckets, now + Duration::from_secs(30));
+        assert!(refilled.unwrap().allowed);
+    }
+
+    #[test]
+    fn test_most_restrictive() {
+        let ownership = bucket(100, 0).rate_limit(40.0, true);
+        let connection = RateLimit {
+            scope: RateLimitScope::Connection,
+            remaining: 3,
+            ..ownership
+        };
+
+        assert_eq!(
+            RateLimit::most_restrictive([ownership, connection]),
+            Some(connection)
+        );
+
+        let slow = bucket(1, 0).rate_limit(0.0, false);
+        let fast = RateLimit {
+            scope: RateLimitScope::Platform,
+            ..bucket(600, 0).rate_limit(0.0, false)
+        };
+
+        assert_eq!(
+            RateLimit::most_restrictive([fast, slow, ownership]),
+            Some(slow)
         );
-        headers.insert(state.reset_header_name.clone(), 60.into());
-        Ok(res)
     }
 }
@@ -1,6 +1,6 @@
 use crate::{
     logic::{
-        common_enum, common_model, connection_definition,
+        caches, common_enum, common_model, connection_definition,
         connection_model_definition::{self},
         connection_model_schema, connection_oauth_definition, event_callback, openapi, platform,
         platform_page, secrets,
@@ -10,7 +10,7 @@ use crate::{
 };
 use axum::{
     middleware::{from_fn, from_fn_with_state},
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
 use osentities::telemetry::log_request_middleware;
@@ -41,6 +41,12 @@ pub async fn get_router(state: &Arc<AppState>) -> Router<Arc<AppState>> {
         .nest("/platform-pages", platform_page::get_router())
         .nest("/platforms", platform::get_router())
         .route("/admin/connection/:id", get(secrets::get_admin_secret))
+        .route("/admin/caches", get(caches::list_caches))
+        .route("/admin/caches/:name", delete(caches::flush_cache))
+        .route(
+            "/admin/caches/:name/entries",
+            delete(caches::evict_cache_entry),
+        )
         .route("/openapi", post(openapi::refresh_openapi));
 
     routes
@@ -12,6 +12,7 @@ use crate::{
         header_auth,
         header_blocker::{handle_blocked_error, BlockInvalidHeaders},
         header_passthrough,
+        quota::{quota_middleware, QuotaLimiter},
         rate_limiter::{rate_limit_middleware, RateLimiter},
     },
     server::AppState,
@@ -62,13 +63,21 @@ pub async fn get_router(state: &Arc<AppState>) -> Router<Arc<AppState>> {
         )
         .route("/available-actions/:platform", get(get_available_actions));
 
+    let routes = match QuotaLimiter::from_state(state.clone()) {
+        Some(quota_limiter) => routes.layer(from_fn_with_state(
+            Arc::new(quota_limiter),
+            quota_middleware,
+        )),
+        None => routes,
+    };
+
     let routes = match RateLimiter::from_state(state.clone()).await {
         Ok(rate_limiter) => routes.layer(axum::middleware::from_fn_with_state(
             Arc::new(rate_limiter),
             rate_limit_middleware,
         )),
         Err(e) => {
-            warn!("Could not connect to redis: {e}");
+            warn!("Skipping rate limiting: {e}");
             routes
         }
     };
@@ -5,17 +5,23 @@ use crate::{
     },
     helper::{K8sDriver, K8sDriverImpl, K8sDriverLogger},
     logic::{
-        connection_oauth_definition::FrontendOauthConnectionDefinition, knowledge::Knowledge,
-        openapi::OpenAPIData,
+        caches::find_cache, connection_oauth_definition::FrontendOauthConnectionDefinition,
+        knowledge::Knowledge, openapi::OpenAPIData,
     },
     router,
 };
 use anyhow::{anyhow, Context, Result};
 use axum::Router;
-use cache::local::{
-    ConnectionDefinitionCache, ConnectionHeaderCache, ConnectionOAuthDefinitionCache,
-    EventAccessCache,
+use cache::{
+    invalidation::{CacheInvalidation, CacheInvalidator},
+    local::{
+        CacheAdmin, ConnectionDefinitionCache, ConnectionHeaderCache,
+        ConnectionOAuthDefinitionCache, EventAccessCache, LocalCacheExt,
+    },
+    remote::RedisCache,
 };
+use futures::StreamExt;
+use http::HeaderValue;
 use mongodb::{options::UpdateOptions, Client, Database};
 use osentities::{
     algebra::{DefaultTemplate, MongoStore},
@@ -25,12 +31,14 @@ use osentities::{
     connection_model_schema::{ConnectionModelSchema, PublicConnectionModelSchema},
     connection_oauth_definition::{ConnectionOAuthDefinition, Settings},
     event_access::EventAccess,
+    oauth_authorization::OAuthAuthorization,
     page::PlatformPage,
     secret::Secret,
     secrets::SecretServiceProvider,
     task::Task,
     user::UserClient,
-    Connection, Event, GoogleKms, IOSKms, PlatformData, PublicConnection, SecretExt, Store,
+    Connection, Event, GoogleKms, IOSKms, PicaError, PlatformData, PublicConnection, SecretExt,
+    Store,
 };
 use std::{sync::Arc, time::Duration};
 use tokio::{net::TcpListener, sync::mpsc::Sender, time::timeout, try_join};
@@ -51,6 +59,7 @@ pub struct AppStores {
     pub model_config: MongoStore<ConnectionModelDefinition>,
     pub model_schema: MongoStore<ConnectionModelSchema>,
     pub oauth_config: MongoStore<ConnectionOAuthDefinition>,
+    pub oauth_authorizations: MongoStore<OAuthAuthorization>,
     pub platform: MongoStore<PlatformData>,
     pub platform_page: MongoStore<PlatformPage>,
     pub public_connection: MongoStore<PublicConnection>,
@@ -60,6 +69,7 @@ pub struct AppStores {
     pub secrets: MongoStore<Secret>,
     pub settings: MongoStore<Settings>,
     pub tasks: MongoStore<Task>,
+    pub cache_invalidator: CacheInvalidator,
 }
 
 #[derive(Clone)]
@@ -91,11 +101,35 @@ impl Server {
         let client = Client::with_uri_str(&config.db_config.event_db_url).await?;
         let db = client.database(&config.db_config.event_db_name);
 
+        let secrets_store = MongoStore::<Secret>::new(&db, &Store::Secrets).await?;
+        let secrets_client: Arc<dyn SecretExt + Sync + Send> = match config.secrets_config.provider
+        {
+            SecretServiceProvider::GoogleKms => {
+                Arc::new(GoogleKms::new(&config.secrets_config, secrets_store).await?)
+            }
+            SecretServiceProvider::IosKms => {
+                Arc::new(IOSKms::new(&config.secrets_config, secrets_store).await?)
+            }
+        };
+
+        Self::init_with_secrets_client(config, secrets_client).await
+    }
+
+    /// Like [`Server::init`], but with secrets stored and resolved by
+    /// `secrets_client` instead of the configured provider
+    pub async fn init_with_secrets_client(
+        config: ConnectionsConfig,
+        secrets_client: Arc<dyn SecretExt + Sync + Send>,
+    ) -> Result<Self> {
+        let client = Client::with_uri_str(&config.db_config.event_db_url).await?;
+        let db = client.database(&config.db_config.event_db_name);
+
         let http_client = reqwest::ClientBuilder::new()
             .timeout(Duration::from_secs(config.http_client_timeout_secs))
             .build()?;
         let model_config = MongoStore::new(&db, &Store::ConnectionModelDefinitions).await?;
         let oauth_config = MongoStore::new(&db, &Store::ConnectionOAuthDefinitions).await?;
+        let oauth_authorizations = MongoStore::new(&db, &Store::OAuthAuthorizations).await?;
         let frontend_oauth_config =
             MongoStore::new(&db, &Store::ConnectionOAuthDefinitions).await?;
         let model_schema = MongoStore::new(&db, &Store::ConnectionModelSchemas).await?;
@@ -116,18 +150,13 @@ impl Server {
         let event = MongoStore::new(&db, &Store::Events).await?;
         let knowledge = MongoStore::new(&db, &Store::ConnectionModelDefinitions).await?;
         let clients = MongoStore::new(&db, &Store::Clients).await?;
-        let secrets_store = MongoStore::<Secret>::new(&db, &Store::Secrets).await?;
         let tasks = MongoStore::new(&db, &Store::Tasks).await?;
-
-        let secrets_client: Arc<dyn SecretExt + Sync + Send> = match config.secrets_config.provider
-        {
-            SecretServiceProvider::GoogleKms => {
-                Arc::new(GoogleKms::new(&config.secrets_config, secrets_store).await?)
-            }
-            SecretServiceProvider::IosKms => {
-                Arc::new(IOSKms::new(&config.secrets_config, secrets_store).await?)
-            }
-        };
+        let cache_invalidator = CacheInvalidator::new(
+            config.cache_invalidation_transport,
+            &config.cache_config,
+            &db,
+        )
+        .await?;
 
         let tracker_client: Arc<dyn Track<TrackedMetric>> = match (
             config.posthog_write_key.as_ref(),
@@ -139,6 +168,16 @@ impl Server {
             _ => Arc::new(LoggerTracker),
         };
 
+        let remote_cache = if config.remote_cache_enabled {
+            Some(
+                RedisCache::new(&config.cache_config)
+                    .await
+                    .with_context(|| "Could not connect to the remote cache")?,
+            )
+        } else {
+            None
+        };
+
         let extractor_caller = UnifiedDestination::new(
             config.db_config.clone(),
             config.cache_size,
@@ -150,15 +189,22 @@ impl Server {
                 connection_model_definition_cache_ttl_secs: config
                     .connection_model_definition_cache_ttl_secs,
                 secret_cache_ttl_secs: config.secret_cache_ttl_secs,
+                connection_negative_cache_ttl_secs: config.connection_negative_cache_ttl_secs,
+                connection_model_definition_negative_cache_ttl_secs: config
+                    .connection_model_definition_negative_cache_ttl_secs,
             },
+            config.common_model_validation,
+            remote_cache.as_ref(),
         )
         .await
-        .with_context(|| "Could not initialize extractor caller")?;
+        .with_context(|| "Could not initialize extractor caller")?
+        .with_cache_invalidator(cache_invalidator.clone());
 
         let app_stores = AppStores {
             db: db.clone(),
             model_config,
             oauth_config,
+            oauth_authorizations,
             platform_page,
             frontend_oauth_config,
             secrets,
@@ -177,20 +223,29 @@ impl Server {
             event,
             clients,
             tasks,
+            cache_invalidator,
         };
 
         let event_access_cache =
-            EventAccessCache::new(config.cache_size, config.access_key_cache_ttl_secs);
+            EventAccessCache::new(config.cache_size, config.access_key_cache_ttl_secs)
+                .with_remote(remote_cache.as_ref(), "event-access")
+                .with_negative_ttl(config.access_key_negative_cache_ttl_secs);
         let connections_cache =
-            ConnectionHeaderCache::new(config.cache_size, config.connection_cache_ttl_secs);
+            ConnectionHeaderCache::new(config.cache_size, config.connection_cache_ttl_secs)
+                .with_remote(remote_cache.as_ref(), "connection-headers")
+                .with_negative_ttl(config.connection_negative_cache_ttl_secs);
         let connection_definitions_cache = ConnectionDefinitionCache::new(
             config.cache_size,
             config.connection_definition_cache_ttl_secs,
-        );
+        )
+        .with_remote(remote_cache.as_ref(), "connection-definitions")
+        .with_negative_ttl(config.connection_definition_negative_cache_ttl_secs);
         let connection_oauth_definitions_cache = ConnectionOAuthDefinitionCache::new(
             config.cache_size,
             config.connection_oauth_definition_cache_ttl_secs,
-        );
+        )
+        .with_remote(remote_cache.as_ref(), "connection-oauth-definitions")
+        .with_negative_ttl(config.connection_oauth_definition_negative_cache_ttl_secs);
         let openapi_data = OpenAPIData::default();
         openapi_data.spawn_openapi_generation(
             app_stores.common_model.clone(),
@@ -300,25 +355,27 @@ impl Server {
             }
         });
 
-        Ok(Self {
-            state: Arc::new(AppState {
-                app_stores,
-                config,
-                connection_definitions_cache,
-                connection_oauth_definitions_cache,
-                connections_cache,
-                event_access_cache,
-                event_tx,
-                extractor_caller,
-                http_client,
-                k8s_client,
-                metric_tx,
-                openapi_data,
-                secrets_client,
-                tracker_client,
-                template,
-            }),
-        })
+        let state = Arc::new(AppState {
+            app_stores,
+            config,
+            connection_definitions_cache,
+            connection_oauth_definitions_cache,
+            connections_cache,
+            event_access_cache,
+            event_tx,
+            extractor_caller,
+            http_client,
+            k8s_client,
+            metric_tx,
+            openapi_data,
+            secrets_client,
+            tracker_client,
+            template,
+        });
+
+        tokio::spawn(evict_invalidated_entries(state.clone()));
+
+        Ok(Self { state })
     }
 
     pub async fn run(&self) -> Result<()> {
@@ -335,3 +392,69 @@ impl Server {
             .map_err(|e| anyhow!("Server error: {}", e))
     }
 }
+
+/// Evicts the entries other replicas (or this one) report as stale, for as
+/// long as the server runs
+async fn evict_invalidated_entries(state: Arc<AppState>) {
+    loop {
+        match state.app_stores.cache_invalidator.subscribe().await {
+            Ok(mut invalidations) => {
+                while let Some(invalidation) = invalidations.next().await {
+                    trace!("Evicting cache entries for {invalidation:?}");
+                    if let Err(e) = evict(&state, invalidation).await {
+                        warn!("Could not evict cache entries: {e}");
+                    }
+                }
+
+                warn!("Cache invalidation subscription ended, resubscribing");
+            }
+            Err(e) => error!("Could not subscribe to cache invalidations: {e}"),
+        }
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+async fn evict(state: &AppState, invalidation: CacheInvalidation) -> Result<(), PicaError> {
+    let caches = &state.extractor_caller;
+
+    match invalidation {
+        CacheInvalidation::Connection {
+            id,
+            key,
+            ownership_id,
+        } => {
+            if let Ok(header) = HeaderValue::from_str(&key) {
+                state
+                    .connections_cache
+                    .remove(&(ownership_id, header))
+                    .await?;
+            }
+            caches.connections_cache.remove(&key).await?;
+            caches.secrets_cache.evict(&id.to_string()).await
+        }
+        CacheInvalidation::ConnectionDefinition { id } => {
+            state.connection_definitions_cache.remove(&id).await
+        }
+        CacheInvalidation::ConnectionOAuthDefinition { id } => {
+            state.connection_oauth_definitions_cache.remove(&id).await
+        }
+        CacheInvalidation::ConnectionModelDefinition { platform, .. } => {
+            caches
+                .connection_model_definitions_cache
+                .remove_tagged(&platform)
+                .await
+        }
+        CacheInvalidation::ConnectionModelSchema { id } => {
+            caches
+                .connection_model_schemas_cache
+                .remove_tagged(&id.to_string())
+                .await
+        }
+        CacheInvalidation::EventAccess { access_key_digest } => {
+            state.event_access_cache.evict(&access_key_digest).await
+        }
+        CacheInvalidation::Cache { name } => find_cache(state, &name)?.flush().await,
+        CacheInvalidation::CacheEntry { name, key } => find_cache(state, &name)?.evict(&key).await,
+    }
+}
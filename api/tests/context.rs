@@ -37,11 +37,10 @@ use osentities::{
 use osentities::{SecretExt, SecretVersion, DEFAULT_AUDIENCE, DEFAULT_ISSUER};
 use rand::Rng;
 use serde::{de::DeserializeOwned, Serialize};
-use serde_json::Value;
-use serde_json::{from_value, to_value};
+use serde_json::{from_value, json, to_value, Value};
 use std::{
     collections::{BTreeMap, HashMap},
-    sync::{Arc, OnceLock},
+    sync::{Arc, Mutex, OnceLock},
     time::Duration,
 };
 use testcontainers_modules::{
@@ -76,20 +75,30 @@ pub struct TestServer {
 }
 
 #[derive(Debug, Clone, Default)]
-pub struct MockSecretsClient;
+pub struct MockSecretsClient {
+    /// The secrets created so far
+    pub created: Arc<Mutex<Vec<Value>>>,
+    /// The ids and buildable ids of the secrets deleted so far
+    pub deleted: Arc<Mutex<Vec<(String, String)>>>,
+}
 
 #[async_trait]
 impl SecretExt for MockSecretsClient {
+    /// Every secret reads as the platform secret of an OAuth app
     async fn get(&self, _id: &str, buildable_id: &str) -> Result<Secret, PicaError> {
         Ok(Secret::new(
-            "secret".to_string(),
+            json!({ "clientId": "client-id", "clientSecret": "client-secret" }).to_string(),
             Some(SecretVersion::V2),
             buildable_id.to_string(),
             None,
         ))
     }
 
-    async fn create(&self, _secret: &Value, buildable_id: &str) -> Result<Secret, PicaError> {
+    async fn create(&self, secret: &Value, buildable_id: &str) -> Result<Secret, PicaError> {
+        self.created
+            .lock()
+            .expect("Could not lock created secrets")
+            .push(secret.clone());
         Ok(Secret::new(
             "secret".to_string(),
             Some(SecretVersion::V2),
@@ -97,6 +106,14 @@ impl SecretExt for MockSecretsClient {
             None,
         ))
     }
+
+    async fn delete(&self, id: &str, buildable_id: &str) -> Result<(), PicaError> {
+        self.deleted
+            .lock()
+            .expect("Could not lock deleted secrets")
+            .push((id.to_string(), buildable_id.to_string()));
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -107,6 +124,16 @@ pub struct ApiResponse<Data: DeserializeOwned = Value> {
 
 impl TestServer {
     pub async fn new(db_name: Option<String>) -> Self {
+        Self::init(db_name, false).await
+    }
+
+    /// A server whose secrets go through `secrets_client` instead of the
+    /// configured provider
+    pub async fn with_mock_secrets(db_name: Option<String>) -> Self {
+        Self::init(db_name, true).await
+    }
+
+    async fn init(db_name: Option<String>, mock_secrets: bool) -> Self {
         // init tracing once
         TRACING.get_or_init(|| {
             let filter = EnvFilter::builder()
@@ -161,7 +188,7 @@ impl TestServer {
         ]))
         .expect("Could not create envconfig");
 
-        let secrets_client = Arc::new(MockSecretsClient);
+        let secrets_client = Arc::new(MockSecretsClient::default());
 
         let data: AccessKeyData = Faker.fake();
         let group = data.group.clone();
@@ -231,7 +258,13 @@ impl TestServer {
             .await
             .unwrap();
 
-        let server = Server::init(config.clone()).await.unwrap();
+        let server = if mock_secrets {
+            Server::init_with_secrets_client(config.clone(), secrets_client.clone())
+                .await
+                .unwrap()
+        } else {
+            Server::init(config.clone()).await.unwrap()
+        };
 
         tokio::task::spawn(async move { server.run().await });
 
@@ -507,6 +540,7 @@ impl TestServer {
                 query_params: None,
                 path_params: None,
                 body: None,
+                strict: false,
             },
             samples: SamplesInput {
                 headers: None,
@@ -1,6 +1,17 @@
 use crate::context::TestServer;
+use fake::{Fake, Faker};
 use http::{Method, StatusCode};
-use serde_json::Value;
+use mockito::Matcher;
+use mongodb::{bson::doc, Client, Database};
+use osentities::{
+    api_model_config::AuthMethod,
+    connection_definition::{ConnectionDefinition, ConnectionDefinitionType},
+    connection_oauth_definition::ConnectionOAuthDefinition,
+    environment::Environment,
+    id::{prefix::IdPrefix, Id},
+    Connection, MongoStore, Store, OAUTH_CLIENT_ID_KEY, OAUTH_CLIENT_SECRET_KEY,
+};
+use serde_json::{json, Value};
 
 #[tokio::test]
 async fn test_connection_data_models_api() {
@@ -11,3 +22,223 @@ async fn test_connection_data_models_api() {
         .unwrap();
     assert_eq!(res.code, StatusCode::OK);
 }
+
+#[tokio::test]
+async fn test_delete_oauth_connection_deletes_secret() {
+    let server = TestServer::with_mock_secrets(None).await;
+
+    let mut connection: Connection = serde_json::from_str(include_str!(
+        "../resource/osentities_domain_connection_Connection.json"
+    ))
+    .unwrap();
+    connection.id = Id::now(IdPrefix::Connection);
+    connection.environment = Environment::Live;
+    connection.ownership.id = server.live_access_key.data.id.clone().into();
+    connection.secrets_service_id = "oauth-secret".to_string();
+
+    let db = Client::with_uri_str(&server.config.db_config.event_db_url)
+        .await
+        .unwrap()
+        .database(&server.config.db_config.event_db_name);
+    MongoStore::<Connection>::new(&db, &Store::Connections)
+        .await
+        .unwrap()
+        .create_one(&connection)
+        .await
+        .unwrap();
+
+    let res = server
+        .send_request::<Value, Value>(
+            &format!("v1/connections/{}", connection.id),
+            Method::DELETE,
+            Some(&server.live_key),
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::OK);
+
+    // The connection's OAuth definition doesn't exist, so revoking its tokens
+    // fails, which must not keep its secret around
+    assert_eq!(
+        *server.secrets_client.deleted.lock().unwrap(),
+        vec![(
+            "oauth-secret".to_string(),
+            server.live_access_key.data.id.clone()
+        )]
+    );
+}
+
+/// An OAuth connection definition of a platform using the client credentials
+/// grant against `token_url`
+async fn seed_client_credentials_platform(db: &Database, token_url: String) -> Id {
+    let mut connection_definition: ConnectionDefinition = Faker.fake();
+    connection_definition.id = Id::now(IdPrefix::ConnectionDefinition);
+    connection_definition.platform = "client-credentials".to_string();
+    connection_definition.r#type = ConnectionDefinitionType::Api;
+    connection_definition.auth_method = Some(AuthMethod::OAuth);
+    connection_definition.test_connection = None;
+    connection_definition.record_metadata.deleted = false;
+
+    let token_config = json!({
+        "baseUrl": token_url,
+        "path": "/oauth/token",
+        "authMethod": { "type": "None" },
+        "content": "form",
+        "schemas": {},
+        "samples": {},
+        "responses": []
+    });
+    let oauth_definition: ConnectionOAuthDefinition = serde_json::from_value(json!({
+        "_id": Id::now(IdPrefix::ConnectionOAuthDefinition),
+        "connectionPlatform": connection_definition.platform,
+        "grantType": "clientCredentials",
+        "configuration": { "init": token_config, "refresh": token_config },
+        "compute": {
+            "init": {
+                "computation": {
+                    "entry": "compute",
+                    "function": "function compute(payload) { return { body: { grant_type: 'client_credentials', client_id: payload.clientId, client_secret: payload.clientSecret, tenant: payload.metadata.tenant } }; }",
+                    "language": "javascript",
+                },
+                "response": {
+                    "entry": "compute",
+                    "function": "function compute(response) { return { accessToken: response.access_token, expiresIn: response.expires_in }; }",
+                    "language": "javascript",
+                },
+            },
+            "refresh": {
+                "computation": null,
+                "response": {
+                    "entry": "compute",
+                    "function": "function compute(response) { return response; }",
+                    "language": "javascript",
+                },
+            },
+        },
+        "frontend": {
+            "platformRedirectUri": "",
+            "scopes": "",
+            "iosRedirectUri": "",
+        },
+    }))
+    .unwrap();
+
+    MongoStore::<ConnectionDefinition>::new(db, &Store::ConnectionDefinitions)
+        .await
+        .unwrap()
+        .create_one(&connection_definition)
+        .await
+        .unwrap();
+    MongoStore::<ConnectionOAuthDefinition>::new(db, &Store::ConnectionOAuthDefinitions)
+        .await
+        .unwrap()
+        .create_one(&oauth_definition)
+        .await
+        .unwrap();
+
+    connection_definition.id
+}
+
+fn client_credentials_payload(connection_definition_id: Id) -> Value {
+    json!({
+        "connectionDefinitionId": connection_definition_id,
+        "authFormData": {
+            OAUTH_CLIENT_ID_KEY: "client-id",
+            OAUTH_CLIENT_SECRET_KEY: "client-secret",
+            "tenant": "acme",
+        },
+        "active": true,
+    })
+}
+
+#[tokio::test]
+async fn test_create_client_credentials_connection_runs_the_grant() {
+    let mut server = TestServer::with_mock_secrets(None).await;
+    let db = Client::with_uri_str(&server.config.db_config.event_db_url)
+        .await
+        .unwrap()
+        .database(&server.config.db_config.event_db_name);
+    let connection_definition_id =
+        seed_client_credentials_platform(&db, server.mock_server.url()).await;
+
+    let mock = server
+        .mock_server
+        .mock("POST", "/oauth/token")
+        .match_body(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("grant_type".to_string(), "client_credentials".to_string()),
+            Matcher::UrlEncoded("client_id".to_string(), "client-id".to_string()),
+            Matcher::UrlEncoded("client_secret".to_string(), "client-secret".to_string()),
+            Matcher::UrlEncoded("tenant".to_string(), "acme".to_string()),
+        ]))
+        .with_status(200)
+        .with_body(r#"{"access_token":"access-token","expires_in":3600}"#)
+        .create_async()
+        .await;
+
+    let res = server
+        .send_request::<Value, Value>(
+            "v1/connections",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&client_credentials_payload(connection_definition_id)),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::OK);
+    assert!(res.data["oauth"]["enabled"].is_object());
+
+    mock.assert_async().await;
+
+    let created = server.secrets_client.created.lock().unwrap().clone();
+    assert_eq!(created.len(), 1);
+    assert_eq!(created[0]["OAUTH_ACCESS_TOKEN"], "access-token");
+    assert_eq!(created[0]["OAUTH_CLIENT_ID"], "client-id");
+    assert_eq!(created[0]["OAUTH_CLIENT_SECRET"], "client-secret");
+    // Kept so that the grant can be run again when the token expires
+    assert_eq!(created[0]["OAUTH_REQUEST_PAYLOAD"]["tenant"], "acme");
+}
+
+#[tokio::test]
+async fn test_create_client_credentials_connection_fails_when_the_provider_refuses() {
+    let mut server = TestServer::with_mock_secrets(None).await;
+    let db = Client::with_uri_str(&server.config.db_config.event_db_url)
+        .await
+        .unwrap()
+        .database(&server.config.db_config.event_db_name);
+    let connection_definition_id =
+        seed_client_credentials_platform(&db, server.mock_server.url()).await;
+
+    let mock = server
+        .mock_server
+        .mock("POST", "/oauth/token")
+        .with_status(401)
+        .with_body(r#"{"error":"invalid_client"}"#)
+        .create_async()
+        .await;
+
+    let res = server
+        .send_request::<Value, Value>(
+            "v1/connections",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&client_credentials_payload(connection_definition_id)),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::BAD_REQUEST);
+
+    mock.assert_async().await;
+    assert!(server.secrets_client.created.lock().unwrap().is_empty());
+
+    let connections = MongoStore::<Connection>::new(&db, &Store::Connections)
+        .await
+        .unwrap()
+        .count(
+            doc! { "connectionDefinitionId": connection_definition_id.to_string() },
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(connections, 0);
+}
@@ -2,7 +2,9 @@ pub mod auth;
 pub mod callback;
 pub mod connection;
 pub mod crud;
+pub mod oauth;
 pub mod pagination;
 pub mod passthrough;
 pub mod schema;
+pub mod task;
 pub mod unified;
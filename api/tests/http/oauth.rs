@@ -0,0 +1,146 @@
+use crate::context::TestServer;
+use http::{Method, StatusCode};
+use mockito::Matcher;
+use mongodb::Client;
+use osentities::{
+    connection_oauth_definition::{ConnectionOAuthDefinition, PkceMethod, Settings},
+    id::{prefix::IdPrefix, Id},
+    oauth_authorization::OAuthAuthorization,
+    MongoStore, Store,
+};
+use serde_json::{json, Value};
+
+const PLATFORM: &str = "airtable";
+
+fn token_config(base_url: String) -> Value {
+    json!({
+        "baseUrl": base_url,
+        "path": "/oauth/token",
+        "authMethod": { "type": "None" },
+        "content": "form",
+        "schemas": {},
+        "samples": {},
+        "responses": []
+    })
+}
+
+fn identity() -> Value {
+    json!({
+        "entry": "compute",
+        "function": "function compute(payload) { return payload; }",
+        "language": "javascript",
+    })
+}
+
+#[tokio::test]
+async fn test_pkce_verifier_is_sent_on_exchange() {
+    let mut server = TestServer::with_mock_secrets(None).await;
+    let buildable_id = server.live_access_key.data.id.clone();
+    let connection_definition_id = Id::now(IdPrefix::ConnectionDefinition);
+
+    let db = Client::with_uri_str(&server.config.db_config.event_db_url)
+        .await
+        .unwrap()
+        .database(&server.config.db_config.event_db_name);
+
+    let oauth_definition: ConnectionOAuthDefinition = serde_json::from_value(json!({
+        "_id": Id::now(IdPrefix::ConnectionOAuthDefinition),
+        "connectionPlatform": PLATFORM,
+        "configuration": {
+            "init": token_config(server.mock_server.url()),
+            "refresh": token_config(server.mock_server.url()),
+        },
+        "compute": {
+            "init": { "computation": null, "response": identity() },
+            "refresh": { "computation": null, "response": identity() },
+        },
+        "frontend": {
+            "platformRedirectUri": "https://example.com/authorize",
+            "scopes": "",
+            "iosRedirectUri": "",
+            "pkce": "S256",
+        },
+    }))
+    .unwrap();
+    MongoStore::<ConnectionOAuthDefinition>::new(&db, &Store::ConnectionOAuthDefinitions)
+        .await
+        .unwrap()
+        .create_one(&oauth_definition)
+        .await
+        .unwrap();
+
+    let settings: Settings = serde_json::from_value(json!({
+        "_id": Id::now(IdPrefix::Settings),
+        "ownership": { "buildableId": buildable_id, "clientId": buildable_id },
+        "connectedPlatforms": [{
+            "type": PLATFORM,
+            "connectionDefinitionId": connection_definition_id,
+            "image": null,
+            "secretsServiceId": "platform-secret",
+            "environment": "live",
+        }],
+    }))
+    .unwrap();
+    MongoStore::<Settings>::new(&db, &Store::Settings)
+        .await
+        .unwrap()
+        .create_one(&settings)
+        .await
+        .unwrap();
+
+    let res = server
+        .send_request::<Value, Value>(
+            &format!("v1/oauth/{PLATFORM}/authorize"),
+            Method::POST,
+            Some(&server.live_key),
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::OK);
+    assert_eq!(res.data["codeChallengeMethod"], "S256");
+    let state = res.data["state"].as_str().unwrap().to_string();
+
+    let authorizations = MongoStore::<OAuthAuthorization>::new(&db, &Store::OAuthAuthorizations)
+        .await
+        .unwrap();
+    let authorization = authorizations.get_one_by_id(&state).await.unwrap().unwrap();
+    assert_eq!(
+        res.data["codeChallenge"],
+        PkceMethod::S256.challenge(&authorization.code_verifier)
+    );
+
+    let mock = server
+        .mock_server
+        .mock("POST", "/oauth/token")
+        .match_body(Matcher::UrlEncoded(
+            "code_verifier".to_string(),
+            authorization.code_verifier.clone(),
+        ))
+        .with_status(200)
+        .with_body(r#"{"accessToken":"token","expiresIn":3600}"#)
+        .create_async()
+        .await;
+
+    server
+        .send_request::<Value, Value>(
+            &format!("v1/oauth/{PLATFORM}"),
+            Method::POST,
+            Some(&server.live_key),
+            Some(&json!({
+                "connectionDefinitionId": connection_definition_id,
+                "clientId": "client-id",
+                "state": state,
+            })),
+        )
+        .await
+        .unwrap();
+
+    mock.assert_async().await;
+    // A verifier is only good for a single exchange
+    assert!(authorizations
+        .get_one_by_id(&state)
+        .await
+        .unwrap()
+        .is_none());
+}
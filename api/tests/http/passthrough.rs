@@ -58,6 +58,7 @@ async fn test_passthrough_api() {
             query_params: None,
             path_params: None,
             body: None,
+            strict: false,
         },
         samples: SamplesInput {
             headers: None,
@@ -0,0 +1,78 @@
+use crate::context::TestServer;
+use chrono::Utc;
+use http::{Method, StatusCode};
+use mongodb::Client;
+use osentities::{
+    task::{Task, TaskState},
+    MongoStore, Store,
+};
+use serde_json::{json, Value};
+
+async fn create_task(server: &TestServer, payload: Value) -> String {
+    let res = server
+        .send_request::<Value, Value>(
+            "v1/tasks",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&payload),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::OK);
+
+    res.data["_id"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn test_delete_unfinished_task_cancels_it_and_settles_dependents() {
+    let server = TestServer::new(None).await;
+    let start_time = Utc::now().timestamp_millis() + 3_600_000;
+
+    let parent = create_task(
+        &server,
+        json!({
+            "startTime": start_time,
+            "endpoint": "http://localhost/hook",
+            "payload": {},
+            "await": false,
+        }),
+    )
+    .await;
+    let dependent = create_task(
+        &server,
+        json!({
+            "startTime": start_time,
+            "endpoint": "http://localhost/hook",
+            "payload": {},
+            "await": false,
+            "dependsOn": [parent],
+            "cascadeFailure": true,
+        }),
+    )
+    .await;
+
+    let res = server
+        .send_request::<Value, Value>(
+            &format!("v1/tasks/{parent}"),
+            Method::DELETE,
+            Some(&server.live_key),
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::OK);
+
+    let db = Client::with_uri_str(&server.config.db_config.event_db_url)
+        .await
+        .unwrap()
+        .database(&server.config.db_config.event_db_name);
+    let tasks = MongoStore::<Task>::new(&db, &Store::Tasks).await.unwrap();
+
+    let parent = tasks.get_one_by_id(&parent).await.unwrap().unwrap();
+    assert_eq!(parent.state, TaskState::Cancelled);
+    assert!(parent.lease.is_none());
+    assert!(!parent.metadata.active);
+
+    let dependent = tasks.get_one_by_id(&dependent).await.unwrap().unwrap();
+    assert_eq!(dependent.state, TaskState::Dead);
+}
@@ -570,6 +570,7 @@ async fn create_connection_model_definition(
             query_params: None,
             path_params: None,
             body: None,
+            strict: false,
         },
         samples: SamplesInput {
             headers: None,
@@ -51,6 +51,7 @@ fn test_json_connection_model_definition() {
                 query_params: None,
                 path_params: None,
                 body: None,
+                strict: false,
             },
             samples: SamplesInput {
                 headers: None,
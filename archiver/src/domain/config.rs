@@ -1,4 +1,4 @@
-use crate::storage::StorageProvider;
+use crate::storage::{local::LocalLayout, StorageProvider};
 use envconfig::Envconfig;
 use osentities::database::DatabaseConfig;
 use std::fmt::{Display, Formatter};
@@ -24,6 +24,10 @@ pub struct ArchiverConfig {
     pub gs_storage_uri: String,
     #[envconfig(from = "STORAGE_PROVIDER", default = "google-cloud")]
     pub storage_provider: StorageProvider,
+    #[envconfig(from = "LOCAL_STORAGE_PATH", default = "archives")]
+    pub local_storage_path: String,
+    #[envconfig(from = "LOCAL_STORAGE_LAYOUT", default = "dated")]
+    pub local_storage_layout: LocalLayout,
     #[envconfig(from = "MAX_RETRIES", default = "3")]
     pub max_retries: u32,
     #[envconfig(from = "READ_BUFFER_SIZE_BYTES", default = "262144")]
@@ -53,6 +57,14 @@ impl Display for ArchiverConfig {
                 writeln!(f, "GS_STORAGE_BUCKET: {}", self.gs_storage_bucket)?;
                 writeln!(f, "GS_STORAGE_URI: {}", self.gs_storage_uri)?;
             }
+            StorageProvider::Local => {
+                writeln!(f, "LOCAL_STORAGE_PATH: {}", self.local_storage_path)?;
+                writeln!(
+                    f,
+                    "LOCAL_STORAGE_LAYOUT: {}",
+                    self.local_storage_layout.as_ref()
+                )?;
+            }
         }
         writeln!(
             f,
@@ -28,17 +28,14 @@ use std::process::Command;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
-use storage::google_cloud::GoogleCloudStorage;
-use storage::{Extension, Storage, StorageProvider};
+use storage::{ArchiveStorage, Extension, Storage};
 use tempfile::TempDir;
 
 #[tokio::main]
 async fn main() -> Result<Unit> {
     dotenv().ok();
     let config = Arc::new(ArchiverConfig::init_from_env()?);
-    let storage = Arc::new(match config.storage_provider {
-        StorageProvider::GoogleCloud => GoogleCloudStorage::new(&config).await?,
-    });
+    let storage = Arc::new(ArchiveStorage::new(&config).await?);
 
     let subscriber = get_subscriber("archiver".into(), "info".into(), std::io::stdout, None);
     init_subscriber(subscriber);
@@ -99,7 +96,7 @@ async fn dump(
     config: &Arc<ArchiverConfig>,
     archives: &Arc<MongoStore<Event>>,
     started: &Started,
-    storage: &Arc<impl Storage>,
+    storage: &Arc<ArchiveStorage>,
     target_store: &Arc<MongoStore<Document>>,
     destructive: bool,
 ) -> Result<Unit> {
@@ -281,7 +278,7 @@ async fn dump(
 async fn save(
     config: &ArchiverConfig,
     archive: &MongoStore<Event>,
-    storage: &Arc<impl Storage>,
+    storage: &Arc<ArchiveStorage>,
     target_store: &MongoStore<Document>,
     started_event: &Started,
     times: (&DateTime<Utc>, &DateTime<Utc>),
@@ -325,6 +322,9 @@ async fn save(
         tracing::info!("Total size of all the events is {}", mem_size);
     }
 
+    // Names every file of the archive, which may be uploaded past midnight
+    let archived_at = Utc::now();
+
     let command = Command::new("mongodump")
         .arg("--uri")
         .arg(&config.db_config.event_db_url)
@@ -359,7 +359,13 @@ async fn save(
     let suffix = format!("{}-part-{}", start_time.timestamp_millis(), part);
 
     if let Err(e) = storage
-        .upload_file(&base_path, &Extension::Bson, config, suffix.clone())
+        .upload_file(
+            &base_path,
+            &Extension::Bson,
+            config,
+            suffix.clone(),
+            archived_at,
+        )
         .await
     {
         return Err(anyhow!("Failed to upload bson file: {e}"));
@@ -374,10 +380,16 @@ async fn save(
         .await?;
 
     let name = storage
-        .upload_file(&base_path, &Extension::Metadata, config, suffix.clone())
+        .upload_file(
+            &base_path,
+            &Extension::Metadata,
+            config,
+            suffix.clone(),
+            archived_at,
+        )
         .await?;
 
-    let remote_path = format!("gs://{}/{}", config.gs_storage_bucket, name);
+    let remote_path = storage.location(&name, config);
 
     archive
         .create_one(&Event::Completed(Completed::new(
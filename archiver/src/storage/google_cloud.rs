@@ -3,7 +3,7 @@ use crate::domain::config::ArchiverConfig;
 use crate::storage::Chunk;
 use crate::Extension;
 use anyhow::{Context, Result};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use google_cloud_storage::client::{Client as GClient, ClientConfig};
 use google_cloud_storage::http::objects::upload::{UploadObjectRequest, UploadType};
 use google_cloud_storage::http::objects::Object;
@@ -51,8 +51,17 @@ impl Storage for GoogleCloudStorage {
         extension: &Extension,
         config: &ArchiverConfig,
         suffix: String,
+        archived_at: DateTime<Utc>,
     ) -> Result<String> {
-        upload_file_google(base_path, extension, config, &self.client, suffix).await
+        upload_file_google(
+            base_path,
+            extension,
+            config,
+            &self.client,
+            suffix,
+            archived_at,
+        )
+        .await
     }
 }
 
@@ -62,11 +71,12 @@ async fn upload_file_google(
     config: &ArchiverConfig,
     storage: &GClient,
     suffix: String,
+    archived_at: DateTime<Utc>,
 ) -> Result<String> {
     let path = base_path.with_extension(extension.as_ref());
     let total = path.metadata()?.len();
 
-    let name = construct_file_name(&path, suffix)?;
+    let name = construct_file_name(&path, suffix, archived_at)?;
     let upload_type = UploadType::Multipart(Box::new(Object {
         name: name.clone(),
         ..Default::default()
@@ -141,14 +151,18 @@ where
     Ok(())
 }
 
-fn construct_file_name(path: &Path, suffix: String) -> Result<String> {
+pub(super) fn construct_file_name(
+    path: &Path,
+    suffix: String,
+    archived_at: DateTime<Utc>,
+) -> Result<String> {
     let file_name = path
         .file_name()
         .context("Missing file name")?
         .to_str()
         .context("Invalid file name: {path:?}")?;
 
-    let timestamp = Utc::now().format("%Y-%m-%d");
+    let timestamp = archived_at.format("%Y-%m-%d");
     let file_name = format!("{}-{}-{}", timestamp, suffix, file_name);
 
     Ok(file_name)
@@ -157,6 +171,7 @@ fn construct_file_name(path: &Path, suffix: String) -> Result<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::TimeZone;
     use fake::{Fake, Faker};
     use std::{
         io::Write,
@@ -167,11 +182,11 @@ mod tests {
     #[test]
     fn test_get_file_name() {
         let string: String = Faker.fake();
-        let file_name = construct_file_name(&PathBuf::from(string), "1-2".into())
+        let archived_at = Utc.with_ymd_and_hms(2025, 3, 4, 23, 59, 59).unwrap();
+        let file_name = construct_file_name(&PathBuf::from(string), "1-2".into(), archived_at)
             .expect("Failed to get file name");
-        let now = Utc::now().format("%Y-%m-%d").to_string();
         assert!(file_name.contains('-'));
-        assert!(file_name.contains(now.as_str()));
+        assert!(file_name.contains("2025-03-04"));
         assert!(file_name.contains("1-2"));
     }
 
@@ -0,0 +1,133 @@
+use super::{google_cloud::construct_file_name, Storage};
+use crate::domain::config::ArchiverConfig;
+use crate::Extension;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use strum::{AsRefStr, EnumString};
+use tempfile::NamedTempFile;
+
+/// How archives are arranged below the local storage directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum LocalLayout {
+    /// Every archive directly in the directory
+    Flat,
+    /// One `{year}/{month}/{day}` directory per day archives were written
+    Dated,
+}
+
+impl LocalLayout {
+    fn directory(&self, archived_at: DateTime<Utc>) -> PathBuf {
+        match self {
+            LocalLayout::Flat => PathBuf::new(),
+            LocalLayout::Dated => PathBuf::from(archived_at.format("%Y/%m/%d").to_string()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+    layout: LocalLayout,
+}
+
+impl LocalStorage {
+    pub async fn new(config: &ArchiverConfig) -> Result<Self> {
+        tokio::fs::create_dir_all(&config.local_storage_path)
+            .await
+            .with_context(|| format!("Could not create {}", config.local_storage_path))?;
+
+        Ok(LocalStorage {
+            root: PathBuf::from(&config.local_storage_path),
+            layout: config.local_storage_layout,
+        })
+    }
+}
+
+impl Storage for LocalStorage {
+    async fn upload_file(
+        &self,
+        base_path: &Path,
+        extension: &Extension,
+        _: &ArchiverConfig,
+        suffix: String,
+        archived_at: DateTime<Utc>,
+    ) -> Result<String> {
+        let path = base_path.with_extension(extension.as_ref());
+        let name = self.layout.directory(archived_at).join(construct_file_name(
+            &path,
+            suffix,
+            archived_at,
+        )?);
+        let destination = self.root.join(&name);
+
+        tokio::task::spawn_blocking(move || copy_atomically(&path, &destination)).await??;
+
+        name.to_str()
+            .map(ToString::to_string)
+            .with_context(|| format!("Invalid file name: {name:?}"))
+    }
+}
+
+/// Copies through a temporary file next to the destination and renames it
+/// into place, so that readers never see a partially written archive
+fn copy_atomically(source: &Path, destination: &Path) -> Result<()> {
+    let directory = destination
+        .parent()
+        .context("Missing destination directory")?;
+    std::fs::create_dir_all(directory)?;
+
+    let mut temporary = NamedTempFile::new_in(directory)?;
+    std::io::copy(&mut File::open(source)?, temporary.as_file_mut())?;
+    temporary.as_file().sync_all()?;
+    temporary.persist(destination)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_directory() {
+        let archived_at = Utc.with_ymd_and_hms(2025, 3, 4, 23, 59, 59).unwrap();
+
+        assert_eq!(LocalLayout::Flat.directory(archived_at), PathBuf::new());
+        assert_eq!(
+            LocalLayout::Dated.directory(archived_at),
+            PathBuf::from("2025/03/04")
+        );
+    }
+
+    #[test]
+    fn test_copy_atomically() {
+        let source_dir = TempDir::new().expect("Failed to create temp dir");
+        let destination_dir = TempDir::new().expect("Failed to create temp dir");
+
+        let source = source_dir.path().join("events.bson.gz");
+        File::create(&source)
+            .and_then(|mut file| file.write_all(b"archive"))
+            .expect("Failed to write source file");
+
+        let destination = destination_dir
+            .path()
+            .join("2025/03/04")
+            .join("events.bson.gz");
+        copy_atomically(&source, &destination).expect("Failed to copy file");
+
+        assert_eq!(
+            std::fs::read(&destination).expect("Failed to read destination"),
+            b"archive"
+        );
+        let entries = std::fs::read_dir(destination.parent().unwrap())
+            .expect("Failed to read destination directory")
+            .count();
+        assert_eq!(entries, 1);
+    }
+}
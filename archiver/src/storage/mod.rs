@@ -1,7 +1,11 @@
 pub mod google_cloud;
+pub mod local;
 
 use crate::domain::config::ArchiverConfig;
 use anyhow::Result;
+use chrono::{DateTime, Utc};
+use google_cloud::GoogleCloudStorage;
+use local::LocalStorage;
 use std::{future::Future, ops::Deref, path::Path};
 use strum::{AsRefStr, EnumString};
 
@@ -9,7 +13,7 @@ use strum::{AsRefStr, EnumString};
 #[strum(serialize_all = "kebab-case")]
 pub enum StorageProvider {
     GoogleCloud,
-    // TODO: Implement LocalStorage
+    Local,
 }
 
 #[derive(Debug)]
@@ -53,11 +57,69 @@ impl Deref for Extension {
 }
 
 pub trait Storage {
+    /// Uploads one file of an archive. Every file of an archive is named after
+    /// the same `archived_at`, so that they aren't split across days.
     fn upload_file(
         &self,
         base_path: &Path,
         extension: &Extension,
         config: &ArchiverConfig,
         suffix: String,
+        archived_at: DateTime<Utc>,
     ) -> impl Future<Output = Result<String>>;
 }
+
+/// The storage of the configured provider
+#[derive(Clone)]
+pub enum ArchiveStorage {
+    GoogleCloud(GoogleCloudStorage),
+    Local(LocalStorage),
+}
+
+impl ArchiveStorage {
+    pub async fn new(config: &ArchiverConfig) -> Result<Self> {
+        Ok(match config.storage_provider {
+            StorageProvider::GoogleCloud => {
+                ArchiveStorage::GoogleCloud(GoogleCloudStorage::new(config).await?)
+            }
+            StorageProvider::Local => ArchiveStorage::Local(LocalStorage::new(config).await?),
+        })
+    }
+
+    /// Where an uploaded file can be found
+    pub fn location(&self, name: &str, config: &ArchiverConfig) -> String {
+        match self {
+            ArchiveStorage::GoogleCloud(_) => format!("gs://{}/{}", config.gs_storage_bucket, name),
+            ArchiveStorage::Local(_) => {
+                format!(
+                    "file://{}",
+                    Path::new(&config.local_storage_path).join(name).display()
+                )
+            }
+        }
+    }
+}
+
+impl Storage for ArchiveStorage {
+    async fn upload_file(
+        &self,
+        base_path: &Path,
+        extension: &Extension,
+        config: &ArchiverConfig,
+        suffix: String,
+        archived_at: DateTime<Utc>,
+    ) -> Result<String> {
+        match self {
+            ArchiveStorage::GoogleCloud(storage) => {
+                storage
+                    .upload_file(base_path, extension, config, suffix, archived_at)
+                    .await
+            }
+            ArchiveStorage::Local(storage) => {
+                storage
+                    .upload_file(base_path, extension, config, suffix, archived_at)
+                    .await
+            }
+        }
+    }
+}
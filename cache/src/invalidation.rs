@@ -0,0 +1,291 @@
+use crate::{local::secret_digest, remote::RedisCache};
+use futures::{stream::BoxStream, StreamExt};
+use mongodb::{
+    bson::{doc, DateTime},
+    options::IndexOptions,
+    Collection, Database, IndexModel,
+};
+use osentities::{
+    cache::CacheConfig, connection_model_definition::ConnectionModelDefinition, Connection, Id,
+    InternalError, PicaError, Store, Unit, CACHE_INVALIDATION_CHANNEL, CACHE_INVALIDATION_TTL_SECS,
+};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use strum::{AsRefStr, EnumString};
+
+/// Identifies the cached entries that became stale after a write, so that
+/// every replica can evict them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum CacheInvalidation {
+    #[serde(rename_all = "camelCase")]
+    Connection {
+        id: Id,
+        key: Arc<str>,
+        ownership_id: Arc<str>,
+    },
+    ConnectionDefinition {
+        id: Id,
+    },
+    ConnectionOAuthDefinition {
+        id: Id,
+    },
+    /// Definitions of a platform are evicted together, since a new version
+    /// can take over the lookups served by another one
+    ConnectionModelDefinition {
+        id: Id,
+        platform: String,
+    },
+    ConnectionModelSchema {
+        id: Id,
+    },
+    /// Access keys are only published as their [`secret_digest`]
+    #[serde(rename_all = "camelCase")]
+    EventAccess {
+        access_key_digest: String,
+    },
+    /// A cache flushed through the admin API, by name
+    Cache {
+        name: String,
+    },
+    /// An entry evicted through the admin API, by its cache key
+    CacheEntry {
+        name: String,
+        key: String,
+    },
+}
+
+impl CacheInvalidation {
+    pub fn event_access(access_key: &str) -> Self {
+        CacheInvalidation::EventAccess {
+            access_key_digest: secret_digest(access_key.as_bytes()),
+        }
+    }
+}
+
+impl From<&ConnectionModelDefinition> for CacheInvalidation {
+    fn from(definition: &ConnectionModelDefinition) -> Self {
+        CacheInvalidation::ConnectionModelDefinition {
+            id: definition.id,
+            platform: definition.connection_platform.clone(),
+        }
+    }
+}
+
+impl From<&Connection> for CacheInvalidation {
+    fn from(connection: &Connection) -> Self {
+        CacheInvalidation::Connection {
+            id: connection.id,
+            key: connection.key.clone(),
+            ownership_id: connection.ownership.id.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CacheInvalidationRecord {
+    invalidation: CacheInvalidation,
+    created_at: DateTime,
+}
+
+/// How replicas tell each other to evict stale cache entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum CacheInvalidationTransport {
+    None,
+    Redis,
+    Mongo,
+}
+
+/// Publishes and receives [`CacheInvalidation`]s between replicas, either
+/// over Redis pub/sub or through a change stream on a Mongo collection.
+/// Change streams require Mongo to run as a replica set.
+#[derive(Clone, Default)]
+pub struct CacheInvalidator {
+    transport: Transport,
+}
+
+#[derive(Clone, Default)]
+enum Transport {
+    #[default]
+    Disabled,
+    Redis(Box<RedisTransport>),
+    Mongo(Collection<CacheInvalidationRecord>),
+}
+
+#[derive(Clone)]
+struct RedisTransport {
+    /// Subscriptions need a dedicated connection each
+    client: redis::Client,
+    publisher: RedisCache,
+}
+
+impl CacheInvalidator {
+    /// Invalidations are neither published nor received
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    pub async fn new(
+        transport: CacheInvalidationTransport,
+        config: &CacheConfig,
+        database: &Database,
+    ) -> Result<Self, PicaError> {
+        match transport {
+            CacheInvalidationTransport::None => Ok(Self::disabled()),
+            CacheInvalidationTransport::Redis => Self::redis(config).await,
+            CacheInvalidationTransport::Mongo => Self::mongo(database).await,
+        }
+    }
+
+    pub async fn redis(config: &CacheConfig) -> Result<Self, PicaError> {
+        let client = redis::Client::open(config.url.clone()).map_err(|e| {
+            tracing::warn!("Error creating the invalidation client: {:?}", e);
+            InternalError::io_err("There was an error with the configuration", None)
+        })?;
+        let publisher = RedisCache::new(config).await?;
+
+        Ok(Self {
+            transport: Transport::Redis(Box::new(RedisTransport { client, publisher })),
+        })
+    }
+
+    pub async fn mongo(database: &Database) -> Result<Self, PicaError> {
+        let collection = database.collection(&Store::CacheInvalidations.to_string());
+
+        // Messages are only useful to replicas that are already listening
+        collection
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! { "createdAt": 1 })
+                    .options(
+                        IndexOptions::builder()
+                            .expire_after(Duration::from_secs(CACHE_INVALIDATION_TTL_SECS))
+                            .build(),
+                    )
+                    .build(),
+            )
+            .await?;
+
+        Ok(Self {
+            transport: Transport::Mongo(collection),
+        })
+    }
+
+    pub async fn publish(&self, invalidation: CacheInvalidation) -> Result<Unit, PicaError> {
+        match &self.transport {
+            Transport::Disabled => Ok(()),
+            Transport::Redis(redis) => {
+                let payload = serde_json::to_string(&invalidation).map_err(|e| {
+                    InternalError::serialize_error(&format!("{e}"), Some("cache_invalidation"))
+                })?;
+
+                redis
+                    .publisher
+                    .inner
+                    .clone()
+                    .publish::<_, _, ()>(CACHE_INVALIDATION_CHANNEL, payload)
+                    .await
+                    .map_err(|e| {
+                        InternalError::io_err(
+                            &format!("Failed to publish cache invalidation: {e}"),
+                            None,
+                        )
+                    })
+            }
+            Transport::Mongo(collection) => {
+                collection
+                    .insert_one(CacheInvalidationRecord {
+                        invalidation,
+                        created_at: DateTime::now(),
+                    })
+                    .await?;
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Streams invalidations published from now on, by any replica. The stream
+    /// ends when the underlying subscription is lost.
+    pub async fn subscribe(&self) -> Result<BoxStream<'static, CacheInvalidation>, PicaError> {
+        match &self.transport {
+            Transport::Disabled => Ok(futures::stream::pending().boxed()),
+            Transport::Redis(redis) => {
+                let mut pubsub = redis.client.get_async_pubsub().await.map_err(|e| {
+                    InternalError::io_err(
+                        &format!("Failed to subscribe to cache invalidations: {e}"),
+                        None,
+                    )
+                })?;
+                pubsub
+                    .subscribe(CACHE_INVALIDATION_CHANNEL)
+                    .await
+                    .map_err(|e| {
+                        InternalError::io_err(
+                            &format!("Failed to subscribe to cache invalidations: {e}"),
+                            None,
+                        )
+                    })?;
+
+                Ok(pubsub
+                    .into_on_message()
+                    .filter_map(|message| async move {
+                        let payload = message.get_payload::<String>().ok()?;
+                        serde_json::from_str(&payload)
+                            .inspect_err(|e| {
+                                tracing::warn!("Invalid cache invalidation {payload}: {e}")
+                            })
+                            .ok()
+                    })
+                    .boxed())
+            }
+            Transport::Mongo(collection) => Ok(collection
+                .watch()
+                .pipeline([doc! { "$match": { "operationType": "insert" } }])
+                .await?
+                .filter_map(|event| async move {
+                    event
+                        .inspect_err(|e| tracing::warn!("Cache invalidation stream error: {e}"))
+                        .ok()?
+                        .full_document
+                        .map(|record| record.invalidation)
+                })
+                .boxed()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_serialize_invalidation() {
+        let invalidation = CacheInvalidation::event_access("sk_live_1");
+
+        assert_eq!(
+            serde_json::to_value(&invalidation).unwrap(),
+            json!({
+                "type": "eventAccess",
+                "accessKeyDigest": "992621cb15795f3e17e7e637aad64ef2cacf8471091104c0153e98355c520261"
+            })
+        );
+    }
+
+    #[test]
+    fn test_serialize_cache_entry_invalidation() {
+        let invalidation = CacheInvalidation::CacheEntry {
+            name: "secrets".to_string(),
+            key: "conn::1".to_string(),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&invalidation).unwrap(),
+            json!({ "type": "cacheEntry", "name": "secrets", "key": "conn::1" })
+        );
+    }
+}
@@ -1,2 +1,3 @@
+pub mod invalidation;
 pub mod local;
 pub mod remote;
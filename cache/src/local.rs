@@ -1,19 +1,29 @@
-use futures::Future;
+use crate::remote::RedisCache;
+use async_trait::async_trait;
+use futures::{Future, StreamExt};
 use http::HeaderValue;
 use moka::future::Cache;
 use mongodb::bson::Document;
 use mongodb::options::FindOneOptions;
+use osentities::common_model::CommonModel;
 use osentities::connection_definition::ConnectionDefinition;
 use osentities::connection_model_definition::ConnectionModelDefinition;
 use osentities::connection_model_schema::ConnectionModelSchema;
 use osentities::connection_oauth_definition::ConnectionOAuthDefinition;
 use osentities::destination::Destination;
 use osentities::event_access::EventAccess;
-use osentities::{ApplicationError, Connection, Id, MongoStore, PicaError, Secret, Unit};
+use osentities::user::Quota;
+use osentities::{
+    ApplicationError, Connection, Id, InternalError, JwtAssertionToken, MongoStore, PicaError,
+    Secret, Unit, REMOTE_CACHE_PREFIX,
+};
+use redis::{aio::ConnectionManager, AsyncCommands};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use sha2::{Digest, Sha256};
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -29,29 +39,19 @@ where
         filter: Document,
         options: Option<FindOneOptions>,
     ) -> impl Future<Output = Result<V, PicaError>> {
-        async move {
-            match self.get(key).await? {
-                Some(entry) => {
-                    tracing::debug!("Cache hit for key: {:?}", key);
-                    Ok(entry)
-                }
-                None => {
-                    tracing::debug!("Cache miss for key: {:?}", key);
-                    let value = store
-                        .collection
-                        .find_one(filter)
-                        .with_options(options)
-                        .await?;
-                    if let Some(value) = value {
-                        self.insert(key, &value).await?;
-                        Ok(value)
-                    } else {
-                        tracing::warn!("Value with id {:?} not found", key);
-                        Err(ApplicationError::not_found("Value not found", None))
-                    }
-                }
-            }
-        }
+        self.get_or_insert_with_fn(key, move || async move {
+            tracing::debug!("Cache miss for key: {:?}", key);
+            let value = store
+                .collection
+                .find_one(filter)
+                .with_options(options)
+                .await?;
+
+            value.ok_or_else(|| {
+                tracing::warn!("Value with id {:?} not found", key);
+                ApplicationError::not_found("Value not found", None)
+            })
+        })
     }
 
     fn get_or_insert_with_fn<F, Fut>(
@@ -94,6 +94,126 @@ where
     V: Clone + DeserializeOwned + Send + Sync + Unpin + Serialize + 'static,
 {
     inner: Arc<Cache<K, V>>,
+    ttl: u64,
+    remote: Option<RemoteTier<K>>,
+    /// See [`GenericCache::with_tag`]
+    tag: Option<fn(&V) -> String>,
+    /// Keys recently found not to exist, see [`GenericCache::with_negative_ttl`]
+    negative: Option<Arc<Cache<K, Unit>>>,
+    counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Counters {
+    fn record(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Point-in-time view of a cache, for introspection
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub entry_count: u64,
+    pub max_capacity: u64,
+    pub ttl_secs: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// `None` until the cache has served at least one lookup
+    pub hit_ratio: Option<f64>,
+    pub negative_entry_count: u64,
+    pub remote: bool,
+}
+
+/// Type-erased administration of a cache, so that caches holding different
+/// types can be listed and managed together
+#[async_trait]
+pub trait CacheAdmin: Send + Sync {
+    async fn stats(&self) -> CacheStats;
+
+    /// Evicts the entry whose [`CacheKey`] is `key`
+    async fn evict(&self, key: &str) -> Result<Unit, PicaError>;
+
+    /// Evicts every entry, including remote and negative ones
+    async fn flush(&self) -> Result<Unit, PicaError>;
+}
+
+/// Second tier shared by every replica, consulted on local misses so that
+/// cold replicas don't all go to the database for the same entries.
+#[derive(Clone)]
+struct RemoteTier<K> {
+    redis: ConnectionManager,
+    namespace: Arc<str>,
+    key: fn(&K) -> String,
+}
+
+impl<K> RemoteTier<K> {
+    fn key(&self, key: &K) -> String {
+        format!("{}:{}", self.namespace, (self.key)(key))
+    }
+
+    /// The set of the keys of the entries tagged `tag`
+    fn tag_key(&self, tag: &str) -> String {
+        format!("{}:tag:{tag}", self.namespace)
+    }
+}
+
+/// Stable string representation of a key, used to address entries in the
+/// remote tier. Unlike `Hash`, it must be identical across processes.
+pub trait CacheKey {
+    fn cache_key(&self) -> String;
+}
+
+impl CacheKey for Arc<str> {
+    fn cache_key(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl CacheKey for Id {
+    fn cache_key(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl CacheKey for HeaderValue {
+    // Header values carry API keys, which must not end up in key names
+    fn cache_key(&self) -> String {
+        secret_digest(self.as_bytes())
+    }
+}
+
+/// Hex SHA-256 of a secret, to address cache entries by it without
+/// revealing it
+pub fn secret_digest(secret: &[u8]) -> String {
+    hex::encode(Sha256::digest(secret))
+}
+
+impl CacheKey for Connection {
+    // Connections are compared by id only, so the key follows suit
+    fn cache_key(&self) -> String {
+        self.id.to_string()
+    }
+}
+
+impl CacheKey for Destination {
+    fn cache_key(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|_| format!("{self:?}"))
+    }
+}
+
+impl<A: CacheKey, B: CacheKey> CacheKey for (A, B) {
+    // Length-prefixed so that no two different pairs share a key
+    fn cache_key(&self) -> String {
+        let first = self.0.cache_key();
+        format!("{}:{first}{}", first.len(), self.1.cache_key())
+    }
 }
 
 impl<K, V> GenericCache<K, V>
@@ -107,31 +227,282 @@ where
                 Cache::builder()
                     .max_capacity(size)
                     .time_to_live(Duration::from_secs(ttl))
+                    .support_invalidation_closures()
                     .build(),
             ),
+            ttl,
+            remote: None,
+            tag: None,
+            negative: None,
+            counters: Arc::default(),
+        }
+    }
+
+    /// Remembers "not found" results for `ttl` seconds, so that lookups of
+    /// missing keys don't reach the database every time. Only kept in process.
+    pub fn with_negative_ttl(mut self, ttl: u64) -> Self {
+        self.negative = (ttl > 0).then(|| {
+            Arc::new(
+                Cache::builder()
+                    .max_capacity(self.max_capacity())
+                    .time_to_live(Duration::from_secs(ttl))
+                    .support_invalidation_closures()
+                    .build(),
+            )
+        });
+        self
+    }
+
+    /// Backs the in-process cache with Redis when `remote` is set. Entries are
+    /// stored under `namespace` with the same TTL as the local tier.
+    pub fn with_remote(mut self, remote: Option<&RedisCache>, namespace: &str) -> Self
+    where
+        K: CacheKey,
+    {
+        self.remote = remote.map(|remote| RemoteTier {
+            redis: remote.inner.clone(),
+            namespace: format!("{REMOTE_CACHE_PREFIX}:{namespace}").into(),
+            key: K::cache_key,
+        });
+        self
+    }
+
+    /// Groups entries by a property of their value, so that they can be
+    /// evicted together with [`GenericCache::remove_tagged`] without knowing
+    /// their keys. Remote entries are indexed by tag as they are written.
+    pub fn with_tag(mut self, tag: fn(&V) -> String) -> Self {
+        self.tag = Some(tag);
+        self
+    }
+
+    /// Evicts every entry tagged `tag`, including remote ones, and every
+    /// negative entry since those have no value to be tagged by
+    pub async fn remove_tagged(&self, tag: &str) -> Result<Unit, PicaError> {
+        let Some(tag_of) = self.tag else {
+            return Err(InternalError::configuration_error(
+                "Cache entries are not tagged",
+                None,
+            ));
+        };
+
+        if let Some(remote) = &self.remote {
+            let mut redis = remote.redis.clone();
+            let tag_key = remote.tag_key(tag);
+            match redis.smembers::<_, Vec<String>>(&tag_key).await {
+                Ok(mut keys) => {
+                    keys.push(tag_key);
+                    let _ = redis.del::<_, ()>(keys).await.inspect_err(|e| {
+                        tracing::warn!("Failed to remove {tag} from remote cache: {e}")
+                    });
+                }
+                Err(e) => tracing::warn!("Failed to read {tag} from remote cache: {e}"),
+            }
+        }
+
+        if let Some(negative) = &self.negative {
+            negative.invalidate_all();
+        }
+
+        let tag = tag.to_string();
+        self.inner
+            .invalidate_entries_if(move |_, value| tag_of(value) == tag)
+            .map_err(|e| {
+                InternalError::unknown(&format!("Failed to invalidate cache entries: {e}"), None)
+            })?;
+
+        Ok(())
+    }
+
+    async fn clear_remote(&self) {
+        let Some(remote) = &self.remote else {
+            return;
+        };
+
+        let mut redis = remote.redis.clone();
+        let keys = match redis
+            .scan_match::<_, String>(format!("{}:*", remote.namespace))
+            .await
+        {
+            Ok(keys) => keys.collect::<Vec<_>>().await,
+            Err(e) => {
+                tracing::warn!("Failed to scan remote cache {}: {e}", remote.namespace);
+                vec![]
+            }
+        };
+
+        if !keys.is_empty() {
+            let _ = redis.del::<_, ()>(keys).await.inspect_err(|e| {
+                tracing::warn!("Failed to clear remote cache {}: {e}", remote.namespace)
+            });
+        }
+    }
+
+    async fn get_remote(&self, remote: &RemoteTier<K>, key: &K) -> Option<V> {
+        let value: Option<String> = remote
+            .redis
+            .clone()
+            .get(remote.key(key))
+            .await
+            .inspect_err(|e| tracing::warn!("Failed to read {key:?} from remote cache: {e}"))
+            .ok()?;
+
+        serde_json::from_str(&value?)
+            .inspect_err(|e| tracing::warn!("Invalid remote cache entry for {key:?}: {e}"))
+            .ok()
+    }
+}
+
+impl<K, V> GenericCache<K, V>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
+    V: Clone + DeserializeOwned + Send + Sync + Unpin + Serialize + 'static,
+{
+    async fn insert_remote(&self, key: &K, value: &V) {
+        let Some(remote) = &self.remote else {
+            return;
+        };
+
+        match serde_json::to_string(value) {
+            Ok(serialized) => {
+                let remote_key = remote.key(key);
+                let mut pipe = redis::pipe();
+                pipe.set_ex(&remote_key, serialized, self.ttl).ignore();
+                // The index outlives the entries it points to by at most a TTL
+                if let Some(tag) = self.tag {
+                    let tag_key = remote.tag_key(&tag(value));
+                    pipe.sadd(&tag_key, &remote_key)
+                        .ignore()
+                        .expire(&tag_key, self.ttl as i64)
+                        .ignore();
+                }
+
+                let _ = pipe
+                    .query_async::<()>(&mut remote.redis.clone())
+                    .await
+                    .inspect_err(|e| {
+                        tracing::warn!("Failed to write {key:?} to remote cache: {e}")
+                    });
+            }
+            Err(e) => tracing::warn!("Failed to serialize {key:?} for remote cache: {e}"),
         }
     }
 }
 
+impl<K, V> Debug for GenericCache<K, V>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
+    V: Clone + DeserializeOwned + Send + Sync + Unpin + Serialize + 'static,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GenericCache")
+            .field("max_capacity", &self.max_capacity())
+            .field("entry_count", &self.inner.entry_count())
+            .field(
+                "remote",
+                &self.remote.as_ref().map(|remote| remote.namespace.clone()),
+            )
+            .finish()
+    }
+}
+
 impl<K, V> LocalCacheExt<K, V> for GenericCache<K, V>
 where
     K: Hash + Eq + Clone + Debug + Sync + Send + 'static,
     V: Clone + DeserializeOwned + Send + Sync + Unpin + Serialize + 'static,
 {
+    /// Concurrent misses for the same key share a single call to `fa`, and
+    /// "not found" errors are remembered when a negative TTL is set.
+    async fn get_or_insert_with_fn<F, Fut>(&self, key: &K, fa: F) -> Result<V, PicaError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, PicaError>>,
+    {
+        if let Some(negative) = &self.negative {
+            if negative.contains_key(key) {
+                tracing::debug!("Negative cache hit for key: {:?}", key);
+                self.counters.record(true);
+                return Err(ApplicationError::not_found("Value not found", None));
+            }
+        }
+
+        let loaded = AtomicBool::new(false);
+        let result = self
+            .inner
+            .try_get_with_by_ref(key, async {
+                if let Some(remote) = &self.remote {
+                    if let Some(value) = self.get_remote(remote, key).await {
+                        return Ok(value);
+                    }
+                }
+
+                loaded.store(true, Ordering::Relaxed);
+                let value = fa().await?;
+                self.insert_remote(key, &value).await;
+
+                Ok(value)
+            })
+            .await
+            .map_err(|e: Arc<PicaError>| e.as_ref().clone());
+        self.counters.record(!loaded.load(Ordering::Relaxed));
+
+        if let (Err(e), Some(negative)) = (&result, &self.negative) {
+            if e.is_not_found() {
+                negative.insert(key.clone(), ()).await;
+            }
+        }
+
+        result
+    }
+
     async fn get(&self, key: &K) -> Result<Option<V>, PicaError> {
         let inner = self.inner.clone();
-        Ok(inner.get(key).await)
+        if let Some(value) = inner.get(key).await {
+            self.counters.record(true);
+            return Ok(Some(value));
+        }
+
+        // Remote failures only cost a trip to the source of truth
+        let Some(remote) = &self.remote else {
+            self.counters.record(false);
+            return Ok(None);
+        };
+        let value = self.get_remote(remote, key).await;
+        if let Some(value) = &value {
+            inner.insert(key.clone(), value.clone()).await;
+        }
+        self.counters.record(value.is_some());
+
+        Ok(value)
     }
 
     async fn insert(&self, key: &K, value: &V) -> Result<Unit, PicaError> {
         let inner = self.inner.clone();
         inner.insert(key.clone(), value.clone()).await;
+        if let Some(negative) = &self.negative {
+            negative.remove(key).await;
+        }
+
+        self.insert_remote(key, value).await;
+
         Ok(())
     }
 
     async fn remove(&self, key: &K) -> Result<Unit, PicaError> {
         let inner = self.inner.clone();
         inner.remove(key).await;
+        if let Some(negative) = &self.negative {
+            negative.remove(key).await;
+        }
+
+        if let Some(remote) = &self.remote {
+            let _ = remote
+                .redis
+                .clone()
+                .del::<_, ()>(remote.key(key))
+                .await
+                .inspect_err(|e| tracing::warn!("Failed to remove {key:?} from remote cache: {e}"));
+        }
+
         Ok(())
     }
 
@@ -143,14 +514,218 @@ where
 type ConnectionModelSchemaKey = (Arc<str>, Arc<str>);
 type ConnectionHeaderKey = (Arc<str>, HeaderValue);
 type ConnectionKey = Arc<str>;
+type ConnectionModelDefinitionDestinationKey = (Destination, Arc<str>);
 
 pub type EventAccessCache = GenericCache<HeaderValue, EventAccess>;
 pub type SecretCache = GenericCache<Connection, Secret>;
 pub type ConnectionOAuthDefinitionCache = GenericCache<Id, ConnectionOAuthDefinition>;
 pub type ConnectionModelSchemaCache = GenericCache<ConnectionModelSchemaKey, ConnectionModelSchema>;
 pub type ConnectionModelDefinitionDestinationCache =
-    GenericCache<Destination, ConnectionModelDefinition>;
+    GenericCache<ConnectionModelDefinitionDestinationKey, ConnectionModelDefinition>;
 pub type ConnectionModelDefinitionCacheIdKey = GenericCache<Id, ConnectionModelDefinition>;
 pub type ConnectionDefinitionCache = GenericCache<Id, ConnectionDefinition>;
 pub type ConnectionHeaderCache = GenericCache<ConnectionHeaderKey, Connection>;
 pub type ConnectionCache = GenericCache<ConnectionKey, Connection>;
+pub type JwtAssertionTokenCache = GenericCache<Arc<str>, JwtAssertionToken>;
+pub type CommonModelCache = GenericCache<Id, CommonModel>;
+pub type QuotaCache = GenericCache<Arc<str>, Vec<Quota>>;
+
+#[async_trait]
+impl<K, V> CacheAdmin for GenericCache<K, V>
+where
+    K: Hash + Eq + Clone + Debug + Sync + Send + CacheKey + 'static,
+    V: Clone + DeserializeOwned + Send + Sync + Unpin + Serialize + 'static,
+{
+    async fn stats(&self) -> CacheStats {
+        // Entry counts only account for pending writes once they are applied
+        self.inner.run_pending_tasks().await;
+        let negative_entry_count = match &self.negative {
+            Some(negative) => {
+                negative.run_pending_tasks().await;
+                negative.entry_count()
+            }
+            None => 0,
+        };
+
+        let hits = self.counters.hits.load(Ordering::Relaxed);
+        let misses = self.counters.misses.load(Ordering::Relaxed);
+
+        CacheStats {
+            entry_count: self.inner.entry_count(),
+            max_capacity: self.max_capacity(),
+            ttl_secs: self.ttl,
+            hits,
+            misses,
+            hit_ratio: (hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64),
+            negative_entry_count,
+            remote: self.remote.is_some(),
+        }
+    }
+
+    async fn evict(&self, key: &str) -> Result<Unit, PicaError> {
+        if let Some(remote) = &self.remote {
+            let _ = remote
+                .redis
+                .clone()
+                .del::<_, ()>(format!("{}:{key}", remote.namespace))
+                .await
+                .inspect_err(|e| tracing::warn!("Failed to remove {key} from remote cache: {e}"));
+        }
+
+        let matches = {
+            let key = key.to_string();
+            move |k: &K| k.cache_key() == key
+        };
+        if let Some(negative) = &self.negative {
+            let matches = matches.clone();
+            negative
+                .invalidate_entries_if(move |k, _| matches(k))
+                .map_err(|e| {
+                    InternalError::unknown(
+                        &format!("Failed to invalidate cache entries: {e}"),
+                        None,
+                    )
+                })?;
+        }
+        self.inner
+            .invalidate_entries_if(move |k, _| matches(k))
+            .map_err(|e| {
+                InternalError::unknown(&format!("Failed to invalidate cache entries: {e}"), None)
+            })?;
+
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<Unit, PicaError> {
+        self.clear_remote().await;
+        if let Some(negative) = &self.negative {
+            negative.invalidate_all();
+        }
+        self.inner.invalidate_all();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_pair_cache_keys_are_unambiguous() {
+        let a: (Arc<str>, Arc<str>) = ("a::b".into(), "c".into());
+        let b: (Arc<str>, Arc<str>) = ("a".into(), "::bc".into());
+
+        assert_ne!(a.cache_key(), b.cache_key());
+        assert_eq!(a.cache_key(), "4:a::bc");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_are_coalesced() {
+        let cache = GenericCache::<Arc<str>, String>::new(10, 60);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let key: Arc<str> = "key".into();
+
+        let lookups = (0..10).map(|_| {
+            cache.get_or_insert_with_fn(&key, || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok("value".to_string())
+            })
+        });
+
+        for value in futures::future::join_all(lookups).await {
+            assert_eq!(value.unwrap(), "value");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_not_found_is_cached_negatively() {
+        let cache = GenericCache::<Arc<str>, String>::new(10, 60).with_negative_ttl(60);
+        let calls = AtomicUsize::new(0);
+        let key: Arc<str> = "missing".into();
+
+        for _ in 0..3 {
+            let result = cache
+                .get_or_insert_with_fn(&key, || async {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    Err(ApplicationError::not_found("Value not found", None))
+                })
+                .await;
+
+            assert!(result.unwrap_err().is_not_found());
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        cache.insert(&key, &"value".to_string()).await.unwrap();
+        let value = cache
+            .get_or_insert_with_fn(&key, || async { unreachable!() })
+            .await;
+        assert_eq!(value.unwrap(), "value");
+    }
+
+    #[tokio::test]
+    async fn test_stats_and_eviction() {
+        let cache = GenericCache::<Arc<str>, String>::new(10, 60);
+        let key: Arc<str> = "key".into();
+
+        cache.insert(&key, &"value".to_string()).await.unwrap();
+        cache.get(&key).await.unwrap();
+        cache.get(&"other".into()).await.unwrap();
+
+        let stats = cache.stats().await;
+        assert_eq!(stats.entry_count, 1);
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert_eq!(stats.hit_ratio, Some(0.5));
+
+        cache.evict("key").await.unwrap();
+        assert_eq!(cache.get(&key).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_remove_tagged() {
+        let cache = GenericCache::<Arc<str>, String>::new(10, 60)
+            .with_tag(|value| value.split(':').next().unwrap_or_default().to_string());
+        let (first, second, other): (Arc<str>, Arc<str>, Arc<str>) =
+            ("first".into(), "second".into(), "other".into());
+
+        cache.insert(&first, &"stripe:1".to_string()).await.unwrap();
+        cache
+            .insert(&second, &"stripe:2".to_string())
+            .await
+            .unwrap();
+        cache
+            .insert(&other, &"shopify:1".to_string())
+            .await
+            .unwrap();
+
+        cache.remove_tagged("stripe").await.unwrap();
+
+        assert_eq!(cache.get(&first).await.unwrap(), None);
+        assert_eq!(cache.get(&second).await.unwrap(), None);
+        assert_eq!(
+            cache.get(&other).await.unwrap(),
+            Some("shopify:1".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_tagged_requires_tags() {
+        let cache = GenericCache::<Arc<str>, String>::new(10, 60);
+
+        assert!(cache.remove_tagged("stripe").await.is_err());
+    }
+
+    #[test]
+    fn test_header_value_cache_key() {
+        let header = HeaderValue::from_static("sk_live_1");
+
+        assert_eq!(
+            header.cache_key(),
+            "992621cb15795f3e17e7e637aad64ef2cacf8471091104c0153e98355c520261"
+        );
+        assert!(!header.cache_key().contains("sk_live_1"));
+    }
+}
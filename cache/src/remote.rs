@@ -2,6 +2,7 @@ use osentities::{cache::CacheConfig, InternalError, PicaError};
 use redis::aio::{ConnectionManager, ConnectionManagerConfig};
 use std::time::Duration;
 
+#[derive(Clone)]
 pub struct RedisCache {
     pub inner: ConnectionManager,
 }
@@ -0,0 +1,292 @@
+use crate::constant::*;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use http::{HeaderMap, HeaderValue, Method};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+fn percent_encode(data: &str) -> String {
+    percent_encoding::utf8_percent_encode(data, EXCLUDE).to_string()
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).context(HMAC_LENGTH_ERROR)?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AwsSigner {
+    pub service: String,
+    pub region: String,
+    pub credentials: AwsCredentials,
+}
+
+impl AwsSigner {
+    /// Signs the request in place by adding the `x-amz-*` headers and the
+    /// `Authorization` header. The signature covers the final url (including
+    /// the query string), the signed headers and the hash of the payload.
+    pub fn sign(
+        &self,
+        method: &Method,
+        uri: &Url,
+        headers: &mut HeaderMap,
+        payload: &[u8],
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let payload_hash = hex::encode(Sha256::digest(payload));
+
+        headers.insert(
+            AWS_DATE_HEADER,
+            HeaderValue::from_str(&now.format("%Y%m%dT%H%M%SZ").to_string())?,
+        );
+        headers.insert(
+            AWS_CONTENT_SHA256_HEADER,
+            HeaderValue::from_str(&payload_hash)?,
+        );
+        if let Some(session_token) = &self.credentials.session_token {
+            headers.insert(
+                AWS_SECURITY_TOKEN_HEADER,
+                HeaderValue::from_str(session_token)?,
+            );
+        }
+
+        let authorization = self.authorization(method, uri, headers, &payload_hash, now)?;
+
+        headers.insert(
+            http::header::AUTHORIZATION,
+            HeaderValue::from_str(&authorization)?,
+        );
+
+        Ok(())
+    }
+
+    pub fn authorization(
+        &self,
+        method: &Method,
+        uri: &Url,
+        headers: &HeaderMap,
+        payload_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<String> {
+        let date = now.format("%Y%m%d").to_string();
+        let date_time = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let scope = format!(
+            "{}/{}/{}/{}",
+            date, self.region, self.service, AWS_SIGV4_TERMINATOR
+        );
+
+        let (canonical_headers, signed_headers) = canonical_headers(uri, headers)?;
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method.as_str(),
+            canonical_uri(uri, &self.service),
+            canonical_query(uri),
+            canonical_headers,
+            signed_headers,
+            payload_hash
+        );
+
+        let string_to_sign = format!(
+            "{}\n{}\n{}\n{}",
+            AWS_SIGV4_ALGORITHM,
+            date_time,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let signing_key = [
+            self.region.as_str(),
+            self.service.as_str(),
+            AWS_SIGV4_TERMINATOR,
+        ]
+        .iter()
+        .try_fold(
+            hmac_sha256(
+                format!("AWS4{}", self.credentials.secret_access_key).as_bytes(),
+                date.as_bytes(),
+            )?,
+            |key, part| hmac_sha256(&key, part.as_bytes()),
+        )?;
+
+        let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes())?);
+
+        Ok(format!(
+            "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+            AWS_SIGV4_ALGORITHM, self.credentials.access_key_id, scope, signed_headers, signature
+        ))
+    }
+}
+
+/// S3 expects the path as it is sent, every other service expects each segment
+/// to be encoded a second time.
+fn canonical_uri(uri: &Url, service: &str) -> String {
+    let path = match uri.path() {
+        "" => "/",
+        path => path,
+    };
+
+    if service == "s3" {
+        path.to_string()
+    } else {
+        path.split('/')
+            .map(percent_encode)
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+fn canonical_query(uri: &Url) -> String {
+    let mut params = uri
+        .query_pairs()
+        .map(|(k, v)| (percent_encode(&k), percent_encode(&v)))
+        .collect::<Vec<_>>();
+    params.sort();
+
+    params
+        .into_iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Only the host, the content headers and the `x-amz-*` headers are signed, so
+/// that proxies adding or rewriting other headers don't invalidate the signature.
+fn canonical_headers(uri: &Url, headers: &HeaderMap) -> Result<(String, String)> {
+    let host = uri
+        .host_str()
+        .context("AWS signed requests require a host")?;
+    let host = match uri.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    };
+
+    let mut canonical: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    canonical.insert(http::header::HOST.to_string(), vec![host]);
+
+    for (name, value) in headers.iter() {
+        let name = name.as_str();
+        let is_signed = name == http::header::CONTENT_TYPE
+            || name == "content-md5"
+            || name.starts_with("x-amz-");
+
+        if is_signed {
+            let value = value
+                .to_str()
+                .context("AWS signed headers must be valid ASCII")?
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            canonical.entry(name.to_string()).or_default().push(value);
+        }
+    }
+
+    let canonical_headers = canonical
+        .iter()
+        .map(|(name, values)| format!("{}:{}\n", name, values.join(",")))
+        .collect::<String>();
+    let signed_headers = canonical.keys().cloned().collect::<Vec<_>>().join(";");
+
+    Ok((canonical_headers, signed_headers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn signer(session_token: Option<String>) -> AwsSigner {
+        AwsSigner {
+            service: "iam".to_string(),
+            region: "us-east-1".to_string(),
+            credentials: AwsCredentials {
+                access_key_id: "AKIDEXAMPLE".to_string(),
+                secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+                session_token,
+            },
+        }
+    }
+
+    #[test]
+    fn test_authorization_matches_aws_reference_example() {
+        let now = Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap();
+        let uri =
+            Url::parse("https://iam.amazonaws.com/?Version=2010-05-08&Action=ListUsers").unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded; charset=utf-8"),
+        );
+        headers.insert(
+            AWS_DATE_HEADER,
+            HeaderValue::from_static("20150830T123600Z"),
+        );
+
+        let authorization = signer(None)
+            .authorization(
+                &Method::GET,
+                &uri,
+                &headers,
+                &hex::encode(Sha256::digest(b"")),
+                now,
+            )
+            .unwrap();
+
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, SignedHeaders=content-type;host;x-amz-date, Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        );
+    }
+
+    #[test]
+    fn test_sign_adds_amz_headers() {
+        let now = Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap();
+        let uri = Url::parse("https://iam.amazonaws.com/").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-custom", HeaderValue::from_static("unsigned"));
+
+        signer(Some("session-token".to_string()))
+            .sign(&Method::POST, &uri, &mut headers, b"{}", now)
+            .unwrap();
+
+        assert_eq!(headers.get(AWS_DATE_HEADER).unwrap(), "20150830T123600Z");
+        assert_eq!(
+            headers.get(AWS_CONTENT_SHA256_HEADER).unwrap(),
+            &hex::encode(Sha256::digest(b"{}"))
+        );
+        assert_eq!(
+            headers.get(AWS_SECURITY_TOKEN_HEADER).unwrap(),
+            "session-token"
+        );
+
+        let authorization = headers
+            .get(http::header::AUTHORIZATION)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(authorization
+            .contains("SignedHeaders=host;x-amz-content-sha256;x-amz-date;x-amz-security-token,"));
+    }
+
+    #[test]
+    fn test_canonical_uri_double_encodes_except_for_s3() {
+        let uri = Url::parse("https://example.com/documents and settings/").unwrap();
+
+        assert_eq!(
+            canonical_uri(&uri, "execute-api"),
+            "/documents%2520and%2520settings/"
+        );
+        assert_eq!(canonical_uri(&uri, "s3"), "/documents%20and%20settings/");
+    }
+}
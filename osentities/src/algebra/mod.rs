@@ -1,21 +1,31 @@
+mod aws;
 mod crypto;
 mod hash;
 mod json;
+mod jwt_assertion;
 mod oauth;
+mod oauth_refresh;
 mod pipeline;
 mod secret;
+mod signature;
 mod store;
 mod string;
+mod task_dependency;
 mod template;
 mod timed;
 
+pub use aws::*;
 pub use crypto::*;
 pub use hash::*;
 pub use json::*;
+pub use jwt_assertion::*;
 pub use oauth::*;
+pub use oauth_refresh::*;
 pub use pipeline::*;
 pub use secret::*;
+pub use signature::*;
 pub use store::*;
 pub use string::*;
+pub use task_dependency::*;
 pub use template::*;
 pub use timed::*;
@@ -0,0 +1,1170 @@
+use super::{DefaultTemplate, MongoStore, SecretExt, TemplateExt};
+use crate::{
+    api_model_config::{ApiModelConfig, ContentType, Function},
+    connection_oauth_definition::{
+        Computation, ConnectionOAuthDefinition, OAuthGrantType, OAuthResponse,
+    },
+    constant::*,
+    oauth_secret::OAuthSecret,
+    ApplicationError, Connection, InternalError, OAuth, PicaError, Secret,
+};
+use bson::doc;
+use chrono::{Duration, Utc};
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use reqwest::{Client, Request};
+use serde_json::{json, to_string_pretty, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    sync::Arc,
+};
+use tracing::error;
+
+/// Builds the request sent to an OAuth token endpoint. Headers and query
+/// params from `config` are rendered against the computation, the body is the
+/// computation's body rendered against the payload.
+pub fn token_request(
+    http_client: &Client,
+    config: &ApiModelConfig,
+    computation: Option<&Computation>,
+    payload: &Value,
+    template: &impl TemplateExt,
+) -> Result<Request, PicaError> {
+    let headers = token_headers(config, computation, template)?;
+    let query = token_query(config, computation, template)?;
+    let body = token_body(payload, computation, template)?;
+
+    let request = http_client.post(config.uri()).headers(headers);
+
+    let request = match config.content {
+        Some(ContentType::Json) => request.json(&body).query(&query),
+        Some(ContentType::Form) => request.form(&body).query(&query),
+        _ => request.query(&query),
+    };
+
+    request.build().map_err(|e| {
+        error!("Failed to build static request: {}", e);
+        InternalError::unknown(&e.to_string(), None)
+    })
+}
+
+fn token_query(
+    config: &ApiModelConfig,
+    computation: Option<&Computation>,
+    template: &impl TemplateExt,
+) -> Result<Option<Value>, PicaError> {
+    let query_params = config.query_params.as_ref().map(|query_params| {
+        let mut map = HashMap::new();
+        for (key, value) in query_params {
+            let key = key.to_string();
+            let value = value.as_str();
+
+            map.insert(key, value.to_string());
+        }
+        map
+    });
+
+    match query_params {
+        Some(query_params) => {
+            let payload = computation.and_then(|computation| computation.clone().query_params);
+
+            let query_params_str = to_string_pretty(&query_params).map_err(|e| {
+                error!("Failed to serialize query params: {}", e);
+                InternalError::serialize_error(&e.to_string(), None)
+            })?;
+
+            let query_params = template.render(&query_params_str, payload.as_ref())?;
+
+            let query_params: BTreeMap<String, String> = serde_json::from_str(&query_params)
+                .map_err(|e| {
+                    error!("Failed to deserialize query params: {}", e);
+                    InternalError::deserialize_error(&e.to_string(), None)
+                })?;
+
+            Ok(Some(serde_json::to_value(query_params).map_err(|e| {
+                error!("Failed to serialize query params: {}", e);
+                InternalError::serialize_error(&e.to_string(), None)
+            })?))
+        }
+        None => Ok(None),
+    }
+}
+
+fn token_body(
+    payload: &Value,
+    computation: Option<&Computation>,
+    template: &impl TemplateExt,
+) -> Result<Option<Value>, PicaError> {
+    let body = computation.and_then(|computation| computation.clone().body);
+
+    match body {
+        Some(body) => {
+            let body_str = to_string_pretty(&body).map_err(|e| {
+                error!("Failed to serialize body: {}", e);
+                InternalError::serialize_error(&e.to_string(), None)
+            })?;
+
+            let body = template.render(&body_str, Some(payload))?;
+
+            Ok(Some(serde_json::from_str(&body).map_err(|e| {
+                error!("Failed to deserialize body: {}", e);
+                InternalError::deserialize_error(&e.to_string(), None)
+            })?))
+        }
+        None => Ok(None),
+    }
+}
+
+fn token_headers(
+    config: &ApiModelConfig,
+    computation: Option<&Computation>,
+    template: &impl TemplateExt,
+) -> Result<HeaderMap, PicaError> {
+    let headers = config.headers.as_ref().and_then(|headers| {
+        let mut map = HashMap::new();
+        for (key, value) in headers {
+            let key = key.to_string();
+            let value = value.to_str().ok()?;
+
+            map.insert(key, value.to_string());
+        }
+        Some(map)
+    });
+
+    match headers {
+        Some(headers) => {
+            let payload = computation.and_then(|computation| computation.clone().headers);
+
+            let headers_str = to_string_pretty(&headers).map_err(|e| {
+                error!("Failed to serialize headers: {}", e);
+                InternalError::serialize_error(&e.to_string(), None)
+            })?;
+
+            let headers = template.render(&headers_str, payload.as_ref())?;
+
+            let headers: BTreeMap<String, String> =
+                serde_json::from_str(&headers).map_err(|e| {
+                    error!("Failed to deserialize headers: {}", e);
+                    InternalError::deserialize_error(&e.to_string(), None)
+                })?;
+
+            headers
+                .iter()
+                .try_fold(HeaderMap::new(), |mut header_map, (key, value)| {
+                    let key = HeaderName::from_str(key).map_err(|e| {
+                        error!("Failed to parse header name: {}", e);
+                        InternalError::invalid_argument(&e.to_string(), None)
+                    })?;
+
+                    let value = HeaderValue::from_str(value).map_err(|e| {
+                        error!("Failed to parse header value: {}", e);
+                        InternalError::invalid_argument(&e.to_string(), None)
+                    })?;
+
+                    header_map.insert(key, value);
+
+                    Ok(header_map)
+                })
+        }
+        None => Ok(HeaderMap::new()),
+    }
+}
+
+/// Unix timestamp (in seconds) at which a token issued now should be refreshed
+pub fn oauth_expires_at(expires_in: i32) -> i64 {
+    Utc::now()
+        .checked_add_signed(Duration::seconds(expires_in as i64))
+        .unwrap_or_else(Utc::now)
+        .checked_sub_signed(Duration::seconds(OAUTH_EXPIRY_BUFFER_SECS))
+        .unwrap_or_else(Utc::now)
+        .timestamp()
+}
+
+/// Payload the client credentials computations are rendered against. It has
+/// the same shape as the one used for the authorization code init request.
+pub fn client_credentials_payload(client_id: &str, client_secret: &str, metadata: Value) -> Value {
+    json!({
+        "clientId": client_id,
+        "clientSecret": client_secret,
+        "metadata": metadata,
+    })
+}
+
+/// Exchanges the client credentials in `payload` for a token by running the
+/// definition's init request. Returns the decoded response along with the raw
+/// one, which is kept as the secret's metadata.
+pub async fn client_credentials_grant(
+    http_client: &Client,
+    definition: &ConnectionOAuthDefinition,
+    payload: &Value,
+    template: &impl TemplateExt,
+) -> Result<(OAuthResponse, Value), PicaError> {
+    run_client_credentials_grant(http_client, definition, payload, template)
+        .await
+        .map_err(|e| e.error)
+}
+
+async fn run_client_credentials_grant(
+    http_client: &Client,
+    definition: &ConnectionOAuthDefinition,
+    payload: &Value,
+    template: &impl TemplateExt,
+) -> Result<(OAuthResponse, Value), TokenError> {
+    let definition = if definition.is_full_template_enabled {
+        template.render_as(definition, Some(payload))?
+    } else {
+        definition.clone()
+    };
+
+    let computation = definition
+        .compute
+        .init
+        .computation
+        .as_ref()
+        .map(|computation| computation.compute::<Computation>(payload))
+        .transpose()?;
+
+    let request = token_request(
+        http_client,
+        &definition.configuration.init,
+        computation.as_ref(),
+        payload,
+        template,
+    )?;
+
+    exchange_token(
+        http_client,
+        request,
+        &definition.compute.init.response,
+        "oauth_client_credentials",
+    )
+    .await
+}
+
+/// Revokes the tokens in `secret` at the definition's revocation endpoint.
+/// Definitions without one are skipped. The revoke computation receives the
+/// same payload as the refresh one.
+pub async fn revoke_token(
+    http_client: &Client,
+    definition: &ConnectionOAuthDefinition,
+    secret: &OAuthSecret,
+    template: &impl TemplateExt,
+) -> Result<(), PicaError> {
+    let payload = refresh_payload(secret);
+
+    let definition = if definition.is_full_template_enabled {
+        template.render_as(definition, Some(&payload))?
+    } else {
+        definition.clone()
+    };
+
+    let Some(config) = &definition.configuration.revoke else {
+        return Ok(());
+    };
+
+    let computation = definition
+        .compute
+        .revoke
+        .as_ref()
+        .map(|computation| computation.compute::<Computation>(&payload))
+        .transpose()?;
+
+    let request = token_request(
+        http_client,
+        config,
+        computation.as_ref(),
+        &payload,
+        template,
+    )?;
+
+    let response = http_client
+        .execute(request)
+        .await
+        .map_err(|e| ApplicationError::failed_dependency(&e.to_string(), Some("oauth_revoke")))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+
+        return Err(ApplicationError::failed_dependency(
+            &format!("Revoke request failed with status {status}: {body}"),
+            Some("oauth_revoke"),
+        ));
+    }
+
+    Ok(())
+}
+
+/// A failed token request. It is `rejected` when the provider refused the
+/// grant, e.g. with `invalid_grant` for a revoked refresh token, rather than
+/// being unreachable or failing on its side.
+struct TokenError {
+    error: PicaError,
+    rejected: bool,
+}
+
+impl From<PicaError> for TokenError {
+    fn from(error: PicaError) -> Self {
+        Self {
+            error,
+            rejected: false,
+        }
+    }
+}
+
+/// Timeouts and throttling are worth retrying like server errors, other client
+/// errors mean the grant itself was refused
+fn is_rejection(status: StatusCode) -> bool {
+    status.is_client_error()
+        && !matches!(
+            status,
+            StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS
+        )
+}
+
+async fn exchange_token(
+    http_client: &Client,
+    request: Request,
+    response_compute: &Function,
+    dependency: &str,
+) -> Result<(OAuthResponse, Value), TokenError> {
+    let response = http_client
+        .execute(request)
+        .await
+        .map_err(|e| ApplicationError::failed_dependency(&e.to_string(), Some(dependency)))?;
+
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| ApplicationError::failed_dependency(&e.to_string(), Some(dependency)))?;
+
+    if !status.is_success() {
+        return Err(TokenError {
+            error: ApplicationError::failed_dependency(
+                &format!("Token request failed with status {status}: {body}"),
+                Some(dependency),
+            ),
+            rejected: is_rejection(status),
+        });
+    }
+
+    let response = serde_json::from_str::<Value>(&body)
+        .map_err(|e| InternalError::deserialize_error(&e.to_string(), None))?;
+    let decoded: OAuthResponse = response_compute.compute(&response)?;
+
+    Ok((decoded, response))
+}
+
+/// Refreshes the tokens of `OAuth::Enabled` connections by running the
+/// definition's refresh computation, storing the new `OAuthSecret` and
+/// pointing the connection to it.
+#[derive(Clone)]
+pub struct OAuthRefresher {
+    connections: MongoStore<Connection>,
+    oauth_definitions: MongoStore<ConnectionOAuthDefinition>,
+    secrets_client: Arc<dyn SecretExt + Sync + Send>,
+    http_client: Client,
+    template: DefaultTemplate,
+}
+
+impl OAuthRefresher {
+    pub fn new(
+        connections: MongoStore<Connection>,
+        oauth_definitions: MongoStore<ConnectionOAuthDefinition>,
+        secrets_client: Arc<dyn SecretExt + Sync + Send>,
+        http_client: Client,
+    ) -> Self {
+        Self {
+            connections,
+            oauth_definitions,
+            secrets_client,
+            http_client,
+            template: DefaultTemplate::default(),
+        }
+    }
+
+    /// Connections whose token expires within `window_secs` from now. Errored
+    /// connections, whose provider refused to refresh them, are skipped so
+    /// that a revoked refresh token isn't retried on every sweep. They are
+    /// refreshed on demand instead. Connections attempted less than
+    /// `retry_after_secs` ago are skipped too, or those that keep failing
+    /// would fill every batch ahead of the healthy ones.
+    pub async fn expiring(
+        &self,
+        window_secs: i64,
+        retry_after_secs: i64,
+        limit: u64,
+    ) -> Result<Vec<Connection>, PicaError> {
+        let now = Utc::now().timestamp();
+
+        self.connections
+            .get_many(
+                Some(doc! {
+                    OAUTH_EXPIRES_AT_PATH: {
+                        "$lte": now + window_secs,
+                    },
+                    // Also matches connections that were never attempted
+                    OAUTH_REFRESH_ATTEMPTED_AT_PATH: {
+                        "$not": { "$gt": now - retry_after_secs },
+                    },
+                    "hasError": { "$ne": true },
+                    "deleted": false,
+                }),
+                None,
+                Some(doc! { OAUTH_EXPIRES_AT_PATH: 1 }),
+                Some(limit),
+                None,
+            )
+            .await
+    }
+
+    /// Called when an upstream rejected `rejected_access_token`. If the stored
+    /// connection already points to a different token (e.g. it was refreshed by
+    /// another replica), that one is returned instead of refreshing again.
+    pub async fn refresh_rejected(
+        &self,
+        connection: &Connection,
+        rejected_access_token: &str,
+    ) -> Result<(Connection, Secret), PicaError> {
+        let current = self
+            .connections
+            .get_one_by_id(&connection.id.to_string())
+            .await?
+            .ok_or_else(|| ApplicationError::not_found("Connection", None))?;
+
+        let secret = self
+            .secrets_client
+            .get(&current.secrets_service_id, &current.ownership.id)
+            .await?;
+
+        match secret.decode::<OAuthSecret>() {
+            Ok(oauth_secret) if oauth_secret.access_token != rejected_access_token => {
+                Ok((current, secret))
+            }
+            _ => self.refresh(&current).await,
+        }
+    }
+
+    /// Refreshes the connection's token and returns the updated connection
+    /// together with the newly stored secret, or with the stored one if
+    /// another refresh of the same token won. The previous secret is deleted.
+    /// Callers are responsible for invalidating cached copies of the
+    /// connection, whose secret id changed. The connection is marked as
+    /// errored when the provider refuses the refresh, other failures are left
+    /// to be retried by the next sweep.
+    pub async fn refresh(
+        &self,
+        connection: &Connection,
+    ) -> Result<(Connection, Secret), PicaError> {
+        // Recorded up front so that a refresh that never completes is backed
+        // off as well
+        self.connections
+            .collection
+            .update_one(
+                doc! { "_id": connection.id.to_string() },
+                doc! {
+                    "$set": { OAUTH_REFRESH_ATTEMPTED_AT_PATH: Utc::now().timestamp() }
+                },
+            )
+            .await?;
+
+        match self.try_refresh(connection).await {
+            Ok(refreshed) => Ok(refreshed),
+            Err(TokenError { error, rejected }) => {
+                error!(
+                    "Failed to refresh oauth token for connection {}: {error}",
+                    connection.id
+                );
+
+                if !rejected {
+                    return Err(error);
+                }
+
+                // A refresh token that was rotated meanwhile is refused too,
+                // which says nothing about the current one
+                self.connections
+                    .collection
+                    .update_one(
+                        doc! {
+                            "_id": connection.id.to_string(),
+                            "secretsServiceId": &connection.secrets_service_id,
+                        },
+                        doc! {
+                            "$set": {
+                                "hasError": true,
+                                "error": error.to_string(),
+                            }
+                        },
+                    )
+                    .await?;
+
+                Err(error)
+            }
+        }
+    }
+
+    async fn try_refresh(
+        &self,
+        connection: &Connection,
+    ) -> Result<(Connection, Secret), TokenError> {
+        let connection_oauth_definition_id = match &connection.oauth {
+            Some(OAuth::Enabled {
+                connection_oauth_definition_id,
+                ..
+            }) => connection_oauth_definition_id,
+            _ => {
+                return Err(InternalError::invalid_argument(
+                    "Connection does not have OAuth enabled",
+                    None,
+                )
+                .into())
+            }
+        };
+
+        let definition = self
+            .oauth_definitions
+            .get_one_by_id(&connection_oauth_definition_id.to_string())
+            .await?
+            .ok_or_else(|| ApplicationError::not_found("Connection OAuth definition", None))?;
+
+        let oauth_secret: OAuthSecret = self
+            .secrets_client
+            .get(&connection.secrets_service_id, &connection.ownership.id)
+            .await?
+            .decode()?;
+
+        let (mut decoded, response) = match definition.grant_type {
+            // There is no refresh token to exchange, the grant is simply run again
+            OAuthGrantType::ClientCredentials => {
+                let payload = client_credentials_payload(
+                    &oauth_secret.client_id,
+                    &oauth_secret.client_secret,
+                    oauth_secret.request_payload.clone().unwrap_or(Value::Null),
+                );
+
+                run_client_credentials_grant(
+                    &self.http_client,
+                    &definition,
+                    &payload,
+                    &self.template,
+                )
+                .await?
+            }
+            OAuthGrantType::AuthorizationCode => {
+                let payload = refresh_payload(&oauth_secret);
+
+                let definition = if definition.is_full_template_enabled {
+                    self.template.render_as(&definition, Some(&payload))?
+                } else {
+                    definition
+                };
+
+                let computation = definition
+                    .compute
+                    .refresh
+                    .computation
+                    .as_ref()
+                    .map(|computation| computation.compute::<Computation>(&payload))
+                    .transpose()?;
+
+                let request = token_request(
+                    &self.http_client,
+                    &definition.configuration.refresh,
+                    computation.as_ref(),
+                    &payload,
+                    &self.template,
+                )?;
+
+                exchange_token(
+                    &self.http_client,
+                    request,
+                    &definition.compute.refresh.response,
+                    "oauth_refresh",
+                )
+                .await?
+            }
+        };
+
+        // Providers that don't rotate refresh tokens omit them from the response
+        decoded.refresh_token = decoded.refresh_token.or(oauth_secret.refresh_token.clone());
+
+        let oauth_secret = oauth_secret.from_refresh(decoded, None, None, response);
+
+        let secret = self
+            .secrets_client
+            .create(&oauth_secret.as_json(), &connection.ownership.id)
+            .await?;
+
+        let oauth = OAuth::Enabled {
+            connection_oauth_definition_id: *connection_oauth_definition_id,
+            expires_in: Some(oauth_secret.expires_in),
+            expires_at: Some(oauth_expires_at(oauth_secret.expires_in)),
+        };
+
+        // Only the first of concurrent refreshes (the watchdog sweep, a
+        // rejected request, other replicas) gets to point the connection to its
+        // secret, as providers that rotate refresh tokens only honour one of them
+        let swapped = self
+            .connections
+            .collection
+            .update_one(
+                doc! {
+                    "_id": connection.id.to_string(),
+                    "secretsServiceId": &connection.secrets_service_id,
+                },
+                doc! {
+                    "$set": {
+                        "secretsServiceId": secret.id(),
+                        "oauth": bson::to_bson(&oauth).map_err(|e| {
+                            InternalError::serialize_error(&e.to_string(), None)
+                        })?,
+                        "hasError": false,
+                        "error": null,
+                        OAUTH_REFRESH_ATTEMPTED_AT_PATH: null,
+                        "updatedAt": Utc::now().timestamp_millis(),
+                    }
+                },
+            )
+            .await
+            .map_err(PicaError::from)?
+            .matched_count
+            > 0;
+
+        let stale_secret_id = if swapped {
+            connection.secrets_service_id.clone()
+        } else {
+            secret.id()
+        };
+        if let Err(e) = self
+            .secrets_client
+            .delete(&stale_secret_id, &connection.ownership.id)
+            .await
+        {
+            error!(
+                "Failed to delete stale secret of connection {}: {e}",
+                connection.id
+            );
+        }
+
+        if !swapped {
+            let current = self
+                .connections
+                .get_one_by_id(&connection.id.to_string())
+                .await?
+                .ok_or_else(|| ApplicationError::not_found("Connection", None))?;
+            let secret = self
+                .secrets_client
+                .get(&current.secrets_service_id, &current.ownership.id)
+                .await?;
+
+            return Ok((current, secret));
+        }
+
+        let mut connection = connection.clone();
+        connection.secrets_service_id = secret.id();
+        connection.oauth = Some(oauth);
+        connection.has_error = false;
+        connection.error = None;
+
+        let decrypted = serde_json::to_string(&oauth_secret.as_json())
+            .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?;
+
+        Ok((
+            connection,
+            Secret::new(
+                decrypted,
+                secret.version(),
+                secret.buildable_id(),
+                Some(secret.created_at()),
+            ),
+        ))
+    }
+}
+
+/// The refresh computation receives the stored secret, along with the
+/// camelCase fields the init computation gets.
+fn refresh_payload(secret: &OAuthSecret) -> Value {
+    let mut payload = secret.as_json();
+
+    if let Some(map) = payload.as_object_mut() {
+        map.insert("clientId".to_string(), secret.client_id.clone().into());
+        map.insert(
+            "clientSecret".to_string(),
+            secret.client_secret.clone().into(),
+        );
+        map.insert(
+            "refreshToken".to_string(),
+            secret.refresh_token.clone().into(),
+        );
+        map.insert("metadata".to_string(), secret.metadata.clone());
+    }
+
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        environment::Environment,
+        id::{prefix::IdPrefix, Id},
+        ownership::Ownership,
+        record_metadata::RecordMetadata,
+        settings::Settings,
+        ConnectionType, SecretVersion, Store, Throughput,
+    };
+    use async_trait::async_trait;
+    use mockito::{Matcher, Server};
+    use mongodb::Database;
+    use serde_json::json;
+    use std::sync::{Mutex, OnceLock};
+    use testcontainers_modules::{
+        mongo::Mongo,
+        testcontainers::{clients::Cli as Docker, Container},
+    };
+    use uuid::Uuid;
+
+    static DOCKER: OnceLock<Docker> = OnceLock::new();
+    static MONGO: OnceLock<Container<'static, Mongo>> = OnceLock::new();
+
+    const OWNER: &str = "owner";
+
+    /// Keeps the secrets it creates in memory
+    #[derive(Default)]
+    struct MockSecretsClient {
+        secrets: Mutex<HashMap<String, Secret>>,
+    }
+
+    #[async_trait]
+    impl SecretExt for MockSecretsClient {
+        async fn get(&self, id: &str, _buildable_id: &str) -> Result<Secret, PicaError> {
+            self.secrets
+                .lock()
+                .unwrap()
+                .get(id)
+                .cloned()
+                .ok_or_else(|| ApplicationError::not_found("Secret", None))
+        }
+
+        async fn create(&self, secret: &Value, buildable_id: &str) -> Result<Secret, PicaError> {
+            let secret = Secret::new(
+                secret.to_string(),
+                Some(SecretVersion::V2),
+                buildable_id.to_string(),
+                None,
+            );
+            self.secrets
+                .lock()
+                .unwrap()
+                .insert(secret.id(), secret.clone());
+
+            Ok(secret)
+        }
+
+        async fn delete(&self, id: &str, _buildable_id: &str) -> Result<(), PicaError> {
+            self.secrets.lock().unwrap().remove(id);
+            Ok(())
+        }
+    }
+
+    /// A fresh database on the shared Mongo container
+    async fn database() -> Database {
+        let docker = DOCKER.get_or_init(Default::default);
+        let mongo = MONGO.get_or_init(|| docker.run(Mongo));
+        let url = format!(
+            "mongodb://127.0.0.1:{}/?directConnection=true",
+            mongo.get_host_port_ipv4(27017)
+        );
+
+        mongodb::Client::with_uri_str(url)
+            .await
+            .unwrap()
+            .database(&Uuid::new_v4().to_string())
+    }
+
+    /// A connection granted with client credentials by a provider at
+    /// `token_url`, whose secret is stored in `secrets_client`
+    async fn seed_client_credentials_connection(
+        db: &Database,
+        secrets_client: &MockSecretsClient,
+        token_url: String,
+    ) -> Connection {
+        let token_config = json!({
+            "baseUrl": token_url,
+            "path": "/oauth/token",
+            "authMethod": { "type": "None" },
+            "content": "form",
+            "schemas": {},
+            "samples": {},
+            "responses": []
+        });
+        let definition: ConnectionOAuthDefinition = serde_json::from_value(json!({
+            "_id": Id::now(IdPrefix::ConnectionOAuthDefinition),
+            "connectionPlatform": "client-credentials",
+            "grantType": "clientCredentials",
+            "configuration": { "init": token_config, "refresh": token_config },
+            "compute": {
+                "init": {
+                    "computation": {
+                        "entry": "compute",
+                        "function": "function compute(payload) { return { body: { grant_type: 'client_credentials', client_id: payload.clientId, tenant: payload.metadata.tenant } }; }",
+                        "language": "javascript",
+                    },
+                    "response": {
+                        "entry": "compute",
+                        "function": "function compute(response) { return { accessToken: response.access_token, expiresIn: response.expires_in }; }",
+                        "language": "javascript",
+                    },
+                },
+                "refresh": {
+                    "computation": null,
+                    "response": {
+                        "entry": "compute",
+                        "function": "function compute(response) { return response; }",
+                        "language": "javascript",
+                    },
+                },
+            },
+            "frontend": {
+                "platformRedirectUri": "",
+                "scopes": "",
+                "iosRedirectUri": "",
+            },
+        }))
+        .unwrap();
+        MongoStore::<ConnectionOAuthDefinition>::new(db, &Store::ConnectionOAuthDefinitions)
+            .await
+            .unwrap()
+            .create_one(&definition)
+            .await
+            .unwrap();
+
+        let secret = secrets_client
+            .create(
+                &OAuthSecret {
+                    access_token: "expired-token".to_string(),
+                    refresh_token: None,
+                    request_payload: Some(json!({ "tenant": "acme" })),
+                    ..oauth_secret()
+                }
+                .as_json(),
+                OWNER,
+            )
+            .await
+            .unwrap();
+
+        let connection = Connection {
+            id: Id::now(IdPrefix::Connection),
+            platform_version: "v1".to_string(),
+            connection_definition_id: Id::now(IdPrefix::ConnectionDefinition),
+            r#type: ConnectionType::Api {},
+            key: "test::client-credentials::default".into(),
+            group: "group".to_string(),
+            name: None,
+            environment: Environment::Test,
+            platform: "client-credentials".into(),
+            secrets_service_id: secret.id(),
+            event_access_id: Id::now(IdPrefix::EventAccess),
+            access_key: "access-key".to_string(),
+            identity: None,
+            identity_type: None,
+            settings: Settings::default(),
+            throughput: Throughput {
+                key: "throughput".to_string(),
+                limit: 100,
+            },
+            ownership: Ownership {
+                id: OWNER.into(),
+                ..Default::default()
+            },
+            oauth: Some(OAuth::Enabled {
+                connection_oauth_definition_id: definition.id,
+                expires_in: Some(3600),
+                expires_at: Some(Utc::now().timestamp()),
+            }),
+            has_error: false,
+            error: None,
+            record_metadata: RecordMetadata::default(),
+        };
+        MongoStore::<Connection>::new(db, &Store::Connections)
+            .await
+            .unwrap()
+            .create_one(&connection)
+            .await
+            .unwrap();
+
+        connection
+    }
+
+    async fn refresher(db: &Database, secrets_client: Arc<MockSecretsClient>) -> OAuthRefresher {
+        OAuthRefresher::new(
+            MongoStore::new(db, &Store::Connections).await.unwrap(),
+            MongoStore::new(db, &Store::ConnectionOAuthDefinitions)
+                .await
+                .unwrap(),
+            secrets_client,
+            Client::new(),
+        )
+    }
+
+    fn oauth_secret() -> OAuthSecret {
+        OAuthSecret {
+            client_id: "client-id".to_string(),
+            client_secret: "client-secret".to_string(),
+            access_token: "access-token".to_string(),
+            token_type: Some("Bearer".to_string()),
+            refresh_token: Some("refresh-token".to_string()),
+            expires_in: 3600,
+            metadata: json!({ "instance_url": "https://example.com" }),
+            request_payload: None,
+        }
+    }
+
+    #[test]
+    fn test_expires_at_path_matches_serialized_oauth() {
+        let oauth = OAuth::Enabled {
+            connection_oauth_definition_id: Id::now(IdPrefix::Connection),
+            expires_in: Some(3600),
+            expires_at: Some(100),
+        };
+        let value = json!({ "oauth": oauth });
+
+        let expires_at = OAUTH_EXPIRES_AT_PATH
+            .split('.')
+            .try_fold(&value, |value, key| value.get(key));
+
+        assert_eq!(expires_at, Some(&json!(100)));
+    }
+
+    #[test]
+    fn test_refresh_payload_exposes_secret_fields() {
+        let payload = refresh_payload(&oauth_secret());
+
+        assert_eq!(payload["OAUTH_REFRESH_TOKEN"], "refresh-token");
+        assert_eq!(payload["refreshToken"], "refresh-token");
+        assert_eq!(payload["clientId"], "client-id");
+        assert_eq!(payload["clientSecret"], "client-secret");
+        assert_eq!(payload["metadata"]["instance_url"], "https://example.com");
+    }
+
+    #[test]
+    fn test_client_credentials_payload_matches_init_payload() {
+        let payload =
+            client_credentials_payload("client-id", "client-secret", json!({ "tenant": "acme" }));
+
+        assert_eq!(
+            payload,
+            json!({
+                "clientId": "client-id",
+                "clientSecret": "client-secret",
+                "metadata": { "tenant": "acme" },
+            })
+        );
+    }
+
+    #[test]
+    fn test_only_refused_grants_are_rejections() {
+        assert!(is_rejection(StatusCode::BAD_REQUEST));
+        assert!(is_rejection(StatusCode::UNAUTHORIZED));
+        assert!(!is_rejection(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_rejection(StatusCode::REQUEST_TIMEOUT));
+        assert!(!is_rejection(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(!is_rejection(StatusCode::BAD_GATEWAY));
+    }
+
+    #[test]
+    fn test_token_request_renders_computation() {
+        let config: ApiModelConfig = serde_json::from_value(json!({
+            "baseUrl": "https://example.com",
+            "path": "/oauth/token",
+            "authMethod": { "type": "None" },
+            "headers": { "authorization": "Basic {{{credentials}}}" },
+            "queryParams": { "tenant": "{{tenant}}" },
+            "content": "form",
+            "schemas": {},
+            "samples": {},
+            "responses": []
+        }))
+        .unwrap();
+
+        let computation = Computation {
+            headers: Some(json!({ "credentials": "Y2xpZW50OnNlY3JldA==" })),
+            query_params: Some(json!({ "tenant": "acme" })),
+            body: Some(json!({
+                "grant_type": "refresh_token",
+                "refresh_token": "{{refreshToken}}"
+            })),
+        };
+
+        let request = token_request(
+            &Client::new(),
+            &config,
+            Some(&computation),
+            &refresh_payload(&oauth_secret()),
+            &DefaultTemplate::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            request.url().as_str(),
+            "https://example.com/oauth/token?tenant=acme"
+        );
+        assert_eq!(
+            request.headers()["authorization"],
+            "Basic Y2xpZW50OnNlY3JldA=="
+        );
+        assert_eq!(
+            request.body().and_then(|body| body.as_bytes()),
+            Some("grant_type=refresh_token&refresh_token=refresh-token".as_bytes())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refresh_runs_the_client_credentials_grant_again() {
+        let db = database().await;
+        let secrets_client = Arc::new(MockSecretsClient::default());
+        let mut provider = Server::new_async().await;
+        let connection =
+            seed_client_credentials_connection(&db, &secrets_client, provider.url()).await;
+
+        let mock = provider
+            .mock("POST", "/oauth/token")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("grant_type".to_string(), "client_credentials".to_string()),
+                Matcher::UrlEncoded("client_id".to_string(), "client-id".to_string()),
+                Matcher::UrlEncoded("tenant".to_string(), "acme".to_string()),
+            ]))
+            .with_status(200)
+            .with_body(r#"{"access_token":"new-token","expires_in":7200}"#)
+            .create_async()
+            .await;
+
+        let (refreshed, secret) = refresher(&db, secrets_client.clone())
+            .await
+            .refresh(&connection)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+
+        let oauth_secret: OAuthSecret = secret.decode().unwrap();
+        assert_eq!(oauth_secret.access_token, "new-token");
+        assert_eq!(oauth_secret.expires_in, 7200);
+        assert_eq!(oauth_secret.client_secret, "client-secret");
+        assert_eq!(
+            oauth_secret.request_payload,
+            Some(json!({ "tenant": "acme" }))
+        );
+
+        let stored = MongoStore::<Connection>::new(&db, &Store::Connections)
+            .await
+            .unwrap()
+            .get_one_by_id(&connection.id.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.secrets_service_id, refreshed.secrets_service_id);
+        assert_ne!(stored.secrets_service_id, connection.secrets_service_id);
+        assert!(!stored.has_error);
+
+        // The previous secret is deleted
+        let secrets = secrets_client.secrets.lock().unwrap();
+        assert_eq!(secrets.len(), 1);
+        assert!(secrets.contains_key(&stored.secrets_service_id));
+    }
+
+    #[tokio::test]
+    async fn test_refused_client_credentials_grant_marks_connection_errored() {
+        let db = database().await;
+        let secrets_client = Arc::new(MockSecretsClient::default());
+        let mut provider = Server::new_async().await;
+        let connection =
+            seed_client_credentials_connection(&db, &secrets_client, provider.url()).await;
+
+        let mock = provider
+            .mock("POST", "/oauth/token")
+            .with_status(401)
+            .with_body(r#"{"error":"invalid_client"}"#)
+            .create_async()
+            .await;
+
+        let refresher = refresher(&db, secrets_client.clone()).await;
+        assert!(refresher.refresh(&connection).await.is_err());
+
+        mock.assert_async().await;
+
+        let stored = MongoStore::<Connection>::new(&db, &Store::Connections)
+            .await
+            .unwrap()
+            .get_one_by_id(&connection.id.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(stored.has_error);
+        assert!(stored.error.unwrap().contains("invalid_client"));
+        assert_eq!(stored.secrets_service_id, connection.secrets_service_id);
+
+        // Errored connections are left out of the sweeps
+        assert!(refresher.expiring(60, 0, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_provider_failure_leaves_connection_to_the_next_sweep() {
+        let db = database().await;
+        let secrets_client = Arc::new(MockSecretsClient::default());
+        let mut provider = Server::new_async().await;
+        let connection =
+            seed_client_credentials_connection(&db, &secrets_client, provider.url()).await;
+
+        let mock = provider
+            .mock("POST", "/oauth/token")
+            .with_status(503)
+            .create_async()
+            .await;
+
+        let refresher = refresher(&db, secrets_client.clone()).await;
+        assert!(refresher.refresh(&connection).await.is_err());
+
+        mock.assert_async().await;
+
+        let stored = MongoStore::<Connection>::new(&db, &Store::Connections)
+            .await
+            .unwrap()
+            .get_one_by_id(&connection.id.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!stored.has_error);
+
+        // Backed off until the retry delay elapsed
+        assert!(refresher.expiring(60, 300, 10).await.unwrap().is_empty());
+        assert_eq!(refresher.expiring(60, 0, 10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failing_connections_dont_hold_back_the_batch() {
+        let db = database().await;
+        let secrets_client = Arc::new(MockSecretsClient::default());
+        let mut provider = Server::new_async().await;
+        let failing =
+            seed_client_credentials_connection(&db, &secrets_client, provider.url()).await;
+        let healthy =
+            seed_client_credentials_connection(&db, &secrets_client, provider.url()).await;
+
+        let mock = provider
+            .mock("POST", "/oauth/token")
+            .with_status(503)
+            .create_async()
+            .await;
+
+        let refresher = refresher(&db, secrets_client.clone()).await;
+        assert!(refresher.refresh(&failing).await.is_err());
+
+        mock.assert_async().await;
+
+        let batch = refresher.expiring(60, 300, 1).await.unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].id, healthy.id);
+    }
+}
@@ -4,6 +4,7 @@ use crate::{
 };
 use async_trait::async_trait;
 use bson::doc;
+use chrono::Utc;
 use secrecy::ExposeSecret;
 use serde_json::Value;
 
@@ -12,6 +13,35 @@ pub trait SecretExt: Send + Sync {
     async fn get(&self, id: &str, buildable_id: &str) -> Result<Secret, PicaError>;
 
     async fn create(&self, secret: &Value, buildable_id: &str) -> Result<Secret, PicaError>;
+
+    async fn delete(&self, id: &str, buildable_id: &str) -> Result<(), PicaError>;
+}
+
+/// The ciphertext is overwritten before the record is removed, so that a
+/// failed delete still leaves a tombstone rather than usable key material.
+async fn delete_secret(
+    storage: &MongoStore<Secret>,
+    id: &str,
+    buildable_id: &str,
+) -> Result<(), PicaError> {
+    let filter = doc! { "_id": id, "buildableId": buildable_id };
+
+    storage
+        .collection
+        .update_one(
+            filter.clone(),
+            doc! {
+                "$set": {
+                    "encryptedSecret": "",
+                    "deletedAt": Utc::now().timestamp_millis(),
+                }
+            },
+        )
+        .await?;
+
+    storage.collection.delete_one(filter).await?;
+
+    Ok(())
 }
 
 #[derive(Debug, Clone)]
@@ -73,6 +103,10 @@ impl SecretExt for IOSKms {
 
         Ok(secret)
     }
+
+    async fn delete(&self, id: &str, buildable_id: &str) -> Result<(), PicaError> {
+        delete_secret(&self.storage, id, buildable_id).await
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -133,4 +167,8 @@ impl SecretExt for GoogleKms {
 
         Ok(secret)
     }
+
+    async fn delete(&self, id: &str, buildable_id: &str) -> Result<(), PicaError> {
+        delete_secret(&self.storage, id, buildable_id).await
+    }
 }
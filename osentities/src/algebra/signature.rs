@@ -0,0 +1,100 @@
+use crate::{constant::*, ApplicationError, PicaError};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use http::HeaderMap;
+use sha2::Sha256;
+use std::time::Duration;
+
+/// The MAC over `{timestamp}.{body}`, so a signature can't be replayed with
+/// a different timestamp
+fn task_mac(secret: &[u8], timestamp: i64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect(HMAC_LENGTH_ERROR);
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+/// The value of the signature header of a task delivery
+pub fn sign_task_payload(secret: &[u8], timestamp: i64, body: &[u8]) -> String {
+    format!(
+        "{TASK_SIGNATURE_VERSION}={}",
+        hex::encode(task_mac(secret, timestamp, body).finalize().into_bytes())
+    )
+}
+
+/// Checks the signature headers of a task delivery, rejecting deliveries
+/// signed more than `tolerance` away from `now`
+pub fn verify_task_signature(
+    secret: &[u8],
+    headers: &HeaderMap,
+    body: &[u8],
+    tolerance: Duration,
+    now: DateTime<Utc>,
+) -> Result<(), PicaError> {
+    let timestamp = headers
+        .get(TASK_TIMESTAMP_HEADER)
+        .and_then(|timestamp| timestamp.to_str().ok())
+        .and_then(|timestamp| timestamp.parse::<i64>().ok())
+        .ok_or_else(|| ApplicationError::unauthorized("Missing task timestamp", None))?;
+
+    if now.timestamp().abs_diff(timestamp) > tolerance.as_secs() {
+        return Err(ApplicationError::unauthorized(
+            "Task timestamp is outside the tolerance",
+            None,
+        ));
+    }
+
+    let signature = headers
+        .get(TASK_SIGNATURE_HEADER)
+        .and_then(|signature| signature.to_str().ok())
+        .and_then(|signature| signature.strip_prefix(TASK_SIGNATURE_VERSION))
+        .and_then(|signature| signature.strip_prefix('='))
+        .and_then(|signature| hex::decode(signature).ok())
+        .ok_or_else(|| ApplicationError::unauthorized("Missing task signature", None))?;
+
+    task_mac(secret, timestamp, body)
+        .verify_slice(&signature)
+        .map_err(|_| ApplicationError::unauthorized("Invalid task signature", None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn signed_headers(secret: &[u8], timestamp: i64, body: &[u8]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(TASK_TIMESTAMP_HEADER, HeaderValue::from(timestamp));
+        headers.insert(
+            TASK_SIGNATURE_HEADER,
+            HeaderValue::from_str(&sign_task_payload(secret, timestamp, body)).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_verify_task_signature() {
+        let now = DateTime::from_timestamp(1_741_000_000, 0).unwrap();
+        let tolerance = Duration::from_secs(300);
+        let body = br#"{"hello":"world"}"#;
+        let headers = signed_headers(b"secret", now.timestamp() - 10, body);
+
+        assert!(verify_task_signature(b"secret", &headers, body, tolerance, now).is_ok());
+        assert!(verify_task_signature(b"other", &headers, body, tolerance, now).is_err());
+        assert!(verify_task_signature(b"secret", &headers, b"{}", tolerance, now).is_err());
+        assert!(verify_task_signature(b"secret", &HeaderMap::new(), body, tolerance, now).is_err());
+    }
+
+    #[test]
+    fn test_verify_stale_task_signature() {
+        let now = DateTime::from_timestamp(1_741_000_000, 0).unwrap();
+        let body = b"{}";
+        let headers = signed_headers(b"secret", now.timestamp() - 301, body);
+
+        assert!(
+            verify_task_signature(b"secret", &headers, body, Duration::from_secs(300), now)
+                .is_err()
+        );
+    }
+}
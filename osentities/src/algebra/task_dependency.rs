@@ -0,0 +1,108 @@
+use super::MongoStore;
+use crate::{
+    task::{Task, TaskState},
+    Id, PicaError, Unit,
+};
+use bson::doc;
+use chrono::Utc;
+use futures::TryStreamExt;
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+#[derive(Deserialize)]
+struct DependencyState {
+    #[serde(default)]
+    state: TaskState,
+}
+
+/// Decides a waiting task once its dependencies are settled: it becomes
+/// eligible when all of them succeeded, and dies as soon as one of them fails
+/// if it cascades failures. Returns the state it moved to, if any.
+pub async fn settle_waiting_task(
+    tasks: &MongoStore<Task>,
+    task: &Task,
+) -> Result<Option<TaskState>, PicaError> {
+    if task.state != TaskState::Waiting {
+        return Ok(None);
+    }
+
+    let dependency_ids = task
+        .depends_on
+        .iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>();
+    let states = tasks
+        .collection
+        .clone_with_type::<DependencyState>()
+        .find(doc! { "_id": { "$in": dependency_ids } })
+        .projection(doc! { "state": 1 })
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let succeeded = states
+        .iter()
+        .filter(|dependency| dependency.state == TaskState::Succeeded)
+        .count();
+    let failed = states
+        .iter()
+        .any(|dependency| matches!(dependency.state, TaskState::Dead | TaskState::Cancelled));
+
+    let (state, update) = if succeeded == task.depends_on.len() {
+        (
+            TaskState::Pending,
+            doc! { "state": TaskState::Pending.to_string(), "active": true },
+        )
+    } else if failed && task.cascade_failure {
+        (
+            TaskState::Dead,
+            doc! {
+                "state": TaskState::Dead.to_string(),
+                "endTime": Utc::now().timestamp_millis(),
+            },
+        )
+    } else {
+        return Ok(None);
+    };
+
+    // Only one of the dependencies finishing concurrently moves the task
+    let result = tasks
+        .collection
+        .update_one(
+            doc! { "_id": task.id.to_string(), "state": TaskState::Waiting.to_string() },
+            doc! { "$set": update },
+        )
+        .await?;
+
+    Ok((result.modified_count > 0).then_some(state))
+}
+
+/// Settles the waiting dependents of a task that finished, and in turn the
+/// dependents of those that died with it
+pub async fn settle_dependents(tasks: &MongoStore<Task>, id: Id) -> Result<Unit, PicaError> {
+    let mut finished = VecDeque::from([id]);
+
+    while let Some(id) = finished.pop_front() {
+        let dependents = tasks
+            .get_many(
+                Some(doc! {
+                    "dependsOn": id.to_string(),
+                    "state": TaskState::Waiting.to_string(),
+                    "deleted": false,
+                }),
+                None,
+                None,
+                None,
+                None,
+            )
+            .await?;
+
+        for dependent in dependents {
+            if settle_waiting_task(tasks, &dependent).await? == Some(TaskState::Dead) {
+                finished.push_back(dependent.id);
+            }
+        }
+    }
+
+    Ok(())
+}
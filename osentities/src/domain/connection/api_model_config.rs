@@ -1,10 +1,13 @@
 use http::HeaderMap;
 use js_sandbox_ios::Script;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::BTreeMap;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Not,
+};
 
-use crate::{prelude::schema::json_schema::JsonSchema, InternalError, PicaError};
+use crate::{prelude::schema::json_schema::JsonSchema, ApplicationError, InternalError, PicaError};
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[cfg_attr(feature = "dummy", derive(fake::Dummy))]
@@ -101,6 +104,78 @@ pub struct SchemasInput {
     pub query_params: Option<JsonSchema>,
     pub path_params: Option<JsonSchema>,
     pub body: Option<JsonSchema>,
+    /// Rejects requests that don't match the schemas before they are sent upstream
+    #[serde(default, skip_serializing_if = "<&bool>::not")]
+    pub strict: bool,
+}
+
+/// The body of a request, as far as [`SchemasInput::validate`] is concerned
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SchemaBody<'a> {
+    Absent,
+    Json(&'a Value),
+    /// Bodies that aren't JSON (e.g. forms) can't be checked against a schema
+    Other,
+}
+
+impl<'a> From<Option<&'a Value>> for SchemaBody<'a> {
+    fn from(body: Option<&'a Value>) -> Self {
+        body.map_or(SchemaBody::Absent, SchemaBody::Json)
+    }
+}
+
+impl SchemasInput {
+    /// Validates a request against the schemas, failing with the violations in
+    /// the error's meta. Missing schemas are skipped, as are bodies that aren't
+    /// JSON and absent bodies when nothing in them is required.
+    pub fn validate(
+        &self,
+        headers: &HeaderMap,
+        query_params: &HashMap<String, String>,
+        path_params: &HashMap<String, String>,
+        body: SchemaBody,
+    ) -> Result<(), PicaError> {
+        let mut violations = vec![];
+
+        if let Some(schema) = &self.headers {
+            violations.extend(schema.validate_params(
+                |name| headers.get(name).and_then(|value| value.to_str().ok()),
+                "headers",
+            ));
+        }
+
+        if let Some(schema) = &self.query_params {
+            violations.extend(
+                schema.validate_params(|name| query_params.get(name).map(String::as_str), "query"),
+            );
+        }
+
+        if let Some(schema) = &self.path_params {
+            violations.extend(
+                schema.validate_params(|name| path_params.get(name).map(String::as_str), "path"),
+            );
+        }
+
+        if let Some(schema) = &self.body {
+            match body {
+                SchemaBody::Json(body) => violations.extend(schema.validate(body, "body")),
+                SchemaBody::Absent if schema.required.iter().flatten().next().is_some() => {
+                    violations.extend(schema.validate(&Value::Object(Default::default()), "body"))
+                }
+                SchemaBody::Absent | SchemaBody::Other => {}
+            }
+        }
+
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        Err(ApplicationError::unprocessable_entity(
+            "Request does not match the model definition schemas",
+            Some("schema_validation"),
+        )
+        .set_meta(&json!({ "errors": violations })))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -142,6 +217,24 @@ pub enum AuthMethod {
         key: String,
         value: String,
     },
+    AwsSigV4 {
+        service: String,
+        region: String,
+    },
+    JwtAssertion {
+        #[serde(rename = "tokenUrl")]
+        token_url: String,
+        algorithm: JwtAssertionAlgorithm,
+        /// Claims added to the assertion on top of `iat` and `exp`. Values are
+        /// rendered against the connection secret like the rest of the config.
+        #[serde(default)]
+        claims: BTreeMap<String, String>,
+        /// Extra form parameters sent to the token endpoint alongside the assertion
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        params: BTreeMap<String, String>,
+        #[serde(rename = "expiresIn", skip_serializing_if = "Option::is_none")]
+        expires_in: Option<i64>,
+    },
     OAuth,
     None,
 }
@@ -159,6 +252,15 @@ pub enum OAuthLegacyHashAlgorithm {
     PlainText,
 }
 
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+pub enum JwtAssertionAlgorithm {
+    #[serde(rename = "RS256")]
+    Rs256,
+    #[serde(rename = "ES256")]
+    Es256,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Function(pub Compute);
@@ -209,3 +311,41 @@ pub enum Lang {
     TypeScript,
     Rust,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schemas() -> SchemasInput {
+        SchemasInput {
+            headers: None,
+            query_params: None,
+            path_params: None,
+            body: Some(
+                serde_json::from_value(json!({
+                    "type": "object",
+                    "required": ["email"],
+                    "properties": { "email": { "type": "string" } }
+                }))
+                .unwrap(),
+            ),
+            strict: true,
+        }
+    }
+
+    fn validate(body: SchemaBody) -> Result<(), PicaError> {
+        schemas().validate(&HeaderMap::new(), &HashMap::new(), &HashMap::new(), body)
+    }
+
+    #[test]
+    fn test_validate_body() {
+        assert!(validate(SchemaBody::Json(&json!({ "email": "jane@example.com" }))).is_ok());
+        assert!(validate(SchemaBody::Json(&json!({ "email": 42 }))).is_err());
+        assert!(validate(SchemaBody::Absent).is_err());
+    }
+
+    #[test]
+    fn test_validate_skips_bodies_that_are_not_json() {
+        assert!(validate(SchemaBody::Other).is_ok());
+    }
+}
@@ -5,6 +5,7 @@ use crate::{
 };
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::cmp::Ordering;
 use strum::{Display, EnumIter};
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
@@ -57,6 +58,90 @@ pub struct ConnectionModelDefinition {
     pub record_metadata: RecordMetadata,
 }
 
+impl ConnectionModelDefinition {
+    /// Picks the definition matching the platform version a connection is
+    /// pinned to, out of the candidates resolved for a single route or action.
+    /// See [`select_platform_version`] for the fallback rules.
+    pub fn select_by_platform_version(candidates: Vec<Self>, platform_version: &str) -> Vec<Self> {
+        let Some(selected) = select_platform_version(
+            candidates.iter().map(|c| c.platform_version.as_str()),
+            platform_version,
+        )
+        .map(str::to_owned) else {
+            return vec![];
+        };
+
+        candidates
+            .into_iter()
+            .filter(|c| c.platform_version == selected)
+            .collect()
+    }
+}
+
+/// Chooses which of the `available` platform versions serves a connection
+/// pinned to `pinned`:
+///
+/// 1. the pinned version itself, when it is published;
+/// 2. otherwise the newest version older than the pinned one, so that
+///    connections never silently move forward to a newer upstream API;
+/// 3. otherwise the oldest version newer than the pinned one.
+pub fn select_platform_version<'a>(
+    available: impl IntoIterator<Item = &'a str>,
+    pinned: &str,
+) -> Option<&'a str> {
+    let mut older = None;
+    let mut newer = None;
+
+    for version in available {
+        match compare_platform_versions(version, pinned) {
+            Ordering::Equal => return Some(version),
+            Ordering::Less => {
+                if older.is_none_or(|o| compare_platform_versions(version, o).is_gt()) {
+                    older = Some(version);
+                }
+            }
+            Ordering::Greater => {
+                if newer.is_none_or(|n| compare_platform_versions(version, n).is_lt()) {
+                    newer = Some(version);
+                }
+            }
+        }
+    }
+
+    older.or(newer)
+}
+
+/// Orders platform versions such as `v1`/`v2`, `1.10.0`/`1.9.2` or
+/// `2024-01-01`/`2024-06-15` by comparing their numeric parts numerically and
+/// everything else lexicographically.
+pub fn compare_platform_versions(a: &str, b: &str) -> Ordering {
+    fn segments(version: &str) -> Vec<&str> {
+        version
+            .trim_start_matches(['v', 'V'])
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    let (a_segments, b_segments) = (segments(a), segments(b));
+
+    for (a, b) in a_segments.iter().zip(b_segments.iter()) {
+        let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            _ => a.cmp(b),
+        };
+
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+
+    a_segments
+        .len()
+        .cmp(&b_segments.len())
+        .then_with(|| a.cmp(b))
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 #[cfg_attr(feature = "dummy", derive(fake::Dummy))]
 #[serde(rename_all = "camelCase")]
@@ -363,6 +448,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_compare_platform_versions() {
+        assert!(compare_platform_versions("v2", "v10").is_lt());
+        assert!(compare_platform_versions("1.10.0", "1.9.2").is_gt());
+        assert!(compare_platform_versions("2024-06-15", "2024-01-01").is_gt());
+        assert!(compare_platform_versions("v1", "1").is_ne());
+        assert!(compare_platform_versions("v1", "v1").is_eq());
+    }
+
+    #[test]
+    fn test_select_platform_version() {
+        let available = ["v1", "v2", "v4"];
+
+        assert_eq!(select_platform_version(available, "v2"), Some("v2"));
+        assert_eq!(select_platform_version(available, "v3"), Some("v2"));
+        assert_eq!(select_platform_version(available, "v9"), Some("v4"));
+        assert_eq!(select_platform_version(["v2", "v4"], "v1"), Some("v2"));
+        assert_eq!(select_platform_version([], "v1"), None);
+    }
+
     #[test]
     fn test_deserialize_parameter_location() {
         let query_parameter = json!("QueryParameter");
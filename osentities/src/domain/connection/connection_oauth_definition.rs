@@ -5,10 +5,12 @@ use crate::{
     prelude::{ownership::Ownership, shared::record_metadata::RecordMetadata},
     Feature,
 };
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use bson::doc;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::ops::Not;
+use sha2::{Digest, Sha256};
+use std::{fmt::Display, ops::Not};
 use tabled::Tabled;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -23,16 +25,40 @@ pub struct ConnectionOAuthDefinition {
     pub frontend: Frontend,
     #[serde(default, skip_serializing_if = "<&bool>::not")]
     pub is_full_template_enabled: bool,
+    #[serde(default)]
+    pub grant_type: OAuthGrantType,
     #[serde(flatten, default)]
     pub record_metadata: RecordMetadata,
 }
 
+/// How tokens are obtained for connections to the platform. With the client
+/// credentials grant the `init` request is run directly when the connection is
+/// created and again whenever the token expires, `refresh` is unused.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub enum OAuthGrantType {
+    #[default]
+    AuthorizationCode,
+    ClientCredentials,
+}
+
+impl ConnectionOAuthDefinition {
+    pub fn pkce(&self) -> Option<PkceMethod> {
+        self.frontend.pkce
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[cfg_attr(feature = "dummy", derive(fake::Dummy))]
 #[serde(rename_all = "camelCase")]
 pub struct OAuthCompute {
     pub init: ComputeRequest,
     pub refresh: ComputeRequest,
+    /// Computes the revocation request from the stored secret. The response of
+    /// the revocation endpoint is not used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoke: Option<Function>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -68,12 +94,43 @@ pub struct Frontend {
     pub ios_redirect_uri: String,
     #[serde(skip_serializing_if = "Option::is_none", default = "default_separator")]
     pub separator: Option<String>,
+    /// Whether the platform requires PKCE, and with which challenge method
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pkce: Option<PkceMethod>,
 }
 
 fn default_separator() -> Option<String> {
     Some(String::from(" "))
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+pub enum PkceMethod {
+    #[serde(rename = "S256")]
+    S256,
+    #[serde(rename = "plain")]
+    Plain,
+}
+
+impl PkceMethod {
+    /// Derives the `code_challenge` sent in the authorization request from the verifier
+    pub fn challenge(&self, code_verifier: &str) -> String {
+        match self {
+            PkceMethod::S256 => URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes())),
+            PkceMethod::Plain => code_verifier.to_string(),
+        }
+    }
+}
+
+impl Display for PkceMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PkceMethod::S256 => write!(f, "S256"),
+            PkceMethod::Plain => write!(f, "plain"),
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Clone, Serialize)]
 #[cfg_attr(feature = "dummy", derive(fake::Dummy))]
 #[serde(rename_all = "camelCase")]
@@ -90,6 +147,9 @@ pub struct OAuthResponse {
 pub struct OAuthApiConfig {
     pub init: ApiModelConfig,
     pub refresh: ApiModelConfig,
+    /// Token revocation endpoint called when a connection is deleted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoke: Option<ApiModelConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -128,3 +128,36 @@ pub const EXCLUDE: &AsciiSet = &NON_ALPHANUMERIC
     .remove(b'.')
     .remove(b'_')
     .remove(b'~');
+
+// AWS Signature V4 constants
+pub const AWS_SIGV4_ALGORITHM: &str = "AWS4-HMAC-SHA256";
+pub const AWS_SIGV4_TERMINATOR: &str = "aws4_request";
+pub const AWS_DATE_HEADER: &str = "x-amz-date";
+pub const AWS_CONTENT_SHA256_HEADER: &str = "x-amz-content-sha256";
+pub const AWS_SECURITY_TOKEN_HEADER: &str = "x-amz-security-token";
+
+// Task delivery signature constants
+pub const TASK_SIGNATURE_HEADER: &str = "x-pica-signature";
+pub const TASK_TIMESTAMP_HEADER: &str = "x-pica-timestamp";
+pub const TASK_SIGNATURE_VERSION: &str = "v1";
+
+// OAuth refresh constants
+// Subtracted from the provider's `expires_in` so tokens are refreshed before they lapse
+pub const OAUTH_EXPIRY_BUFFER_SECS: i64 = 120;
+pub const OAUTH_EXPIRES_AT_PATH: &str = "oauth.enabled.expires_at";
+// Unix timestamp (in seconds) of a connection's last refresh attempt, cleared when it succeeds
+pub const OAUTH_REFRESH_ATTEMPTED_AT_PATH: &str = "oauthRefreshAttemptedAt";
+pub const OAUTH_AUTHORIZATION_TTL_SECS: i64 = 600;
+pub const OAUTH_CLIENT_ID_KEY: &str = "OAUTH_CLIENT_ID";
+pub const OAUTH_CLIENT_SECRET_KEY: &str = "OAUTH_CLIENT_SECRET";
+
+// JWT assertion constants
+pub const JWT_BEARER_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+pub const JWT_ASSERTION_DEFAULT_EXPIRES_IN: i64 = 3600;
+pub const JWT_ASSERTION_EXPIRY_SKEW_SECS: i64 = 60;
+pub const JWT_ASSERTION_TOKEN_CACHE_TTL_SECS: u64 = 3600;
+
+// Cache constants
+pub const REMOTE_CACHE_PREFIX: &str = "pica:cache";
+pub const CACHE_INVALIDATION_CHANNEL: &str = "pica:cache:invalidations";
+pub const CACHE_INVALIDATION_TTL_SECS: u64 = 3600;
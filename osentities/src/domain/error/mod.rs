@@ -1303,6 +1303,14 @@ impl PicaError {
     pub fn is_application(&self) -> bool {
         matches!(self, PicaError::Application(_))
     }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(
+            self,
+            PicaError::Internal(InternalError::KeyNotFound { .. })
+                | PicaError::Application(ApplicationError::NotFound { .. })
+        )
+    }
 }
 
 impl ErrorMeta for PicaError {
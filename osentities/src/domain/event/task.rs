@@ -1,21 +1,429 @@
-use crate::{record_metadata::RecordMetadata, Id};
-use bytes::Bytes;
+use crate::{
+    destination::Action, environment::Environment, ownership::Ownership,
+    record_metadata::RecordMetadata, ApplicationError, Id, PicaError,
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use http::{HeaderMap, Method};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::{collections::HashMap, str::FromStr, sync::Arc};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Task {
     #[serde(rename = "_id")]
     pub id: Id,
+    /// The ownership that created the task, the only one it is visible to
+    #[serde(default)]
+    pub ownership: Ownership,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<Environment>,
     pub worker_id: i64,
     pub start_time: i64,
     pub end_time: Option<i64>,
     pub payload: Value,
     pub endpoint: String,
+    #[serde(with = "http_serde_ext_ios::method", default = "default_method")]
+    pub method: Method,
+    #[serde(with = "http_serde_ext_ios::header_map", default)]
+    pub headers: HeaderMap,
+    /// Runs an action on a connection instead of calling `endpoint`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<TaskAction>,
+    /// The response body of a successful action
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    /// Deliveries are signed with this secret when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<TaskSecretRef>,
     pub status: Option<String>,
     pub r#await: bool,
-    pub log_trail: Vec<Bytes>,
+    /// The response of the latest attempt, capped in size
+    #[serde(default)]
+    pub logs: Vec<TaskLogEntry>,
+    #[serde(default)]
+    pub state: TaskState,
+    /// Failed attempts are retried this many times before the task is dead
+    #[serde(default)]
+    pub max_retries: u32,
+    #[serde(default)]
+    pub backoff: Backoff,
+    #[serde(default)]
+    pub attempts: Vec<TaskAttempt>,
+    /// Recurring tasks spawn their next occurrence after each run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<TaskSchedule>,
+    /// The first task of a recurring series, shared by all its occurrences
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series_id: Option<Id>,
+    /// Tasks that must succeed before this one becomes eligible
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<Id>,
+    /// Whether this task dies when one of its dependencies fails or is
+    /// cancelled, rather than waiting until it is cancelled itself
+    #[serde(default)]
+    pub cascade_failure: bool,
+    /// Held by the watchdog replica running the task
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lease: Option<TaskLease>,
     #[serde(flatten)]
     pub metadata: RecordMetadata,
 }
+
+impl Task {
+    /// When the task should run again after its `failed_attempts`th failure,
+    /// `None` once its retries are exhausted
+    pub fn retry_at(&self, failed_attempts: u32, now: i64) -> Option<i64> {
+        (failed_attempts <= self.max_retries)
+            .then(|| now.saturating_add(self.backoff.delay_ms(failed_attempts) as i64))
+    }
+}
+
+fn default_method() -> Method {
+    Method::POST
+}
+
+/// A unified or passthrough action on a connection, with the task's payload
+/// as its body and the task's headers forwarded
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskAction {
+    pub connection_key: Arc<str>,
+    /// The ownership the connection must belong to
+    pub buildable_id: String,
+    pub action: Action,
+    #[serde(default)]
+    pub query_params: HashMap<String, String>,
+}
+
+/// A secret in the secrets service holding the key deliveries are signed with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskSecretRef {
+    pub id: String,
+    pub buildable_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, strum::Display)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+#[strum(serialize_all = "camelCase")]
+pub enum TaskState {
+    /// Waiting for its start time, including between retries
+    #[default]
+    Pending,
+    /// Waiting for its dependencies to succeed
+    Waiting,
+    Running,
+    Succeeded,
+    /// Failed and out of retries, or one of its dependencies failed
+    Dead,
+    Cancelled,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub enum BackoffStrategy {
+    Fixed,
+    #[default]
+    Exponential,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase", default)]
+pub struct Backoff {
+    pub strategy: BackoffStrategy,
+    pub delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            strategy: BackoffStrategy::Exponential,
+            delay_ms: 1_000,
+            max_delay_ms: 3_600_000,
+        }
+    }
+}
+
+impl Backoff {
+    /// The delay before retrying after the `failed_attempts`th failure
+    pub fn delay_ms(&self, failed_attempts: u32) -> u64 {
+        let delay = match self.strategy {
+            BackoffStrategy::Fixed => self.delay_ms,
+            BackoffStrategy::Exponential => self
+                .delay_ms
+                .saturating_mul(2u64.saturating_pow(failed_attempts.saturating_sub(1))),
+        };
+
+        delay.min(self.max_delay_ms)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub struct TaskSchedule {
+    #[serde(flatten)]
+    pub recurrence: Recurrence,
+    /// An IANA timezone that cron expressions are evaluated in
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[serde(default)]
+    pub paused: bool,
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum Recurrence {
+    /// Standard five field expressions, or six and seven fields with seconds
+    /// and years
+    Cron { expression: String },
+    /// Counted from the end of the previous run
+    #[serde(rename_all = "camelCase")]
+    Interval { every_ms: u64 },
+}
+
+impl TaskSchedule {
+    pub fn validate(&self) -> Result<(), PicaError> {
+        self.upcoming(Utc::now(), 1).map(|_| ())
+    }
+
+    /// When a new series that may start at `start` first fires
+    pub fn first_at(&self, start: DateTime<Utc>) -> Result<Option<DateTime<Utc>>, PicaError> {
+        match self.recurrence {
+            Recurrence::Cron { .. } => self.next_after(start - chrono::Duration::milliseconds(1)),
+            Recurrence::Interval { .. } => self.validate().map(|_| Some(start)),
+        }
+    }
+
+    /// The first fire time strictly after `after`
+    pub fn next_after(&self, after: DateTime<Utc>) -> Result<Option<DateTime<Utc>>, PicaError> {
+        Ok(self.upcoming(after, 1)?.into_iter().next())
+    }
+
+    /// Up to `count` fire times strictly after `after`
+    pub fn upcoming(
+        &self,
+        after: DateTime<Utc>,
+        count: usize,
+    ) -> Result<Vec<DateTime<Utc>>, PicaError> {
+        match &self.recurrence {
+            Recurrence::Cron { expression } => {
+                let timezone = Tz::from_str(&self.timezone).map_err(|_| {
+                    ApplicationError::bad_request(
+                        &format!("Invalid timezone {}", self.timezone),
+                        None,
+                    )
+                })?;
+
+                Ok(cron_schedule(expression)?
+                    .after(&after.with_timezone(&timezone))
+                    .take(count)
+                    .map(|time| time.with_timezone(&Utc))
+                    .collect())
+            }
+            Recurrence::Interval { every_ms: 0 } => Err(ApplicationError::bad_request(
+                "The interval must be greater than zero",
+                None,
+            )),
+            Recurrence::Interval { every_ms } => Ok((1..=count as i64)
+                .filter_map(|n| {
+                    after.checked_add_signed(chrono::Duration::milliseconds(
+                        (*every_ms as i64).saturating_mul(n),
+                    ))
+                })
+                .collect()),
+        }
+    }
+}
+
+fn cron_schedule(expression: &str) -> Result<Schedule, PicaError> {
+    // The cron crate expects a leading seconds field
+    let expression = if expression.split_whitespace().count() == 5 {
+        format!("0 {expression}")
+    } else {
+        expression.to_string()
+    };
+
+    Schedule::from_str(&expression)
+        .map_err(|e| ApplicationError::bad_request(&format!("Invalid cron expression: {e}"), None))
+}
+
+/// A claim on a running task that lapses unless its worker keeps renewing it,
+/// so that tasks of crashed workers can be reclaimed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskLease {
+    pub worker: String,
+    pub expires_at: i64,
+    pub heartbeat_at: i64,
+}
+
+/// A line of a task's response, stamped with when it was received
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskLogEntry {
+    pub timestamp: i64,
+    #[serde(flatten)]
+    pub content: TaskLogContent,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum TaskLogContent {
+    /// A line of newline delimited JSON
+    Json {
+        data: Value,
+    },
+    /// A server-sent event, with its data parsed as JSON where possible
+    Event {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        event: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        data: Value,
+    },
+    Text {
+        data: String,
+    },
+    /// Marks where the rest of the response was dropped after the size cap
+    /// was reached
+    #[serde(rename_all = "camelCase")]
+    Truncated {
+        max_bytes: u64,
+    },
+}
+
+/// The outcome of a single delivery of a task
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskAttempt {
+    pub number: u32,
+    pub started_at: i64,
+    pub ended_at: i64,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+impl TaskAttempt {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+            && self
+                .status
+                .is_some_and(|status| (200..300).contains(&status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay() {
+        let backoff = Backoff {
+            strategy: BackoffStrategy::Exponential,
+            delay_ms: 1_000,
+            max_delay_ms: 5_000,
+        };
+
+        assert_eq!(backoff.delay_ms(1), 1_000);
+        assert_eq!(backoff.delay_ms(2), 2_000);
+        assert_eq!(backoff.delay_ms(3), 4_000);
+        assert_eq!(backoff.delay_ms(4), 5_000);
+        assert_eq!(backoff.delay_ms(100), 5_000);
+
+        let backoff = Backoff {
+            strategy: BackoffStrategy::Fixed,
+            ..backoff
+        };
+
+        assert_eq!(backoff.delay_ms(3), 1_000);
+    }
+
+    #[test]
+    fn test_cron_schedule_in_timezone() {
+        let schedule = TaskSchedule {
+            recurrence: Recurrence::Cron {
+                expression: "0 9 * * MON".to_string(),
+            },
+            timezone: "Europe/Paris".to_string(),
+            paused: false,
+        };
+        // A Sunday
+        let after = "2025-03-02T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+
+        let upcoming = schedule.upcoming(after, 2).unwrap();
+
+        assert_eq!(
+            upcoming,
+            vec![
+                "2025-03-03T08:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+                "2025-03-10T08:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interval_schedule() {
+        let schedule = TaskSchedule {
+            recurrence: Recurrence::Interval { every_ms: 60_000 },
+            timezone: default_timezone(),
+            paused: false,
+        };
+        let after = "2025-03-02T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+
+        assert_eq!(
+            schedule.next_after(after).unwrap(),
+            Some("2025-03-02T12:01:00Z".parse::<DateTime<Utc>>().unwrap())
+        );
+        assert!(TaskSchedule {
+            recurrence: Recurrence::Interval { every_ms: 0 },
+            ..schedule.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(TaskSchedule {
+            recurrence: Recurrence::Cron {
+                expression: "not a cron".to_string()
+            },
+            ..schedule
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_attempt_succeeded() {
+        let attempt = TaskAttempt {
+            number: 1,
+            started_at: 0,
+            ended_at: 10,
+            status: Some(204),
+            error: None,
+        };
+
+        assert!(attempt.succeeded());
+        assert!(!TaskAttempt {
+            status: Some(502),
+            ..attempt.clone()
+        }
+        .succeeded());
+        assert!(!TaskAttempt {
+            status: None,
+            error: Some("connection refused".to_string()),
+            ..attempt
+        }
+        .succeeded());
+    }
+}
@@ -1,3 +1,4 @@
+use super::json_schema::SchemaViolation;
 use crate::{
     api_model_config::Lang,
     id::{prefix::IdPrefix, Id},
@@ -1598,6 +1599,106 @@ impl CommonModel {
     }
 }
 
+impl CommonModel {
+    /// Checks a mapped value against the fields of an expanded model (see
+    /// [`CommonModel::expand_all`]). Unexpanded models and enums without
+    /// options are only checked for their JSON type.
+    pub fn validate(&self, value: &Value, path: &str) -> Vec<SchemaViolation> {
+        let mut violations = vec![];
+        self.validate_into(value, path, true, &mut violations);
+        violations
+    }
+
+    /// Like [`CommonModel::validate`], for values that may hold only some of
+    /// the fields, such as what upstreams echo back from writes. Missing
+    /// required fields are not violations.
+    pub fn validate_partial(&self, value: &Value, path: &str) -> Vec<SchemaViolation> {
+        let mut violations = vec![];
+        self.validate_into(value, path, false, &mut violations);
+        violations
+    }
+
+    fn validate_into(
+        &self,
+        value: &Value,
+        path: &str,
+        complete: bool,
+        violations: &mut Vec<SchemaViolation>,
+    ) {
+        let Value::Object(map) = value else {
+            violations.push(SchemaViolation::new(path, "expected object".to_string()));
+            return;
+        };
+
+        for field in &self.fields {
+            let path = format!("{path}.{}", field.name);
+
+            match map.get(&field.name).filter(|value| !value.is_null()) {
+                Some(value) => field
+                    .datatype
+                    .validate_into(value, &path, complete, violations),
+                None if field.required && complete => {
+                    violations.push(SchemaViolation::new(&path, "is required".to_string()))
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+impl DataType {
+    fn validate_into(
+        &self,
+        value: &Value,
+        path: &str,
+        complete: bool,
+        violations: &mut Vec<SchemaViolation>,
+    ) {
+        let expected = match self {
+            DataType::String if !value.is_string() => Some("string".to_string()),
+            DataType::Number if !value.is_number() => Some("number".to_string()),
+            DataType::Boolean if !value.is_boolean() => Some("boolean".to_string()),
+            // Dates are mapped either as ISO strings or as timestamps
+            DataType::Date if !value.is_string() && !value.is_number() => Some("date".to_string()),
+            DataType::Enum { options, .. } => match (value.as_str(), options) {
+                (None, _) => Some("string".to_string()),
+                (Some(value), Some(options))
+                    if !options.is_empty() && !options.iter().any(|o| o == value) =>
+                {
+                    Some(format!("one of {}", options.join(", ")))
+                }
+                _ => None,
+            },
+            DataType::Expandable(Expandable::Expanded { model, .. }) => {
+                model.validate_into(value, path, complete, violations);
+                None
+            }
+            DataType::Expandable(_) if !value.is_object() => Some("object".to_string()),
+            DataType::Array { element_type } => match value.as_array() {
+                Some(values) => {
+                    for (index, value) in values.iter().enumerate() {
+                        if !value.is_null() {
+                            element_type.validate_into(
+                                value,
+                                &format!("{path}[{index}]"),
+                                complete,
+                                violations,
+                            );
+                        }
+                    }
+                    None
+                }
+                None => Some("array".to_string()),
+            },
+            _ => None,
+        };
+
+        if let Some(expected) = expected {
+            violations.push(SchemaViolation::new(path, format!("expected {expected}")));
+        }
+    }
+}
+
 impl Expandable {
     pub async fn expand(&self, store: MongoStore<CommonModel>) -> Result<Self, PicaError> {
         Ok(match self {
@@ -1790,4 +1891,93 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_validate_reports_mapping_violations() {
+        let address = CommonModel {
+            name: "Address".to_string(),
+            fields: vec![Field {
+                name: "city".to_string(),
+                datatype: DataType::String,
+                description: None,
+                required: true,
+            }],
+            ..CommonModel::default()
+        };
+
+        let contact = CommonModel {
+            name: "Contact".to_string(),
+            fields: vec![
+                Field {
+                    name: "id".to_string(),
+                    datatype: DataType::String,
+                    description: None,
+                    required: true,
+                },
+                Field {
+                    name: "status".to_string(),
+                    datatype: DataType::Enum {
+                        options: Some(vec!["active".to_string(), "archived".to_string()]),
+                        reference: "ContactStatus".to_string(),
+                    },
+                    description: None,
+                    required: false,
+                },
+                Field {
+                    name: "addresses".to_string(),
+                    datatype: DataType::Array {
+                        element_type: Box::new(DataType::Expandable(Expandable::Expanded {
+                            reference: "Address".to_string(),
+                            model: address,
+                        })),
+                    },
+                    description: None,
+                    required: false,
+                },
+                Field {
+                    name: "createdAt".to_string(),
+                    datatype: DataType::Date,
+                    description: None,
+                    required: false,
+                },
+            ],
+            ..CommonModel::default()
+        };
+
+        let violations = contact.validate(
+            &json!({
+                "status": "deleted",
+                "addresses": [{ "city": "Paris" }, { "city": 75 }],
+                "createdAt": "2024-01-01T00:00:00Z",
+            }),
+            "unified",
+        );
+
+        assert_eq!(
+            violations,
+            vec![
+                SchemaViolation::new("unified.id", "is required".to_string()),
+                SchemaViolation::new(
+                    "unified.status",
+                    "expected one of active, archived".to_string()
+                ),
+                SchemaViolation::new("unified.addresses[1].city", "expected string".to_string()),
+            ]
+        );
+
+        assert!(contact
+            .validate(&json!({ "id": "1", "status": "active" }), "unified")
+            .is_empty());
+
+        assert_eq!(
+            contact.validate_partial(
+                &json!({ "status": "active", "addresses": [{}, { "city": 75 }] }),
+                "unified"
+            ),
+            vec![SchemaViolation::new(
+                "unified.addresses[1].city",
+                "expected string".to_string()
+            )]
+        );
+    }
 }
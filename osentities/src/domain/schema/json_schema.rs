@@ -210,6 +210,105 @@ impl JsonSchema {
             },
         );
     }
+
+    /// Collects every place where `value` doesn't match the schema. Only the
+    /// top level `required` is enforced since properties don't carry one, and
+    /// null values are treated as absent.
+    pub fn validate(&self, value: &Value, path: &str) -> Vec<SchemaViolation> {
+        let mut violations = vec![];
+
+        if !matches_type(&self.type_name, value) {
+            violations.push(SchemaViolation::new(
+                path,
+                format!("expected {}", self.type_name),
+            ));
+            return violations;
+        }
+
+        match value {
+            Value::Object(map) => {
+                for name in self.required.iter().flatten() {
+                    if map.get(name).map(Value::is_null).unwrap_or(true) {
+                        violations.push(SchemaViolation::new(
+                            &format!("{path}.{name}"),
+                            "is required".to_string(),
+                        ));
+                    }
+                }
+
+                for (name, property) in &self.properties {
+                    if let Some(value) = map.get(name).filter(|value| !value.is_null()) {
+                        property.validate(value, &format!("{path}.{name}"), &mut violations);
+                    }
+                }
+            }
+            Value::Array(values) => {
+                if let Some(items) = &self.items {
+                    for (index, value) in values.iter().enumerate() {
+                        items.validate(value, &format!("{path}[{index}]"), &mut violations);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        violations
+    }
+
+    /// Validates string parameters (headers, query or path params), which are
+    /// parsed according to the type of their property first. Parameters that
+    /// are not part of the schema are ignored.
+    pub fn validate_params<'a>(
+        &self,
+        get: impl Fn(&str) -> Option<&'a str>,
+        path: &str,
+    ) -> Vec<SchemaViolation> {
+        let params = self
+            .properties
+            .keys()
+            .chain(self.required.iter().flatten())
+            .filter_map(|name| {
+                let value = get(name)?;
+                let value = match self.properties.get(name) {
+                    Some(property) => property.parse(value),
+                    None => Value::String(value.to_string()),
+                };
+
+                Some((name.clone(), value))
+            })
+            .collect::<Map<String, Value>>();
+
+        self.validate(&Value::Object(params), path)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaViolation {
+    pub field: String,
+    pub message: String,
+}
+
+impl SchemaViolation {
+    pub(crate) fn new(field: &str, message: String) -> Self {
+        Self {
+            field: field.to_string(),
+            message,
+        }
+    }
+}
+
+/// Types this schema format doesn't know about (e.g. `unknown`) match anything
+fn matches_type(type_name: &str, value: &Value) -> bool {
+    match type_name {
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "null" => value.is_null(),
+        _ => true,
+    }
 }
 
 impl TryFrom<CommonModel> for JsonSchema {
@@ -263,6 +362,53 @@ impl Property {
         }
     }
 
+    fn validate(&self, value: &Value, path: &str, violations: &mut Vec<SchemaViolation>) {
+        if !matches_type(&self.r#type, value) {
+            violations.push(SchemaViolation::new(
+                path,
+                format!("expected {}", self.r#type),
+            ));
+            return;
+        }
+
+        match value {
+            Value::String(string) => {
+                if let Some(options) = self.r#enum.as_ref().filter(|o| !o.is_empty()) {
+                    if !options.contains(string) {
+                        violations.push(SchemaViolation::new(
+                            path,
+                            format!("expected one of {}", options.join(", ")),
+                        ));
+                    }
+                }
+            }
+            Value::Object(map) => {
+                for (name, property) in self.properties.iter().flatten() {
+                    if let Some(value) = map.get(name).filter(|value| !value.is_null()) {
+                        property.validate(value, &format!("{path}.{name}"), violations);
+                    }
+                }
+            }
+            Value::Array(values) => {
+                if let Some(items) = &self.items {
+                    for (index, value) in values.iter().enumerate() {
+                        items.validate(value, &format!("{path}[{index}]"), violations);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn parse(&self, value: &str) -> Value {
+        match self.r#type.as_str() {
+            "number" | "integer" | "boolean" => {
+                serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))
+            }
+            _ => Value::String(value.to_string()),
+        }
+    }
+
     pub fn retain_recursive(&mut self, name: &str, map: &HashMap<String, Field>) -> bool {
         match self.r#type.as_str() {
             "object" => {
@@ -761,4 +907,60 @@ mod tests {
 
         assert!(serde_json::to_value(&schema).is_ok());
     }
+
+    fn contact_schema() -> JsonSchema {
+        serde_json::from_value(json!({
+            "type": "object",
+            "required": ["email"],
+            "properties": {
+                "email": { "type": "string" },
+                "age": { "type": "integer" },
+                "status": { "type": "string", "enum": ["active", "archived"] },
+                "tags": { "type": "array", "items": { "type": "string" } },
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_validate_reports_every_violation() {
+        let violations = contact_schema().validate(
+            &json!({ "age": "42", "status": "deleted", "tags": ["vip", 1] }),
+            "body",
+        );
+
+        let mut fields = violations
+            .iter()
+            .map(|violation| violation.field.as_str())
+            .collect::<Vec<_>>();
+        fields.sort();
+
+        assert_eq!(
+            fields,
+            vec!["body.age", "body.email", "body.status", "body.tags[1]"]
+        );
+    }
+
+    #[test]
+    fn test_validate_accepts_matching_value() {
+        let violations = contact_schema().validate(
+            &json!({ "email": "jane@example.com", "age": 42, "status": null, "extra": true }),
+            "body",
+        );
+
+        assert!(violations.is_empty());
+    }
+
+    #[test]
+    fn test_validate_params_parses_typed_values() {
+        let params = HashMap::from([("email", "jane@example.com"), ("age", "42")]);
+        let violations =
+            contact_schema().validate_params(|name| params.get(name).copied(), "query");
+        assert!(violations.is_empty());
+
+        let params = HashMap::from([("age", "forty")]);
+        let violations =
+            contact_schema().validate_params(|name| params.get(name).copied(), "query");
+        assert_eq!(violations.len(), 2);
+    }
 }
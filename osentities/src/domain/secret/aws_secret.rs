@@ -0,0 +1,22 @@
+use crate::AwsCredentials;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct AwsSigV4Secret {
+    #[serde(rename = "AWS_ACCESS_KEY_ID")]
+    pub access_key_id: String,
+    #[serde(rename = "AWS_SECRET_ACCESS_KEY")]
+    pub secret_access_key: String,
+    #[serde(rename = "AWS_SESSION_TOKEN", default)]
+    pub session_token: Option<String>,
+}
+
+impl From<AwsSigV4Secret> for AwsCredentials {
+    fn from(secret: AwsSigV4Secret) -> Self {
+        AwsCredentials {
+            access_key_id: secret.access_key_id,
+            secret_access_key: secret.secret_access_key,
+            session_token: secret.session_token,
+        }
+    }
+}
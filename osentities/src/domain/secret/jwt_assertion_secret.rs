@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+/// Key material used to sign JWT assertions. The aliases allow a Google
+/// service account JSON key to be stored as is.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct JwtAssertionSecret {
+    #[serde(rename = "PRIVATE_KEY", alias = "private_key")]
+    pub private_key: String,
+    #[serde(rename = "PRIVATE_KEY_ID", alias = "private_key_id", default)]
+    pub private_key_id: Option<String>,
+}
@@ -1,5 +1,7 @@
+pub mod aws_secret;
 pub mod database_secret;
 pub mod hashed_secret;
+pub mod jwt_assertion_secret;
 pub mod oauth_secret;
 
 use crate::{InternalError, PicaError};
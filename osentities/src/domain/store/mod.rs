@@ -87,6 +87,10 @@ generate_stores!(
     "connection-model-definitions",
     ConnectionOAuthDefinitions,
     "connection-oauth-definitions",
+    OAuthAuthorizations,
+    "oauth-authorizations",
+    CacheInvalidations,
+    "cache-invalidations",
     Store,
     "store",
     Archives,
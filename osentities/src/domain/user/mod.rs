@@ -1,3 +1,5 @@
+use crate::constant::{DAILY_KEY, MONTHLY_KEY, PLATFORMS_KEY};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,6 +41,8 @@ pub struct Billing {
     #[serde(rename = "customerId")]
     pub customer_id: String,
     pub subscription: BillingSubscription,
+    #[serde(default)]
+    pub quotas: Vec<Quota>,
 }
 
 fn default_throughput() -> u64 {
@@ -54,3 +58,152 @@ pub struct BillingSubscription {
     pub key: String,
     pub reason: Option<String>,
 }
+
+/// Caps the number of metered requests an ownership can make per day or month,
+/// optionally only on one platform or one API type
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Quota {
+    pub period: QuotaPeriod,
+    pub limit: u64,
+    #[serde(default)]
+    pub platform: Option<String>,
+    #[serde(default)]
+    pub api_type: Option<QuotaApiType>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum::Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum QuotaPeriod {
+    Daily,
+    Monthly,
+}
+
+/// The metered APIs, named as in the metrics store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum::Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum QuotaApiType {
+    Passthrough,
+    Unified,
+}
+
+impl QuotaPeriod {
+    /// The key usage is counted under in the metrics store
+    pub fn metric_key(&self, now: DateTime<Utc>) -> String {
+        match self {
+            QuotaPeriod::Daily => format!("{}-{:02}-{:02}", now.year(), now.month(), now.day()),
+            QuotaPeriod::Monthly => format!("{}-{:02}", now.year(), now.month()),
+        }
+    }
+
+    pub fn reset_at(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let today = now.date_naive();
+        let next = match self {
+            QuotaPeriod::Daily => today + Duration::days(1),
+            QuotaPeriod::Monthly => {
+                let (year, month) = match today.month() {
+                    12 => (today.year() + 1, 1),
+                    month => (today.year(), month + 1),
+                };
+                NaiveDate::from_ymd_opt(year, month, 1).unwrap_or(today)
+            }
+        };
+
+        next.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()
+    }
+
+    fn collection_key(&self) -> &'static str {
+        match self {
+            QuotaPeriod::Daily => DAILY_KEY,
+            QuotaPeriod::Monthly => MONTHLY_KEY,
+        }
+    }
+}
+
+impl Quota {
+    pub fn applies_to(&self, platform: Option<&str>, api_type: QuotaApiType) -> bool {
+        self.api_type
+            .is_none_or(|quota_type| quota_type == api_type)
+            && self
+                .platform
+                .as_deref()
+                .is_none_or(|quota_platform| Some(quota_platform) == platform)
+    }
+
+    /// The counters in a metrics document that add up to this quota's usage
+    pub fn metric_paths(&self, now: DateTime<Utc>) -> Vec<String> {
+        let api_types = match self.api_type {
+            Some(api_type) => vec![api_type],
+            None => vec![QuotaApiType::Passthrough, QuotaApiType::Unified],
+        };
+        let period = self.period.collection_key();
+        let key = self.period.metric_key(now);
+
+        api_types
+            .into_iter()
+            .map(|api_type| match &self.platform {
+                Some(platform) => {
+                    format!("{api_type}.{PLATFORMS_KEY}.{platform}.{period}.{key}")
+                }
+                None => format!("{api_type}.{period}.{key}"),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_quota_reset() {
+        let now = Utc.with_ymd_and_hms(2025, 12, 31, 18, 30, 0).unwrap();
+
+        assert_eq!(
+            QuotaPeriod::Daily.reset_at(now),
+            Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            QuotaPeriod::Monthly.reset_at(now),
+            Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap()
+        );
+        assert_eq!(QuotaPeriod::Daily.metric_key(now), "2025-12-31");
+        assert_eq!(QuotaPeriod::Monthly.metric_key(now), "2025-12");
+    }
+
+    #[test]
+    fn test_quota_metric_paths() {
+        let now = Utc.with_ymd_and_hms(2025, 3, 4, 0, 0, 0).unwrap();
+        let quota = Quota {
+            period: QuotaPeriod::Monthly,
+            limit: 10,
+            platform: None,
+            api_type: None,
+        };
+
+        assert_eq!(
+            quota.metric_paths(now),
+            vec!["passthrough.monthly.2025-03", "unified.monthly.2025-03"]
+        );
+        assert!(quota.applies_to(None, QuotaApiType::Unified));
+
+        let quota = Quota {
+            period: QuotaPeriod::Daily,
+            platform: Some("stripe".to_string()),
+            api_type: Some(QuotaApiType::Unified),
+            ..quota
+        };
+
+        assert_eq!(
+            quota.metric_paths(now),
+            vec!["unified.platforms.stripe.daily.2025-03-04"]
+        );
+        assert!(quota.applies_to(Some("stripe"), QuotaApiType::Unified));
+        assert!(!quota.applies_to(Some("stripe"), QuotaApiType::Passthrough));
+        assert!(!quota.applies_to(Some("shopify"), QuotaApiType::Unified));
+        assert!(!quota.applies_to(None, QuotaApiType::Unified));
+    }
+}
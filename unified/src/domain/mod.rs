@@ -2,11 +2,13 @@ use bson::doc;
 use derive_builder::Builder;
 use http::StatusCode;
 use http::{HeaderMap, HeaderName, HeaderValue};
+use osentities::json_schema::SchemaViolation;
 use osentities::Id;
 use osentities::PicaError;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
+use strum::{AsRefStr, EnumString};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Builder)]
 #[builder(setter(into))]
@@ -184,6 +186,24 @@ pub struct UnifiedMetadata {
     latency: Option<i32>,
     #[builder(setter(strip_option), default)]
     hash: Option<String>,
+    /// Mismatches between the mapped response and its common model, reported
+    /// in lenient validation mode
+    #[builder(setter(strip_option), default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    warnings: Option<Vec<SchemaViolation>>,
+}
+
+/// What happens when a mapped unified response doesn't match its common model
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, EnumString, AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum CommonModelValidation {
+    #[default]
+    Off,
+    /// Violations are returned in `meta.warnings`
+    Lenient,
+    /// Violations fail reads. Those of writes, which already happened
+    /// upstream, are returned in `meta.warnings`.
+    Strict,
 }
 
 impl UnifiedMetadata {
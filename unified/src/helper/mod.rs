@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 pub fn match_route<'a>(
     full_path: &'a str,
     routes: impl Iterator<Item = &'a str>,
@@ -57,10 +59,48 @@ pub fn template_route(model_definition_path: String, full_request_path: String)
     template
 }
 
+/// Extracts the values of the `:name` and `{{name}}` segments of a route
+pub fn route_params(
+    model_definition_path: &str,
+    full_request_path: &str,
+) -> HashMap<String, String> {
+    let path = full_request_path.split('?').next().unwrap_or("");
+
+    model_definition_path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .zip(path.split('/').filter(|s| !s.is_empty()))
+        .filter_map(|(route_seg, path_seg)| {
+            let name = route_seg.strip_prefix(':').or_else(|| {
+                route_seg
+                    .strip_prefix("{{")
+                    .and_then(|seg| seg.strip_suffix("}}"))
+            })?;
+
+            Some((name.trim().to_string(), path_seg.to_string()))
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_route_params() {
+        assert_eq!(
+            route_params(
+                "/customers/{{id}}/orders/:order_id",
+                "/customers/123/orders/456?expand=true"
+            ),
+            HashMap::from([
+                ("id".to_string(), "123".to_string()),
+                ("order_id".to_string(), "456".to_string()),
+            ])
+        );
+        assert!(route_params("/customers", "/customers").is_empty());
+    }
+
     #[test]
     fn test_match_route() {
         let routes = [
@@ -2,28 +2,35 @@ use crate::domain::{ResponseCrudToMapBuilder, ResponseCrudToMapRequest};
 use crate::{
     algebra::jsruntime::JSRuntimeImpl,
     client::CallerClient,
-    domain::{RequestCrud, ResponseCrud, UnifiedMetadata, UnifiedMetadataBuilder},
-    helper::{match_route, template_route},
+    domain::{
+        CommonModelValidation, RequestCrud, ResponseCrud, UnifiedMetadata, UnifiedMetadataBuilder,
+    },
+    helper::{match_route, route_params, template_route},
 };
 use bson::doc;
-use cache::local::{
-    ConnectionCache, ConnectionModelDefinitionDestinationCache, ConnectionModelSchemaCache,
-    LocalCacheExt, SecretCache,
+use cache::{
+    invalidation::{CacheInvalidation, CacheInvalidator},
+    local::{
+        CommonModelCache, ConnectionCache, ConnectionModelDefinitionDestinationCache,
+        ConnectionModelSchemaCache, JwtAssertionTokenCache, LocalCacheExt, SecretCache,
+    },
+    remote::RedisCache,
 };
 use chrono::Utc;
 use futures::{
     future::{join_all, OptionFuture},
-    FutureExt,
+    FutureExt, TryStreamExt,
 };
 use handlebars::Handlebars;
 use http::{HeaderMap, HeaderName, HeaderValue, Response, StatusCode};
 use mongodb::{
-    options::{Collation, CollationStrength, FindOneOptions},
+    options::{Collation, CollationStrength, FindOneOptions, FindOptions},
     Client,
 };
 use osentities::{
     algebra::JsonExt,
-    api_model_config::{ModelPaths, RequestModelPaths},
+    api_model_config::{ModelPaths, RequestModelPaths, SchemaBody},
+    common_model::{CommonEnum, CommonModel},
     connection_model_definition::{ConnectionModelDefinition, CrudAction, PlatformInfo},
     connection_model_schema::ConnectionModelSchema,
     constant::*,
@@ -33,8 +40,10 @@ use osentities::{
     error::InternalError,
     hashed_secret::HashedSecret,
     id::{prefix::IdPrefix, Id},
+    oauth_secret::OAuthSecret,
     prelude::{MongoStore, TimedExt},
-    ApplicationError, Connection, ErrorMeta, PicaError, Secret, SecretExt, Store,
+    ApplicationError, Connection, ErrorMeta, OAuth, OAuthRefresher, PicaError, Secret, SecretExt,
+    Store,
 };
 use serde_json::{json, Number, Value};
 use std::{collections::HashMap, str::FromStr, sync::Arc};
@@ -63,7 +72,15 @@ pub struct UnifiedDestination {
     pub connection_model_schemas_store: MongoStore<ConnectionModelSchema>,
     pub secrets_client: Arc<dyn SecretExt + Sync + Send>,
     pub secrets_cache: SecretCache,
+    pub jwt_assertion_tokens_cache: JwtAssertionTokenCache,
+    pub oauth_refresher: OAuthRefresher,
+    pub common_models_cache: CommonModelCache,
+    pub common_models_store: MongoStore<CommonModel>,
+    pub common_enums_store: MongoStore<CommonEnum>,
+    pub common_model_validation: CommonModelValidation,
     pub http_client: reqwest::Client,
+    /// Tells other replicas about connections refreshed after a rejection
+    pub cache_invalidator: CacheInvalidator,
 }
 
 pub struct UnifiedCacheTTLs {
@@ -71,27 +88,47 @@ pub struct UnifiedCacheTTLs {
     pub connection_model_definition_cache_ttl_secs: u64,
     pub connection_model_schema_cache_ttl_secs: u64,
     pub secret_cache_ttl_secs: u64,
+    pub connection_negative_cache_ttl_secs: u64,
+    pub connection_model_definition_negative_cache_ttl_secs: u64,
 }
 
 impl UnifiedDestination {
+    /// Secrets and access tokens are only ever cached in process, even when
+    /// `remote_cache` is set.
     pub async fn new(
         db_config: DatabaseConfig,
         cache_size: u64,
         secrets_client: Arc<dyn SecretExt + Sync + Send>,
         cache_ttls: UnifiedCacheTTLs,
+        common_model_validation: CommonModelValidation,
+        remote_cache: Option<&RedisCache>,
     ) -> Result<Self, PicaError> {
         let http_client = reqwest::Client::new();
         let connections_cache =
-            ConnectionCache::new(cache_size, cache_ttls.connection_cache_ttl_secs);
+            ConnectionCache::new(cache_size, cache_ttls.connection_cache_ttl_secs)
+                .with_remote(remote_cache, "connections")
+                .with_negative_ttl(cache_ttls.connection_negative_cache_ttl_secs);
         let connection_model_definitions_cache = ConnectionModelDefinitionDestinationCache::new(
             cache_size,
             cache_ttls.connection_model_definition_cache_ttl_secs,
-        );
+        )
+        .with_remote(remote_cache, "connection-model-definitions")
+        .with_tag(|definition| definition.connection_platform.clone())
+        .with_negative_ttl(cache_ttls.connection_model_definition_negative_cache_ttl_secs);
         let connection_model_schemas_cache = ConnectionModelSchemaCache::new(
             cache_size,
             cache_ttls.connection_model_schema_cache_ttl_secs,
-        );
+        )
+        .with_remote(remote_cache, "connection-model-schemas")
+        .with_tag(|schema| schema.id.to_string());
         let secrets_cache = SecretCache::new(cache_size, cache_ttls.secret_cache_ttl_secs);
+        let jwt_assertion_tokens_cache =
+            JwtAssertionTokenCache::new(cache_size, JWT_ASSERTION_TOKEN_CACHE_TTL_SECS);
+        let common_models_cache = CommonModelCache::new(
+            cache_size,
+            cache_ttls.connection_model_schema_cache_ttl_secs,
+        )
+        .with_remote(remote_cache, "common-models");
 
         let client = Client::with_uri_str(&db_config.control_db_url)
             .await
@@ -109,6 +146,14 @@ impl UnifiedDestination {
             MongoStore::new(&db, &Store::ConnectionModelDefinitions).await?;
         let connection_model_schemas_store =
             MongoStore::new(&db, &Store::ConnectionModelSchemas).await?;
+        let oauth_refresher = OAuthRefresher::new(
+            connections_store.clone(),
+            MongoStore::new(&db, &Store::ConnectionOAuthDefinitions).await?,
+            secrets_client.clone(),
+            http_client.clone(),
+        );
+        let common_models_store = MongoStore::new(&db, &Store::CommonModels).await?;
+        let common_enums_store = MongoStore::new(&db, &Store::CommonEnums).await?;
 
         Ok(Self {
             connections_cache,
@@ -119,13 +164,28 @@ impl UnifiedDestination {
             connection_model_schemas_store,
             secrets_client,
             secrets_cache,
+            jwt_assertion_tokens_cache,
+            oauth_refresher,
+            common_models_cache,
+            common_models_store,
+            common_enums_store,
+            common_model_validation,
             http_client,
+            cache_invalidator: CacheInvalidator::disabled(),
         })
     }
 
+    pub fn with_cache_invalidator(mut self, cache_invalidator: CacheInvalidator) -> Self {
+        self.cache_invalidator = cache_invalidator;
+        self
+    }
+
+    /// Resolves the definition serving `destination` for a connection pinned
+    /// to `platform_version`, falling back to the closest published version.
     pub async fn get_connection_model_definition(
         &self,
         destination: &Destination,
+        platform_version: &str,
     ) -> Result<Option<ConnectionModelDefinition>, PicaError> {
         match &destination.action {
             Action::Passthrough { method, path, id } => match id {
@@ -170,16 +230,19 @@ impl UnifiedDestination {
 
                     let matched_route = match_route(path, routes.clone()).map(|r| r.to_string());
 
-                    let connection_model_definitions: Vec<ConnectionModelDefinition> =
-                        connection_model_definitions
-                            .clone()
-                            .into_iter()
-                            .filter(|c| match c.platform_info {
-                                PlatformInfo::Api(ref c) => matched_route
-                                    .as_ref()
-                                    .is_some_and(|mr| c.path.as_str() == mr),
-                            })
-                            .collect();
+                    let connection_model_definitions =
+                        ConnectionModelDefinition::select_by_platform_version(
+                            connection_model_definitions
+                                .clone()
+                                .into_iter()
+                                .filter(|c| match c.platform_info {
+                                    PlatformInfo::Api(ref c) => matched_route
+                                        .as_ref()
+                                        .is_some_and(|mr| c.path.as_str() == mr),
+                                })
+                                .collect(),
+                            platform_version,
+                        );
 
                     if connection_model_definitions.len() > 1 {
                         error!("Multiple connection model definitions found for this path. Destination: {:?}, Routes: {:?}", destination, routes);
@@ -193,25 +256,36 @@ impl UnifiedDestination {
                     Ok(connection_model_definitions.first().cloned())
                 }
             },
-            Action::Unified { name, action, .. } => Ok(self
-                .connection_model_definitions_store
-                .collection
-                .find_one(doc! {
-                    "connectionPlatform": destination.platform.as_ref(),
-                    "mapping.commonModelName": name.as_ref(),
-                    "actionName": action.to_string()
-                })
-                .with_options(
-                    FindOneOptions::builder()
-                        .collation(Some(
-                            Collation::builder()
-                                .strength(CollationStrength::Secondary)
-                                .locale("en")
-                                .build(),
-                        ))
-                        .build(),
+            Action::Unified { name, action, .. } => {
+                let connection_model_definitions = self
+                    .connection_model_definitions_store
+                    .collection
+                    .find(doc! {
+                        "connectionPlatform": destination.platform.as_ref(),
+                        "mapping.commonModelName": name.as_ref(),
+                        "actionName": action.to_string()
+                    })
+                    .with_options(
+                        FindOptions::builder()
+                            .collation(Some(
+                                Collation::builder()
+                                    .strength(CollationStrength::Secondary)
+                                    .locale("en")
+                                    .build(),
+                            ))
+                            .build(),
+                    )
+                    .await?
+                    .try_collect::<Vec<_>>()
+                    .await?;
+
+                Ok(ConnectionModelDefinition::select_by_platform_version(
+                    connection_model_definitions,
+                    platform_version,
                 )
-                .await?),
+                .into_iter()
+                .next())
+            }
         }
     }
 
@@ -265,7 +339,8 @@ impl UnifiedDestination {
 
         match config.platform_info {
             PlatformInfo::Api(ref c) => {
-                let api_caller = CallerClient::new(c, config.action, &self.http_client);
+                let api_caller = CallerClient::new(c, config.action, &self.http_client)
+                    .with_token_cache(&self.jwt_assertion_tokens_cache);
 
                 let response = api_caller
                     .make_request(context, Some(secret), Some(headers), Some(query_params))
@@ -298,7 +373,16 @@ impl UnifiedDestination {
         self.perform_unified_request(connection, action, environment, params, metadata)
             .await
             .map_err(|e| match metadata.build().ok() {
-                Some(metadata) => e.set_meta(&metadata.as_value()),
+                Some(metadata) => {
+                    let mut meta = metadata.as_value();
+                    // Keep the details the error already carries, e.g. validation errors
+                    if let (Some(meta), Some(Value::Object(details))) =
+                        (meta.as_object_mut(), e.meta().map(|meta| *meta))
+                    {
+                        meta.extend(details);
+                    }
+                    e.set_meta(&meta)
+                }
                 None => e,
             })
     }
@@ -325,7 +409,7 @@ impl UnifiedDestination {
                 passthrough: is_passthrough,
             } => {
                 // (ConnectionModelDefinition, Secret, ConnectionModelSchema)
-                let (config, secret, cms) = self.get_dependencies(&key, &connection, &name).await.inspect_err(|e| {
+                let (config, stored_secret, cms) = self.get_dependencies(&key, &connection, &name).await.inspect_err(|e| {
                     error!("Failed to get dependencies for unified destination. Destination: {:?}, Error: {e}", key.platform);
                 })?;
                 tracing::info!("Dependencies retrieved for unified destination. Destination: {:?}, Config: {}, ConnectionModelSchema: {}", key.platform, config.id, cms.id);
@@ -334,7 +418,7 @@ impl UnifiedDestination {
                     .action(action.to_string())
                     .common_model(config.mapping.as_ref().map(|m| m.common_model_name.clone()).unwrap_or_default());
 
-                let secret = insert_action_id(secret.as_value()?, id.as_ref());
+                let secret = insert_action_id(stored_secret.as_value()?, id.as_ref());
 
                 // Namespace for js scripts
                 let jsruntime = JSRuntimeImpl;
@@ -382,12 +466,32 @@ impl UnifiedDestination {
                 let body: Option<Value> = insert_body_into_path_object(&config, params.get_body());
                 let params: RequestCrud = params.set_body(body);
 
+                let PlatformInfo::Api(ref api_config) = config.platform_info;
+                if api_config.schemas.strict {
+                    api_config.schemas.validate(params.get_headers(), params.get_query_params(), params.get_path_params().unwrap_or(&HashMap::new()), params.get_body().into())?;
+                }
+
                 tracing::debug!("Request crud prepared for unified destination. RequestCrud: {:?}", params);
 
                 let response: reqwest::Response = self.execute_model_definition_from_request(&config, &params, &secret).timed(|_, duration| {
                     metadata.latency(duration.as_millis() as i32);
                 }).await?;
 
+                // The token may have been revoked or expired ahead of time, refresh it and retry once
+                let response: reqwest::Response = match response.status() {
+                    StatusCode::UNAUTHORIZED => match self.refresh_rejected_secret(&connection, &stored_secret).await {
+                        Some(refreshed) => {
+                            let secret: Value = extend_secret(insert_action_id(refreshed.as_value()?, id.as_ref()), params.get_path_params());
+
+                            self.execute_model_definition_from_request(&config, &params, &secret).timed(|_, duration| {
+                                metadata.latency(duration.as_millis() as i32);
+                            }).await?
+                        }
+                        None => response,
+                    },
+                    _ => response,
+                };
+
                 let status: StatusCode = response.status();
                 let headers: HeaderMap = response.headers().clone();
 
@@ -518,6 +622,13 @@ impl UnifiedDestination {
                     CrudAction::Update | CrudAction::Delete => Ok(None),
                 }?;
 
+                if let (Some(body), Some(mapping)) = (&body, &cms.mapping) {
+                    if self.common_model_validation != CommonModelValidation::Off && matches!(config.action_name, CrudAction::GetMany | CrudAction::GetOne | CrudAction::Create | CrudAction::Upsert) {
+                        let read = matches!(config.action_name, CrudAction::GetMany | CrudAction::GetOne);
+                        self.validate_common_model(&mapping.common_model_id, body, read, metadata).await?;
+                    }
+                }
+
                 build_unified_response(config, metadata, is_passthrough)(body, pagination,  passthrough, params, status, headers)
             }
             Action::Passthrough { method, path, .. } => Err(InternalError::invalid_argument(
@@ -550,7 +661,10 @@ impl UnifiedDestination {
             )
         };
 
-        let config = match self.get_connection_model_definition(destination).await {
+        let config = match self
+            .get_connection_model_definition(destination, &connection.platform_version)
+            .await
+        {
             Ok(Some(c)) => Ok(Arc::new(c)),
             Ok(None) => Err(InternalError::key_not_found(
                 "ConnectionModelDefinition",
@@ -592,6 +706,26 @@ impl UnifiedDestination {
             })
             .await?;
 
+        let PlatformInfo::Api(ref api_config) = config.platform_info;
+        if api_config.schemas.strict {
+            let path_params = match &destination.action {
+                Action::Passthrough { path, .. } => route_params(&api_config.path, path),
+                _ => HashMap::new(),
+            };
+            let body = context
+                .as_deref()
+                .map(serde_json::from_slice::<Value>)
+                .transpose();
+            let body = match &body {
+                Ok(body) => body.as_ref().into(),
+                Err(_) => SchemaBody::Other,
+            };
+
+            api_config
+                .schemas
+                .validate(&headers, &query_params, &path_params, body)?;
+        }
+
         // Template the route for passthrough actions
         let templated_config = match &destination.action {
             Action::Passthrough { path, .. } => {
@@ -605,14 +739,142 @@ impl UnifiedDestination {
             _ => config.clone(),
         };
 
-        self.execute_model_definition(
-            &templated_config,
-            headers,
-            &query_params,
-            &secret.as_value()?,
-            context,
-        )
-        .await
+        let response = self
+            .execute_model_definition(
+                &templated_config,
+                headers.clone(),
+                &query_params,
+                &secret.as_value()?,
+                context.clone(),
+            )
+            .await?;
+
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        match self.refresh_rejected_secret(&connection, &secret).await {
+            Some(refreshed) => {
+                self.execute_model_definition(
+                    &templated_config,
+                    headers,
+                    &query_params,
+                    &refreshed.as_value()?,
+                    context,
+                )
+                .await
+            }
+            None => Ok(response),
+        }
+    }
+
+    /// Validates a mapped response against its common model. Violations end up
+    /// in the response metadata or fail the request depending on the mode.
+    /// Responses to writes are only checked for the fields they hold, and never
+    /// fail the request since the write already happened upstream.
+    async fn validate_common_model(
+        &self,
+        common_model_id: &Id,
+        body: &Value,
+        read: bool,
+        metadata: &mut UnifiedMetadataBuilder,
+    ) -> Result<(), PicaError> {
+        let common_model = self
+            .common_models_cache
+            .get_or_insert_with_fn(common_model_id, || async {
+                self.common_models_store
+                    .get_one_by_id(&common_model_id.to_string())
+                    .await?
+                    .ok_or_else(|| InternalError::key_not_found("common model", None))?
+                    .expand_all(
+                        self.common_models_store.clone(),
+                        self.common_enums_store.clone(),
+                    )
+                    .await
+            })
+            .await?;
+
+        let validate = |value: &Value, path: &str| match read {
+            true => common_model.validate(value, path),
+            false => common_model.validate_partial(value, path),
+        };
+        let violations = match body {
+            Value::Array(values) => values
+                .iter()
+                .enumerate()
+                .flat_map(|(index, value)| validate(value, &format!("{UNIFIED_KEY}[{index}]")))
+                .collect::<Vec<_>>(),
+            value => validate(value, UNIFIED_KEY),
+        };
+
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        match self.common_model_validation {
+            CommonModelValidation::Strict if read => Err(ApplicationError::internal_server_error(
+                &format!(
+                    "Mapped response does not match the {} common model",
+                    common_model.name
+                ),
+                Some("common_model_validation"),
+            )
+            .set_meta(&json!({ "errors": violations }))),
+            _ => {
+                tracing::warn!(
+                    "Mapped response does not match the {} common model: {violations:?}",
+                    common_model.name
+                );
+                metadata.warnings(violations);
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Refreshes the OAuth token of a connection after the upstream rejected
+    /// `secret`, replacing the cached secret. Returns `None` when the connection
+    /// doesn't use OAuth or the refresh failed, so the original response is kept.
+    async fn refresh_rejected_secret(
+        &self,
+        connection: &Connection,
+        secret: &Secret,
+    ) -> Option<Secret> {
+        let Some(OAuth::Enabled { .. }) = connection.oauth else {
+            return None;
+        };
+
+        let rejected = secret.decode::<OAuthSecret>().ok()?;
+
+        let (refreshed, secret) = self
+            .oauth_refresher
+            .refresh_rejected(connection, &rejected.access_token)
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Failed to refresh rejected oauth token for connection {}: {e}",
+                    connection.id
+                );
+            })
+            .ok()?;
+
+        // Secrets are cached by connection id, so this replaces the stale entry
+        let _ = self.secrets_cache.insert(&refreshed, &secret).await;
+        let _ = self.connections_cache.remove(&refreshed.key).await;
+        // Other replicas may still hold the connection pointing to the secret
+        // the refresh deleted
+        if let Err(e) = self
+            .cache_invalidator
+            .publish(CacheInvalidation::from(&refreshed))
+            .await
+        {
+            error!(
+                "Failed to publish the invalidation of connection {}: {e}",
+                refreshed.id
+            );
+        }
+
+        Some(secret)
     }
 
     async fn get_dependencies(
@@ -621,10 +883,14 @@ impl UnifiedDestination {
         connection: &Connection,
         name: &str,
     ) -> Result<(ConnectionModelDefinition, Secret, ConnectionModelSchema), PicaError> {
+        let config_key = (key.clone(), connection.platform_version.as_str().into());
         let config_fut = self
             .connection_model_definitions_cache
-            .get_or_insert_with_fn(key, || async {
-                match self.get_connection_model_definition(key).await {
+            .get_or_insert_with_fn(&config_key, || async {
+                match self
+                    .get_connection_model_definition(key, &connection.platform_version)
+                    .await
+                {
                     Ok(Some(c)) => Ok(c),
                     Ok(None) => Err(InternalError::key_not_found("model definition", None)),
                     Err(e) => Err(InternalError::connection_error(
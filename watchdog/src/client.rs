@@ -1,23 +1,43 @@
-use crate::config::WatchdogConfig;
-use bson::doc;
-use cache::remote::RedisCache;
-use chrono::Utc;
+use crate::{
+    config::WatchdogConfig,
+    executor::TaskExecutor,
+    metrics::WatchdogMetrics,
+    server::{AppState, Server},
+};
+use cache::{
+    invalidation::{CacheInvalidation, CacheInvalidator},
+    remote::RedisCache,
+};
 use futures::{stream::FuturesUnordered, StreamExt};
+use mongodb::Database;
 use osentities::{
-    cache::CacheConfig, database::DatabaseConfig, task::Task, Id, InternalError, MongoStore,
-    PicaError, Store, Unit,
+    cache::CacheConfig,
+    database::DatabaseConfig,
+    secrets::SecretServiceProvider,
+    task::{Task, TaskState},
+    GoogleKms, IOSKms, InternalError, MongoStore, OAuthRefresher, PicaError, Secret, SecretExt,
+    Store, Unit,
 };
-use redis::{AsyncCommands, RedisResult};
+use redis::AsyncCommands;
 use std::fmt::Display;
+use std::sync::Arc;
 use std::time::Duration;
 use tracing::{error, info};
+use unified::{
+    domain::CommonModelValidation,
+    unified::{UnifiedCacheTTLs, UnifiedDestination},
+};
+use uuid::Uuid;
 
 pub struct WatchdogClient {
     watchdog: WatchdogConfig,
     cache: CacheConfig,
     database: DatabaseConfig,
-    client: reqwest::Client,
-    tasks: MongoStore<Task>,
+    executor: TaskExecutor,
+    oauth_refresher: Option<OAuthRefresher>,
+    cache_invalidator: CacheInvalidator,
+    db: Database,
+    metrics: Arc<WatchdogMetrics>,
 }
 
 impl Display for WatchdogClient {
@@ -45,12 +65,91 @@ impl WatchdogClient {
 
         let tasks: MongoStore<Task> = MongoStore::new(&db, &Store::Tasks).await?;
 
+        let secrets_client: Option<Arc<dyn SecretExt + Sync + Send>> = if watchdog
+            .oauth_refresh_enabled
+            || watchdog.task_signing_enabled
+            || watchdog.task_actions_enabled
+        {
+            let secrets_store = MongoStore::<Secret>::new(&db, &Store::Secrets).await?;
+            match watchdog.secrets_config.provider {
+                SecretServiceProvider::GoogleKms => Some(Arc::new(
+                    GoogleKms::new(&watchdog.secrets_config, secrets_store).await?,
+                )),
+                SecretServiceProvider::IosKms => Some(Arc::new(
+                    IOSKms::new(&watchdog.secrets_config, secrets_store).await?,
+                )),
+            }
+        } else {
+            None
+        };
+
+        let cache_invalidator =
+            CacheInvalidator::new(watchdog.cache_invalidation_transport, &cache, &db).await?;
+
+        let oauth_refresher = match &secrets_client {
+            Some(secrets_client) if watchdog.oauth_refresh_enabled => Some(OAuthRefresher::new(
+                MongoStore::new(&db, &Store::Connections).await?,
+                MongoStore::new(&db, &Store::ConnectionOAuthDefinitions).await?,
+                secrets_client.clone(),
+                http_client.clone(),
+            )),
+            _ => None,
+        };
+
+        let unified = match &secrets_client {
+            Some(secrets_client) if watchdog.task_actions_enabled => Some(Arc::new(
+                UnifiedDestination::new(
+                    database.clone(),
+                    watchdog.cache_size,
+                    secrets_client.clone(),
+                    UnifiedCacheTTLs {
+                        connection_cache_ttl_secs: watchdog.connection_cache_ttl_secs,
+                        connection_model_schema_cache_ttl_secs: watchdog
+                            .connection_model_schema_cache_ttl_secs,
+                        connection_model_definition_cache_ttl_secs: watchdog
+                            .connection_model_definition_cache_ttl_secs,
+                        secret_cache_ttl_secs: watchdog.secret_cache_ttl_secs,
+                        connection_negative_cache_ttl_secs: watchdog
+                            .connection_negative_cache_ttl_secs,
+                        connection_model_definition_negative_cache_ttl_secs: watchdog
+                            .connection_model_definition_negative_cache_ttl_secs,
+                    },
+                    CommonModelValidation::default(),
+                    None,
+                )
+                .await?
+                .with_cache_invalidator(cache_invalidator.clone()),
+            )),
+            _ => None,
+        };
+
+        let worker = watchdog.worker_id.clone().unwrap_or_else(|| {
+            let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "watchdog".to_string());
+            format!("{host}-{}", Uuid::new_v4().simple())
+        });
+        info!("Claiming tasks as worker {worker}");
+
+        let executor = TaskExecutor {
+            http_client,
+            tasks,
+            secrets_client: secrets_client.filter(|_| watchdog.task_signing_enabled),
+            unified,
+            worker: worker.into(),
+            timeout_secs: watchdog.http_client_timeout_secs,
+            max_log_bytes: watchdog.task_log_max_bytes,
+            lease_secs: watchdog.task_lease_secs,
+            heartbeat_interval_secs: watchdog.task_heartbeat_interval_secs,
+        };
+
         Ok(Self {
             watchdog,
             cache,
             database,
-            client: http_client,
-            tasks,
+            executor,
+            oauth_refresher,
+            cache_invalidator,
+            db,
+            metrics: Arc::new(WatchdogMetrics::new()),
         })
     }
 
@@ -69,70 +168,87 @@ impl WatchdogClient {
 
         info!("Initializing connection to cache");
 
+        let server = Server {
+            state: Arc::new(AppState {
+                config: self.watchdog.clone(),
+                metrics: self.metrics.clone(),
+                redis: cache.inner.clone(),
+                db: self.db.clone(),
+            }),
+        };
+        tokio::spawn(async move {
+            if let Err(e) = server.run().await {
+                error!("Watchdog server stopped: {e}");
+            }
+        });
+
+        if let Some(oauth_refresher) = self.oauth_refresher.clone() {
+            tokio::spawn(refresh_oauth_connections(
+                oauth_refresher,
+                self.cache_invalidator.clone(),
+                self.watchdog.clone(),
+            ));
+        }
+
         let mut redis_clone = cache.inner.clone();
+        let metrics = self.metrics.clone();
         tokio::spawn(async move {
             loop {
-                let _: RedisResult<String> = async { redis_clone.del(key.clone()).await }.await;
+                // Only a sweep that went through keeps the sweeper alive
+                match redis_clone.del::<_, ()>(key.clone()).await {
+                    Ok(()) => metrics.sweep(),
+                    Err(e) => {
+                        error!("Could not sweep the rate limiter: {e}");
+                        metrics.sweep_failed();
+                    }
+                }
                 tokio::time::sleep(Duration::from_secs(1)).await;
             }
         });
 
-        let key = self.cache.api_throughput_key.clone();
-        let mut redis_clone = cache.inner.clone();
-
-        tracing::info!("Rate limiter enabled. Connecting to initialized cache");
-
         loop {
-            let _: RedisResult<String> = async { redis_clone.del(key.clone()).await }.await;
-            tracing::info!("Rate limiter cleared for {key} at {}", Utc::now());
-
-            let tasks: Vec<Task> = self
-                .tasks
-                .get_many(
-                    Some(doc! {
-                    "active": true,
-                    "workerId": 0,
-                    "startTime": {
-                        "$lte": Utc::now().timestamp_millis(),
-                    }}),
-                    None,
-                    None,
-                    Some(self.watchdog.max_amount_of_tasks_to_process),
-                    None,
-                )
-                .await?;
+            self.metrics.loop_beat();
 
-            tracing::info!("Executing {} tasks", tasks.len());
+            let mut tasks = vec![];
+            while (tasks.len() as u64) < self.watchdog.max_amount_of_tasks_to_process {
+                match self.executor.claim().await? {
+                    Some(task) => tasks.push(task),
+                    None => break,
+                }
+            }
 
-            self.tasks
-                .update_many(
-                    doc! {
-                        "_id": {
-                            "$in": tasks.iter().map(|t| t.id.to_string()).collect::<Vec<_>>()
-                        }
-                    },
-                    doc! {
-                        "$set": {
-                            "workerId": 1,
-                            "active": false
-                        }
-                    },
-                )
-                .await?;
+            tracing::info!("Executing {} tasks", tasks.len());
+            self.metrics.claimed(tasks.len() as u64);
 
-            let client = self.client.clone();
-            let tasks_store = self.tasks.clone();
-            let timeout = self.watchdog.http_client_timeout_secs;
+            let executor = self.executor.clone();
+            let metrics = self.metrics.clone();
 
             tokio::spawn(async move {
                 let mut tasks = tasks
                     .into_iter()
-                    .map(|task| execute(task, client.clone(), tasks_store.clone(), timeout))
+                    .map(|task| executor.execute(task))
                     .collect::<FuturesUnordered<_>>();
 
                 while let Some(result) = tasks.next().await {
+                    metrics.finished(match &result {
+                        Ok((_, TaskState::Succeeded)) => Some(true),
+                        Ok((_, TaskState::Dead | TaskState::Pending)) | Err(_) => Some(false),
+                        // Cancelled or taken over by another worker
+                        Ok(_) => None,
+                    });
+
                     match result {
-                        Ok(id) => tracing::info!("Task {id} executed successfully"),
+                        Ok((id, TaskState::Succeeded)) => {
+                            tracing::info!("Task {id} executed successfully")
+                        }
+                        Ok((id, TaskState::Dead)) => {
+                            tracing::error!("Task {id} failed and is out of retries")
+                        }
+                        Ok((id, TaskState::Cancelled)) => tracing::info!("Task {id} was cancelled"),
+                        Ok((id, TaskState::Pending)) => {
+                            tracing::warn!("Task {id} failed and will be retried")
+                        }
+                        Ok((id, state)) => tracing::warn!("Task {id} was left {state}"),
                         Err(e) => {
                             tracing::error!("Error executing task: {e}");
                         }
@@ -140,67 +256,63 @@ impl WatchdogClient {
                 }
             });
 
-            tokio::time::sleep(Duration::from_secs(
-                self.watchdog.rate_limiter_refresh_interval,
-            ))
-            .await;
+            tokio::time::sleep(Duration::from_secs(self.watchdog.task_poll_interval_secs)).await;
         }
     }
 }
 
-async fn execute(
-    task: Task,
-    http_client: reqwest::Client,
-    tasks_store: MongoStore<Task>,
-    timeout: u64,
-) -> Result<Id, PicaError> {
-    let timeout = if task.r#await {
-        Duration::from_secs(300)
-    } else {
-        Duration::from_secs(timeout)
-    };
-
-    let response = http_client
-        .post(task.endpoint)
-        .timeout(timeout)
-        .json(&task.payload)
-        .send()
-        .await?;
-
-    let status = response.status();
-    let mut stream = response.bytes_stream();
-    let mut log_trail = vec![];
-
-    while let Some(item) = stream.next().await {
-        tracing::debug!("Response from API {:?}", item);
-        log_trail.push(item);
-    }
+async fn refresh_oauth_connections(
+    oauth_refresher: OAuthRefresher,
+    cache_invalidator: CacheInvalidator,
+    watchdog: WatchdogConfig,
+) {
+    info!("OAuth refresh enabled");
 
-    let log_trail = log_trail
-        .into_iter()
-        .filter_map(|x| x.ok())
-        .collect::<Vec<_>>();
-
-    let bson_log_trail = bson::to_bson(&log_trail).map_err(|e| {
-        error!("Could not convert log trail to BSON: {e}");
-        InternalError::io_err(e.to_string().as_str(), None)
-    })?;
-
-    tasks_store
-        .collection
-        .find_one_and_update(
-            doc! {
-                "_id": task.id.to_string() // Filter by task ID
-            },
-            doc! {
-                "$set": {
-                    "status": status.to_string(),
-                    "endTime": Utc::now().timestamp_millis(),
-                    "logTrail": bson_log_trail,
-                }
-            },
-        )
-        .await?;
+    loop {
+        match oauth_refresher
+            .expiring(
+                watchdog.oauth_refresh_window_secs,
+                watchdog.oauth_refresh_retry_secs,
+                watchdog.oauth_refresh_batch_size,
+            )
+            .await
+        {
+            Ok(connections) => {
+                info!("Refreshing {} oauth connections", connections.len());
+
+                futures::stream::iter(connections)
+                    .for_each_concurrent(watchdog.oauth_refresh_concurrency, |connection| {
+                        let oauth_refresher = &oauth_refresher;
+                        let cache_invalidator = &cache_invalidator;
+                        async move {
+                            match oauth_refresher.refresh(&connection).await {
+                                Ok((refreshed, _)) => {
+                                    info!("Refreshed oauth connection {}", connection.id);
+                                    // Cached copies point to the secret the refresh deleted
+                                    if let Err(e) = cache_invalidator
+                                        .publish(CacheInvalidation::from(&refreshed))
+                                        .await
+                                    {
+                                        error!(
+                                            "Could not invalidate oauth connection {}: {e}",
+                                            connection.id
+                                        )
+                                    }
+                                }
+                                Err(e) => {
+                                    error!(
+                                        "Could not refresh oauth connection {}: {e}",
+                                        connection.id
+                                    )
+                                }
+                            }
+                        }
+                    })
+                    .await;
+            }
+            Err(e) => error!("Could not fetch expiring oauth connections: {e}"),
+        }
 
-    Ok(task.id)
+        tokio::time::sleep(Duration::from_secs(watchdog.oauth_refresh_interval_secs)).await;
+    }
 }
@@ -1,15 +1,81 @@
+use cache::invalidation::CacheInvalidationTransport;
 use envconfig::Envconfig;
-use osentities::{cache::CacheConfig, database::DatabaseConfig};
-use std::fmt::{Display, Formatter};
+use osentities::{cache::CacheConfig, database::DatabaseConfig, secrets::SecretsConfig};
+use std::{
+    fmt::{Display, Formatter},
+    net::SocketAddr,
+};
 
 #[derive(Envconfig, Clone)] // Intentionally no Debug so secret is not printed
 pub struct WatchdogConfig {
-    #[envconfig(from = "RATE_LIMITER_REFRESH_INTERVAL", default = "10")]
-    pub rate_limiter_refresh_interval: u64,
+    #[envconfig(from = "INTERNAL_SERVER_ADDRESS", default = "0.0.0.0:3007")]
+    pub address: SocketAddr,
+    /// Liveness fails once the task loop or the sweeper stalls for longer
+    #[envconfig(from = "LIVENESS_THRESHOLD_SECS", default = "120")]
+    pub liveness_threshold_secs: u64,
+    #[envconfig(from = "READINESS_TIMEOUT_SECS", default = "2")]
+    pub readiness_timeout_secs: u64,
+    #[envconfig(from = "TASK_POLL_INTERVAL_SECS", default = "10")]
+    pub task_poll_interval_secs: u64,
     #[envconfig(from = "HTTP_CLIENT_TIMEOUT_SECS", default = "10")]
     pub http_client_timeout_secs: u64,
     #[envconfig(from = "MAX_AMOUNT_OF_TASKS_TO_PROCESS", default = "100")]
     pub max_amount_of_tasks_to_process: u64,
+    /// Responses of tasks are truncated beyond this size, as received or as stored
+    #[envconfig(from = "TASK_LOG_MAX_BYTES", default = "1048576")]
+    pub task_log_max_bytes: usize,
+    /// Identifies this replica in task leases, generated from the hostname if unset
+    #[envconfig(from = "WORKER_ID")]
+    pub worker_id: Option<String>,
+    /// Tasks whose lease is not renewed within this window are reclaimed
+    #[envconfig(from = "TASK_LEASE_SECS", default = "60")]
+    pub task_lease_secs: u64,
+    #[envconfig(from = "TASK_HEARTBEAT_INTERVAL_SECS", default = "20")]
+    pub task_heartbeat_interval_secs: u64,
+    /// Signs deliveries of tasks that reference a secret
+    #[envconfig(from = "TASK_SIGNING_ENABLED", default = "false")]
+    pub task_signing_enabled: bool,
+    /// Runs tasks that reference a connection action
+    #[envconfig(from = "TASK_ACTIONS_ENABLED", default = "false")]
+    pub task_actions_enabled: bool,
+    #[envconfig(from = "CACHE_SIZE", default = "100")]
+    pub cache_size: u64,
+    #[envconfig(from = "CONNECTION_CACHE_TTL_SECS", default = "120")]
+    pub connection_cache_ttl_secs: u64,
+    #[envconfig(from = "CONNECTION_MODEL_SCHEMA_TTL_SECS", default = "86400")]
+    pub connection_model_schema_cache_ttl_secs: u64,
+    #[envconfig(from = "CONNECTION_MODEL_DEFINITION_CACHE_TTL_SECS", default = "86400")]
+    pub connection_model_definition_cache_ttl_secs: u64,
+    #[envconfig(from = "SECRET_CACHE_TTL_SECS", default = "300")]
+    pub secret_cache_ttl_secs: u64,
+    #[envconfig(from = "CONNECTION_NEGATIVE_CACHE_TTL_SECS", default = "10")]
+    pub connection_negative_cache_ttl_secs: u64,
+    #[envconfig(
+        from = "CONNECTION_MODEL_DEFINITION_NEGATIVE_CACHE_TTL_SECS",
+        default = "60"
+    )]
+    pub connection_model_definition_negative_cache_ttl_secs: u64,
+    #[envconfig(from = "OAUTH_REFRESH_ENABLED", default = "false")]
+    pub oauth_refresh_enabled: bool,
+    #[envconfig(from = "OAUTH_REFRESH_INTERVAL_SECS", default = "60")]
+    pub oauth_refresh_interval_secs: u64,
+    /// Connections expiring within this window are refreshed
+    #[envconfig(from = "OAUTH_REFRESH_WINDOW_SECS", default = "600")]
+    pub oauth_refresh_window_secs: i64,
+    /// Connections whose last refresh attempt failed are skipped for this
+    /// long, so that they don't hold back the rest of the batch
+    #[envconfig(from = "OAUTH_REFRESH_RETRY_SECS", default = "300")]
+    pub oauth_refresh_retry_secs: i64,
+    #[envconfig(from = "OAUTH_REFRESH_BATCH_SIZE", default = "100")]
+    pub oauth_refresh_batch_size: u64,
+    #[envconfig(from = "OAUTH_REFRESH_CONCURRENCY", default = "10")]
+    pub oauth_refresh_concurrency: usize,
+    /// How the API replicas are told to evict connections refreshed here
+    /// (`none`, `redis` or `mongo`)
+    #[envconfig(from = "CACHE_INVALIDATION_TRANSPORT", default = "none")]
+    pub cache_invalidation_transport: CacheInvalidationTransport,
+    #[envconfig(nested = true)]
+    pub secrets_config: SecretsConfig,
     #[envconfig(nested = true)]
     pub redis: CacheConfig,
     #[envconfig(nested = true)]
@@ -18,16 +84,97 @@ pub struct WatchdogConfig {
 
 impl Display for WatchdogConfig {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "INTERNAL_SERVER_ADDRESS: {}", self.address)?;
+        writeln!(
+            f,
+            "LIVENESS_THRESHOLD_SECS: {}",
+            self.liveness_threshold_secs
+        )?;
+        writeln!(f, "READINESS_TIMEOUT_SECS: {}", self.readiness_timeout_secs)?;
         writeln!(
             f,
-            "RATE_LIMITER_REFRESH_INTERVAL: {}",
-            self.rate_limiter_refresh_interval
+            "TASK_POLL_INTERVAL_SECS: {}",
+            self.task_poll_interval_secs
         )?;
         writeln!(
             f,
             "HTTP_CLIENT_TIMEOUT_SECS: {}",
             self.http_client_timeout_secs
         )?;
+        writeln!(
+            f,
+            "MAX_AMOUNT_OF_TASKS_TO_PROCESS: {}",
+            self.max_amount_of_tasks_to_process
+        )?;
+        writeln!(f, "TASK_LOG_MAX_BYTES: {}", self.task_log_max_bytes)?;
+        writeln!(f, "WORKER_ID: {:?}", self.worker_id)?;
+        writeln!(f, "TASK_LEASE_SECS: {}", self.task_lease_secs)?;
+        writeln!(
+            f,
+            "TASK_HEARTBEAT_INTERVAL_SECS: {}",
+            self.task_heartbeat_interval_secs
+        )?;
+        writeln!(f, "TASK_SIGNING_ENABLED: {}", self.task_signing_enabled)?;
+        writeln!(f, "TASK_ACTIONS_ENABLED: {}", self.task_actions_enabled)?;
+        writeln!(f, "CACHE_SIZE: {}", self.cache_size)?;
+        writeln!(
+            f,
+            "CONNECTION_CACHE_TTL_SECS: {}",
+            self.connection_cache_ttl_secs
+        )?;
+        writeln!(
+            f,
+            "CONNECTION_MODEL_SCHEMA_TTL_SECS: {}",
+            self.connection_model_schema_cache_ttl_secs
+        )?;
+        writeln!(
+            f,
+            "CONNECTION_MODEL_DEFINITION_CACHE_TTL_SECS: {}",
+            self.connection_model_definition_cache_ttl_secs
+        )?;
+        writeln!(f, "SECRET_CACHE_TTL_SECS: {}", self.secret_cache_ttl_secs)?;
+        writeln!(
+            f,
+            "CONNECTION_NEGATIVE_CACHE_TTL_SECS: {}",
+            self.connection_negative_cache_ttl_secs
+        )?;
+        writeln!(
+            f,
+            "CONNECTION_MODEL_DEFINITION_NEGATIVE_CACHE_TTL_SECS: {}",
+            self.connection_model_definition_negative_cache_ttl_secs
+        )?;
+        writeln!(f, "OAUTH_REFRESH_ENABLED: {}", self.oauth_refresh_enabled)?;
+        writeln!(
+            f,
+            "OAUTH_REFRESH_INTERVAL_SECS: {}",
+            self.oauth_refresh_interval_secs
+        )?;
+        writeln!(
+            f,
+            "OAUTH_REFRESH_WINDOW_SECS: {}",
+            self.oauth_refresh_window_secs
+        )?;
+        writeln!(
+            f,
+            "OAUTH_REFRESH_RETRY_SECS: {}",
+            self.oauth_refresh_retry_secs
+        )?;
+        writeln!(
+            f,
+            "OAUTH_REFRESH_BATCH_SIZE: {}",
+            self.oauth_refresh_batch_size
+        )?;
+        writeln!(
+            f,
+            "OAUTH_REFRESH_CONCURRENCY: {}",
+            self.oauth_refresh_concurrency
+        )?;
+        writeln!(
+            f,
+            "CACHE_INVALIDATION_TRANSPORT: {}",
+            self.cache_invalidation_transport.as_ref()
+        )?;
+        write!(f, "{}", self.secrets_config)?;
         writeln!(f, "{}", self.redis)?;
         writeln!(f, "{}", self.db)
     }
@@ -0,0 +1,1183 @@
+use crate::log_trail::LogTrail;
+use bson::doc;
+use chrono::Utc;
+use futures::StreamExt;
+use mongodb::options::ReturnDocument;
+use osentities::{
+    constant::{TASK_SIGNATURE_HEADER, TASK_TIMESTAMP_HEADER},
+    destination::{Action, Destination},
+    prefix::IdPrefix,
+    record_metadata::RecordMetadata,
+    settle_dependents, sign_task_payload,
+    task::{Task, TaskAction, TaskAttempt, TaskLogContent, TaskLogEntry, TaskSecretRef, TaskState},
+    ApplicationError, Id, InternalError, MongoStore, PicaError, SecretExt, Unit,
+};
+use reqwest::{header::CONTENT_TYPE, Method, StatusCode};
+use serde_json::Value;
+use std::{sync::Arc, time::Duration};
+use tracing::{error, info, warn};
+use unified::{domain::RequestCrudBuilder, unified::UnifiedDestination};
+
+/// What a task's endpoint or action responded with
+struct Delivery {
+    status: StatusCode,
+    logs: Vec<TaskLogEntry>,
+    result: Option<Value>,
+}
+
+/// Claims due tasks under a lease and delivers them
+#[derive(Clone)]
+pub struct TaskExecutor {
+    pub http_client: reqwest::Client,
+    pub tasks: MongoStore<Task>,
+    /// Resolves the keys deliveries are signed with, `None` if signing is disabled
+    pub secrets_client: Option<Arc<dyn SecretExt + Sync + Send>>,
+    /// Runs the tasks with an action, `None` if actions are disabled
+    pub unified: Option<Arc<UnifiedDestination>>,
+    /// Identifies this replica in the leases it holds
+    pub worker: Arc<str>,
+    pub timeout_secs: u64,
+    pub max_log_bytes: usize,
+    pub lease_secs: u64,
+    pub heartbeat_interval_secs: u64,
+}
+
+impl TaskExecutor {
+    /// Atomically takes the next due task, after retrying or burying the
+    /// running tasks whose lease expired because their worker stopped
+    /// renewing it
+    pub async fn claim(&self) -> Result<Option<Task>, PicaError> {
+        while self.recover_expired_lease().await? {}
+
+        let now = Utc::now().timestamp_millis();
+
+        self.tasks
+            .collection
+            .find_one_and_update(
+                doc! {
+                    "deleted": false,
+                    "active": true,
+                    "workerId": 0,
+                    "startTime": { "$lte": now },
+                },
+                doc! {
+                    "$set": {
+                        "workerId": 1,
+                        "active": false,
+                        "state": TaskState::Running.to_string(),
+                        "lease": {
+                            "worker": self.worker.as_ref(),
+                            "expiresAt": self.lease_expiry(now),
+                            "heartbeatAt": now,
+                        },
+                    }
+                },
+            )
+            .sort(doc! { "startTime": 1 })
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Counts an expired lease as a failed attempt of its task, so that a task
+    /// that keeps taking its worker down runs out of retries like any other.
+    /// Returns whether an expired lease was found.
+    async fn recover_expired_lease(&self) -> Result<bool, PicaError> {
+        let now = Utc::now().timestamp_millis();
+
+        let Some(task) = self
+            .tasks
+            .get_one(doc! {
+                "deleted": false,
+                "state": TaskState::Running.to_string(),
+                "lease.expiresAt": { "$lt": now },
+            })
+            .await?
+        else {
+            return Ok(false);
+        };
+        let Some(lease) = &task.lease else {
+            return Ok(false);
+        };
+
+        warn!(
+            "Recovering task {} from worker {} whose lease expired at {}",
+            task.id, lease.worker, lease.expires_at
+        );
+
+        let attempt = TaskAttempt {
+            number: task.attempts.len() as u32 + 1,
+            started_at: lease.heartbeat_at,
+            ended_at: lease.expires_at,
+            status: None,
+            error: Some("lease expired".to_string()),
+        };
+        let bson_attempt = bson::to_bson(&attempt).map_err(|e| {
+            error!("Could not convert task attempt to BSON: {e}");
+            InternalError::io_err(e.to_string().as_str(), None)
+        })?;
+
+        let mut set = doc! {};
+        let state = if let Some(retry_at) = task.retry_at(attempt.number, now) {
+            set.insert("startTime", retry_at);
+            set.insert("workerId", 0);
+            set.insert("active", true);
+            TaskState::Pending
+        } else {
+            set.insert("endTime", now);
+            TaskState::Dead
+        };
+        set.insert("state", state.to_string());
+
+        // Only one worker recovers the task, and not if its lease was renewed
+        let current = self
+            .tasks
+            .collection
+            .find_one_and_update(
+                doc! {
+                    "_id": task.id.to_string(),
+                    "lease.worker": lease.worker.as_str(),
+                    "lease.expiresAt": lease.expires_at,
+                },
+                doc! {
+                    "$set": set,
+                    "$unset": { "lease": "" },
+                    "$push": { "attempts": bson_attempt },
+                },
+            )
+            .return_document(ReturnDocument::After)
+            .await?;
+
+        if let Some(current) = current.filter(|_| state == TaskState::Dead) {
+            self.finished(current).await?;
+        }
+
+        Ok(true)
+    }
+
+    fn lease_expiry(&self, now: i64) -> i64 {
+        now + Duration::from_secs(self.lease_secs).as_millis() as i64
+    }
+
+    /// Extends the lease on a task for as long as it is being delivered,
+    /// returning once the lease is lost, which is also how cancellations of
+    /// running tasks are noticed
+    async fn heartbeat(&self, id: Id) {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.heartbeat_interval_secs.max(1)));
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            let now = Utc::now().timestamp_millis();
+            let renewed = self
+                .tasks
+                .collection
+                .update_one(
+                    doc! { "_id": id.to_string(), "lease.worker": self.worker.as_ref() },
+                    doc! {
+                        "$set": {
+                            "lease.expiresAt": self.lease_expiry(now),
+                            "lease.heartbeatAt": now,
+                        }
+                    },
+                )
+                .await;
+
+            match renewed {
+                Ok(result) if result.matched_count == 0 => {
+                    warn!("Lost the lease on task {id}");
+                    return;
+                }
+                Ok(_) => {}
+                Err(e) => error!("Could not renew the lease on task {id}: {e}"),
+            }
+        }
+    }
+
+    pub async fn execute(&self, task: Task) -> Result<(Id, TaskState), PicaError> {
+        let timeout = if task.r#await {
+            Duration::from_secs(300)
+        } else {
+            Duration::from_secs(self.timeout_secs)
+        };
+
+        let started_at = Utc::now().timestamp_millis();
+        let delivery = tokio::select! {
+            delivery = self.deliver(&task, timeout) => delivery,
+            // Dropping the delivery aborts the request in flight
+            _ = self.heartbeat(task.id) => return self.abandon(task.id).await,
+        };
+        let ended_at = Utc::now().timestamp_millis();
+
+        let (status, logs, result, error) = match delivery {
+            Ok(Delivery {
+                status,
+                logs,
+                result,
+            }) => (Some(status), logs, result, None),
+            Err(e) => (None, vec![], None, Some(e.to_string())),
+        };
+
+        let attempt = TaskAttempt {
+            number: task.attempts.len() as u32 + 1,
+            started_at,
+            ended_at,
+            status: status.map(|status| status.as_u16()),
+            error,
+        };
+
+        let bson_logs = bson::to_bson(&logs).map_err(|e| {
+            error!("Could not convert logs to BSON: {e}");
+            InternalError::io_err(e.to_string().as_str(), None)
+        })?;
+        let bson_attempt = bson::to_bson(&attempt).map_err(|e| {
+            error!("Could not convert task attempt to BSON: {e}");
+            InternalError::io_err(e.to_string().as_str(), None)
+        })?;
+
+        let mut set = doc! {
+            "status": status.map(|status| status.to_string()),
+            "logs": bson_logs,
+        };
+        if let Some(result) = result.filter(|_| attempt.succeeded()) {
+            let bson_result = bson::to_bson(&result).map_err(|e| {
+                error!("Could not convert task result to BSON: {e}");
+                InternalError::io_err(e.to_string().as_str(), None)
+            })?;
+            set.insert("result", bson_result);
+        }
+
+        let state = if attempt.succeeded() {
+            set.insert("endTime", ended_at);
+            TaskState::Succeeded
+        } else if let Some(retry_at) = task.retry_at(attempt.number, ended_at) {
+            set.insert("startTime", retry_at);
+            set.insert("workerId", 0);
+            set.insert("active", true);
+            TaskState::Pending
+        } else {
+            set.insert("endTime", ended_at);
+            TaskState::Dead
+        };
+        set.insert("state", state.to_string());
+
+        // Another worker owns the task if our lease lapsed, so its outcome wins
+        let current = self
+            .tasks
+            .collection
+            .find_one_and_update(
+                doc! {
+                    "_id": task.id.to_string(),
+                    "lease.worker": self.worker.as_ref(),
+                },
+                doc! {
+                    "$set": set,
+                    "$unset": { "lease": "" },
+                    "$push": { "attempts": bson_attempt },
+                },
+            )
+            .return_document(ReturnDocument::After)
+            .await?;
+
+        let Some(current) = current else {
+            warn!(
+                "Discarding the outcome of task {} after losing its lease",
+                task.id
+            );
+            return Ok((task.id, state));
+        };
+
+        if state != TaskState::Pending {
+            // The stored task has the schedule as of now, in case it was paused meanwhile
+            self.finished(current).await?;
+        }
+
+        Ok((task.id, state))
+    }
+
+    /// Lets the dependents and the schedule of a task that finished move on
+    async fn finished(&self, task: Task) -> Result<Unit, PicaError> {
+        if let Err(e) = settle_dependents(&self.tasks, task.id).await {
+            error!("Could not settle the dependents of task {}: {e}", task.id);
+        }
+
+        self.schedule_next_occurrence(task).await
+    }
+
+    /// Gives up on a task whose lease was taken away, leaving it to whoever
+    /// holds it now
+    async fn abandon(&self, id: Id) -> Result<(Id, TaskState), PicaError> {
+        let state = self
+            .tasks
+            .get_one_by_id(&id.to_string())
+            .await?
+            .map(|task| task.state)
+            .unwrap_or(TaskState::Cancelled);
+
+        info!("Abandoned task {id}, which is now {state}");
+
+        Ok((id, state))
+    }
+
+    async fn deliver(&self, task: &Task, timeout: Duration) -> Result<Delivery, PicaError> {
+        if let Some(action) = &task.action {
+            return tokio::time::timeout(timeout, self.run_action(task, action))
+                .await
+                .map_err(|_| InternalError::timeout("The action timed out", None))?;
+        }
+
+        let body = if matches!(task.method, Method::GET | Method::HEAD) {
+            vec![]
+        } else {
+            serde_json::to_vec(&task.payload)
+                .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?
+        };
+
+        let mut request = self
+            .http_client
+            .request(task.method.clone(), &task.endpoint)
+            .timeout(timeout)
+            .headers(task.headers.clone());
+
+        if !body.is_empty() {
+            request = request.header(CONTENT_TYPE, "application/json");
+        }
+
+        if let Some(secret) = &task.secret {
+            let key = self.signing_key(secret).await?;
+            let timestamp = Utc::now().timestamp();
+
+            request = request.header(TASK_TIMESTAMP_HEADER, timestamp).header(
+                TASK_SIGNATURE_HEADER,
+                sign_task_payload(&key, timestamp, &body),
+            );
+        }
+
+        let response = request.body(body).send().await?;
+        let status = response.status();
+        let (logs, _) = self.read_response(task.id, response, false).await;
+
+        Ok(Delivery {
+            status,
+            logs,
+            result: None,
+        })
+    }
+
+    /// Runs the action through the same destinations as the unified and
+    /// passthrough APIs, using the connection's own credentials
+    async fn run_action(&self, task: &Task, action: &TaskAction) -> Result<Delivery, PicaError> {
+        let unified = self
+            .unified
+            .as_ref()
+            .ok_or_else(|| InternalError::configuration_error("Task actions are disabled", None))?;
+
+        let connection = unified
+            .connections_store
+            .get_one(doc! {
+                "key": action.connection_key.as_ref(),
+                "ownership.buildableId": &action.buildable_id,
+                "deleted": false,
+            })
+            .await?
+            .map(Arc::new)
+            .ok_or_else(|| ApplicationError::not_found("Connection", None))?;
+
+        let body = match &task.payload {
+            Value::Null => None,
+            payload => Some(payload.clone()),
+        };
+
+        match &action.action {
+            Action::Passthrough { .. } => {
+                let destination = Destination {
+                    platform: connection.platform.clone(),
+                    action: action.action.clone(),
+                    connection_key: connection.key.clone(),
+                };
+                let context = body
+                    .map(|body| serde_json::to_vec(&body))
+                    .transpose()
+                    .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?;
+
+                let response = unified
+                    .dispatch_destination_request(
+                        Some(connection),
+                        &destination,
+                        task.headers.clone(),
+                        action.query_params.clone(),
+                        context,
+                    )
+                    .await?;
+                let status = response.status();
+                let (logs, body) = self.read_response(task.id, response, true).await;
+
+                Ok(Delivery {
+                    status,
+                    logs,
+                    result: body.map(|body| {
+                        serde_json::from_slice(&body).unwrap_or_else(|_| {
+                            Value::String(String::from_utf8_lossy(&body).into_owned())
+                        })
+                    }),
+                })
+            }
+            Action::Unified { .. } => {
+                let params = RequestCrudBuilder::default()
+                    .headers(task.headers.clone())
+                    .query_params(action.query_params.clone())
+                    .body(body)
+                    .build()
+                    .map_err(|e| {
+                        InternalError::invalid_argument(
+                            &format!("Error building request crud: {e}"),
+                            None,
+                        )
+                    })?;
+
+                let environment = connection.environment;
+                let response = unified
+                    .dispatch_unified_request(
+                        connection,
+                        action.action.clone(),
+                        environment,
+                        params,
+                    )
+                    .await?;
+                let (parts, result) = response.response.into_parts();
+
+                // Results beyond the cap are dropped like oversized logs
+                let size = serde_json::to_vec(&result).map_or(usize::MAX, |body| body.len());
+                let (logs, result) = if size > self.max_log_bytes {
+                    warn!("Truncated the result of task {}", task.id);
+                    let truncated = TaskLogEntry {
+                        timestamp: Utc::now().timestamp_millis(),
+                        content: TaskLogContent::Truncated {
+                            max_bytes: self.max_log_bytes as u64,
+                        },
+                    };
+                    (vec![truncated], None)
+                } else {
+                    (vec![], Some(result))
+                };
+
+                Ok(Delivery {
+                    status: parts.status,
+                    logs,
+                    result,
+                })
+            }
+        }
+    }
+
+    /// Reads a response into log entries, along with the whole body if asked
+    /// for and it fits within the cap
+    async fn read_response(
+        &self,
+        id: Id,
+        response: reqwest::Response,
+        keep_body: bool,
+    ) -> (Vec<TaskLogEntry>, Option<Vec<u8>>) {
+        let mut stream = response.bytes_stream();
+        let mut log_trail = LogTrail::new(self.max_log_bytes);
+        let mut body = keep_body.then(Vec::new);
+
+        while let Some(chunk) = stream.next().await {
+            let now = Utc::now().timestamp_millis();
+            match chunk {
+                Ok(chunk) if !log_trail.push(&chunk, now) => {
+                    warn!("Truncated the response of task {id}");
+                    body = None;
+                    break;
+                }
+                Ok(chunk) => {
+                    if let Some(body) = body.as_mut() {
+                        body.extend_from_slice(&chunk);
+                    }
+                }
+                Err(e) => {
+                    warn!("Could not read the response of task {id}: {e}");
+                    body = None;
+                    break;
+                }
+            }
+        }
+
+        (log_trail.finish(Utc::now().timestamp_millis()), body)
+    }
+
+    /// The secret is either the key itself or an object holding it under `secret`
+    async fn signing_key(&self, secret: &TaskSecretRef) -> Result<Vec<u8>, PicaError> {
+        let secrets_client = self
+            .secrets_client
+            .as_ref()
+            .ok_or_else(|| InternalError::configuration_error("Task signing is disabled", None))?;
+
+        let key = match secrets_client
+            .get(&secret.id, &secret.buildable_id)
+            .await?
+            .as_value()?
+        {
+            Value::String(key) => Some(key),
+            Value::Object(mut object) => match object.remove("secret") {
+                Some(Value::String(key)) => Some(key),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        key.map(String::into_bytes).ok_or_else(|| {
+            InternalError::invalid_argument(
+                &format!("Secret {} has no signing key", secret.id),
+                None,
+            )
+        })
+    }
+
+    async fn schedule_next_occurrence(&self, task: Task) -> Result<Unit, PicaError> {
+        let Some(schedule) = task.schedule.clone() else {
+            return Ok(());
+        };
+        let Some(start_time) = schedule.next_after(Utc::now())? else {
+            info!("Schedule of task {} has no further occurrences", task.id);
+            return Ok(());
+        };
+
+        let next = Task {
+            id: Id::now(IdPrefix::Task),
+            worker_id: 0,
+            start_time: start_time.timestamp_millis(),
+            end_time: None,
+            result: None,
+            status: None,
+            logs: vec![],
+            state: TaskState::Pending,
+            attempts: vec![],
+            series_id: Some(task.series_id.unwrap_or(task.id)),
+            lease: None,
+            // Paused occurrences are picked up again when the schedule is resumed
+            metadata: RecordMetadata {
+                active: !schedule.paused,
+                ..Default::default()
+            },
+            ..task
+        };
+
+        self.tasks.create_one(&next).await?;
+        info!("Scheduled task {} at {start_time}", next.id);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::async_trait;
+    use http::HeaderMap;
+    use mockito::{Matcher, Server};
+    use mongodb::{Client, Database};
+    use osentities::{
+        api_model_config::{ApiModelConfig, AuthMethod, SamplesInput, SchemasInput},
+        connection_model_definition::{
+            ConnectionModelDefinition, CrudAction, PlatformInfo, TestConnection,
+        },
+        database::DatabaseConfig,
+        environment::Environment,
+        ownership::Ownership,
+        settings::Settings,
+        settle_waiting_task,
+        task::{Backoff, Recurrence, TaskLease, TaskSchedule},
+        Connection, ConnectionType, Secret, SecretVersion, Store, Throughput,
+    };
+    use serde_json::json;
+    use std::{collections::HashMap, sync::OnceLock};
+    use testcontainers_modules::{
+        mongo::Mongo,
+        testcontainers::{clients::Cli as Docker, Container},
+    };
+    use unified::{domain::CommonModelValidation, unified::UnifiedCacheTTLs};
+    use uuid::Uuid;
+
+    static DOCKER: OnceLock<Docker> = OnceLock::new();
+    static MONGO: OnceLock<Container<'static, Mongo>> = OnceLock::new();
+
+    const OWNER: &str = "owner";
+    const CONNECTION_KEY: &str = "test::stripe::default";
+
+    struct MockSecretsClient;
+
+    #[async_trait]
+    impl SecretExt for MockSecretsClient {
+        async fn get(&self, _id: &str, buildable_id: &str) -> Result<Secret, PicaError> {
+            Ok(Secret::new(
+                json!({ "accessToken": "token" }).to_string(),
+                Some(SecretVersion::V2),
+                buildable_id.to_string(),
+                None,
+            ))
+        }
+
+        async fn create(&self, _secret: &Value, buildable_id: &str) -> Result<Secret, PicaError> {
+            self.get("", buildable_id).await
+        }
+
+        async fn delete(&self, _id: &str, _buildable_id: &str) -> Result<(), PicaError> {
+            Ok(())
+        }
+    }
+
+    /// A fresh database on the shared Mongo container
+    async fn database() -> DatabaseConfig {
+        let docker = DOCKER.get_or_init(Default::default);
+        let mongo = MONGO.get_or_init(|| docker.run(Mongo));
+        let url = format!(
+            "mongodb://127.0.0.1:{}/?directConnection=true",
+            mongo.get_host_port_ipv4(27017)
+        );
+
+        DatabaseConfig {
+            control_db_url: url,
+            control_db_name: Uuid::new_v4().to_string(),
+            ..Default::default()
+        }
+    }
+
+    async fn executor(
+        config: &DatabaseConfig,
+        worker: &str,
+        unified: Option<Arc<UnifiedDestination>>,
+    ) -> TaskExecutor {
+        TaskExecutor {
+            http_client: reqwest::Client::new(),
+            tasks: MongoStore::new(&db(config).await, &Store::Tasks)
+                .await
+                .unwrap(),
+            secrets_client: None,
+            unified,
+            worker: worker.into(),
+            timeout_secs: 10,
+            max_log_bytes: 1024,
+            lease_secs: 30,
+            heartbeat_interval_secs: 10,
+        }
+    }
+
+    async fn db(config: &DatabaseConfig) -> Database {
+        Client::with_uri_str(&config.control_db_url)
+            .await
+            .unwrap()
+            .database(&config.control_db_name)
+    }
+
+    async fn unified(config: &DatabaseConfig) -> Arc<UnifiedDestination> {
+        Arc::new(
+            UnifiedDestination::new(
+                config.clone(),
+                100,
+                Arc::new(MockSecretsClient),
+                UnifiedCacheTTLs {
+                    connection_cache_ttl_secs: 0,
+                    connection_model_definition_cache_ttl_secs: 0,
+                    connection_model_schema_cache_ttl_secs: 0,
+                    secret_cache_ttl_secs: 0,
+                    connection_negative_cache_ttl_secs: 0,
+                    connection_model_definition_negative_cache_ttl_secs: 0,
+                },
+                CommonModelValidation::default(),
+                None,
+            )
+            .await
+            .unwrap(),
+        )
+    }
+
+    /// A connection of `OWNER` and a passthrough definition calling `base_url`
+    async fn seed_connection(config: &DatabaseConfig, base_url: String) {
+        let db = db(config).await;
+        let connection_definition_id = Id::now(IdPrefix::ConnectionDefinition);
+
+        let connection = Connection {
+            id: Id::now(IdPrefix::Connection),
+            platform_version: "v1".to_string(),
+            connection_definition_id,
+            r#type: ConnectionType::Api {},
+            key: CONNECTION_KEY.into(),
+            group: "group".to_string(),
+            name: None,
+            environment: Environment::Test,
+            platform: "stripe".into(),
+            secrets_service_id: "secret".to_string(),
+            event_access_id: Id::now(IdPrefix::EventAccess),
+            access_key: "access-key".to_string(),
+            identity: None,
+            identity_type: None,
+            settings: Settings::default(),
+            throughput: Throughput {
+                key: "throughput".to_string(),
+                limit: 100,
+            },
+            ownership: Ownership {
+                id: OWNER.into(),
+                ..Default::default()
+            },
+            oauth: None,
+            has_error: false,
+            error: None,
+            record_metadata: RecordMetadata::default(),
+        };
+        MongoStore::<Connection>::new(&db, &Store::Connections)
+            .await
+            .unwrap()
+            .create_one(&connection)
+            .await
+            .unwrap();
+
+        let definition = ConnectionModelDefinition {
+            id: Id::now(IdPrefix::ConnectionModelDefinition),
+            connection_platform: "stripe".to_string(),
+            connection_definition_id,
+            platform_version: "v1".to_string(),
+            key: "api::stripe::v1::customer::create".to_string(),
+            title: "Create Customer".to_string(),
+            name: "Create Customer".to_string(),
+            model_name: "Customer".to_string(),
+            action: Method::POST,
+            action_name: CrudAction::Create,
+            platform_info: PlatformInfo::Api(ApiModelConfig {
+                base_url,
+                path: "customers".to_string(),
+                auth_method: AuthMethod::BearerToken {
+                    value: "{{accessToken}}".to_string(),
+                },
+                headers: None,
+                query_params: None,
+                content: None,
+                schemas: SchemasInput {
+                    headers: None,
+                    query_params: None,
+                    path_params: None,
+                    body: None,
+                    strict: false,
+                },
+                samples: SamplesInput {
+                    headers: None,
+                    query_params: None,
+                    path_params: None,
+                    body: None,
+                },
+                responses: vec![],
+                paths: None,
+            }),
+            extractor_config: None,
+            test_connection_status: TestConnection::default(),
+            test_connection_payload: None,
+            is_default_crud_mapping: None,
+            mapping: None,
+            supported: true,
+            knowledge: None,
+            record_metadata: RecordMetadata::default(),
+        };
+        MongoStore::<ConnectionModelDefinition>::new(&db, &Store::ConnectionModelDefinitions)
+            .await
+            .unwrap()
+            .create_one(&definition)
+            .await
+            .unwrap();
+    }
+
+    fn due_task(action: Option<TaskAction>) -> Task {
+        Task {
+            id: Id::now(IdPrefix::Task),
+            ownership: Ownership {
+                id: OWNER.into(),
+                ..Default::default()
+            },
+            environment: Some(Environment::Test),
+            worker_id: 0,
+            start_time: Utc::now().timestamp_millis() - 1,
+            end_time: None,
+            payload: json!({ "name": "Ada" }),
+            endpoint: String::new(),
+            method: Method::POST,
+            headers: HeaderMap::new(),
+            action,
+            result: None,
+            secret: None,
+            status: None,
+            r#await: false,
+            logs: vec![],
+            state: TaskState::Pending,
+            max_retries: 0,
+            backoff: Backoff::default(),
+            attempts: vec![],
+            schedule: None,
+            series_id: None,
+            depends_on: vec![],
+            cascade_failure: false,
+            lease: None,
+            metadata: RecordMetadata::default(),
+        }
+    }
+
+    fn passthrough(buildable_id: &str) -> TaskAction {
+        TaskAction {
+            connection_key: CONNECTION_KEY.into(),
+            buildable_id: buildable_id.to_string(),
+            action: Action::Passthrough {
+                method: Method::POST,
+                path: "customers".into(),
+                id: None,
+            },
+            query_params: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_action_stores_result() {
+        let mut mock_server = Server::new_async().await;
+        let mock = mock_server
+            .mock("POST", "/customers")
+            .match_header("authorization", "Bearer token")
+            .match_body(Matcher::Json(json!({ "name": "Ada" })))
+            .with_status(201)
+            .with_body(r#"{"id":"cus_1"}"#)
+            .create_async()
+            .await;
+
+        let config = database().await;
+        seed_connection(&config, mock_server.url()).await;
+        let executor = executor(&config, "worker", Some(unified(&config).await)).await;
+
+        let task = due_task(Some(passthrough(OWNER)));
+        executor.tasks.create_one(&task).await.unwrap();
+
+        let claimed = executor.claim().await.unwrap().unwrap();
+        let (id, state) = executor.execute(claimed).await.unwrap();
+
+        assert_eq!((id, state), (task.id, TaskState::Succeeded));
+        mock.assert_async().await;
+
+        let stored = executor
+            .tasks
+            .get_one_by_id(&task.id.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.result, Some(json!({ "id": "cus_1" })));
+        assert_eq!(stored.status.as_deref(), Some("201 Created"));
+    }
+
+    #[tokio::test]
+    async fn test_next_occurrence_starts_without_result() {
+        let mut mock_server = Server::new_async().await;
+        mock_server
+            .mock("POST", "/customers")
+            .with_status(201)
+            .with_body(r#"{"id":"cus_1"}"#)
+            .create_async()
+            .await;
+
+        let config = database().await;
+        seed_connection(&config, mock_server.url()).await;
+        let executor = executor(&config, "worker", Some(unified(&config).await)).await;
+
+        let task = Task {
+            schedule: Some(TaskSchedule {
+                recurrence: Recurrence::Interval { every_ms: 60_000 },
+                timezone: "UTC".to_string(),
+                paused: false,
+            }),
+            ..due_task(Some(passthrough(OWNER)))
+        };
+        executor.tasks.create_one(&task).await.unwrap();
+
+        let claimed = executor.claim().await.unwrap().unwrap();
+        executor.execute(claimed).await.unwrap();
+
+        let next = executor
+            .tasks
+            .get_one(doc! { "seriesId": task.id.to_string() })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(next.state, TaskState::Pending);
+        assert_eq!(next.result, None);
+        assert_eq!(next.status, None);
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_action_of_another_ownership_fails() {
+        let mut mock_server = Server::new_async().await;
+        let mock = mock_server
+            .mock("POST", "/customers")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let config = database().await;
+        seed_connection(&config, mock_server.url()).await;
+        let executor = executor(&config, "worker", Some(unified(&config).await)).await;
+
+        let task = due_task(Some(passthrough("someone-else")));
+        executor.tasks.create_one(&task).await.unwrap();
+
+        let claimed = executor.claim().await.unwrap().unwrap();
+        let (_, state) = executor.execute(claimed).await.unwrap();
+
+        assert_eq!(state, TaskState::Dead);
+        mock.assert_async().await;
+
+        let stored = executor
+            .tasks
+            .get_one_by_id(&task.id.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.result, None);
+        assert!(stored.attempts[0].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_claims_take_a_task_once() {
+        let config = database().await;
+        let first = executor(&config, "first", None).await;
+        let second = executor(&config, "second", None).await;
+
+        let task = due_task(None);
+        first.tasks.create_one(&task).await.unwrap();
+
+        let (a, b) = tokio::join!(first.claim(), second.claim());
+        let claimed = [a.unwrap(), b.unwrap()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].id, task.id);
+
+        let stored = first
+            .tasks
+            .get_one_by_id(&task.id.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.state, TaskState::Running);
+        assert!(!stored.metadata.active);
+        assert!(stored.lease.is_some());
+    }
+
+    fn expired(max_retries: u32) -> Task {
+        let now = Utc::now().timestamp_millis();
+
+        Task {
+            worker_id: 1,
+            state: TaskState::Running,
+            max_retries,
+            backoff: Backoff {
+                delay_ms: 0,
+                ..Default::default()
+            },
+            lease: Some(TaskLease {
+                worker: "first".to_string(),
+                expires_at: now - 1_000,
+                heartbeat_at: now - 31_000,
+            }),
+            metadata: RecordMetadata {
+                active: false,
+                ..Default::default()
+            },
+            ..due_task(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_claim_retries_task_of_expired_lease() {
+        let config = database().await;
+        let executor = executor(&config, "second", None).await;
+        let now = Utc::now().timestamp_millis();
+
+        let task = expired(1);
+        executor.tasks.create_one(&task).await.unwrap();
+
+        let claimed = executor.claim().await.unwrap().unwrap();
+        assert_eq!(claimed.id, task.id);
+        assert_eq!(claimed.attempts.len(), 1);
+        assert_eq!(claimed.attempts[0].error.as_deref(), Some("lease expired"));
+
+        let lease = executor
+            .tasks
+            .get_one_by_id(&task.id.to_string())
+            .await
+            .unwrap()
+            .unwrap()
+            .lease
+            .unwrap();
+        assert_eq!(lease.worker, "second");
+        assert!(lease.expires_at > now);
+    }
+
+    #[tokio::test]
+    async fn test_expired_lease_counts_against_retries() {
+        let config = database().await;
+        let executor = executor(&config, "second", None).await;
+
+        let task = expired(0);
+        executor.tasks.create_one(&task).await.unwrap();
+
+        assert!(executor.claim().await.unwrap().is_none());
+
+        let stored = executor
+            .tasks
+            .get_one_by_id(&task.id.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.state, TaskState::Dead);
+        assert!(stored.lease.is_none());
+        assert!(stored.end_time.is_some());
+        assert_eq!(stored.attempts.len(), 1);
+        assert_eq!(stored.attempts[0].error.as_deref(), Some("lease expired"));
+    }
+
+    #[tokio::test]
+    async fn test_claim_skips_held_lease() {
+        let config = database().await;
+        let executor = executor(&config, "second", None).await;
+        let now = Utc::now().timestamp_millis();
+
+        let task = Task {
+            worker_id: 1,
+            state: TaskState::Running,
+            lease: Some(TaskLease {
+                worker: "first".to_string(),
+                expires_at: now + 30_000,
+                heartbeat_at: now,
+            }),
+            metadata: RecordMetadata {
+                active: false,
+                ..Default::default()
+            },
+            ..due_task(None)
+        };
+        executor.tasks.create_one(&task).await.unwrap();
+
+        assert!(executor.claim().await.unwrap().is_none());
+    }
+
+    fn dependent(depends_on: Vec<Id>, cascade_failure: bool) -> Task {
+        Task {
+            state: TaskState::Waiting,
+            depends_on,
+            cascade_failure,
+            metadata: RecordMetadata {
+                active: false,
+                ..Default::default()
+            },
+            ..due_task(None)
+        }
+    }
+
+    fn finished(state: TaskState) -> Task {
+        Task {
+            state,
+            worker_id: 1,
+            metadata: RecordMetadata {
+                active: false,
+                ..Default::default()
+            },
+            ..due_task(None)
+        }
+    }
+
+    async fn stored(executor: &TaskExecutor, id: Id) -> Task {
+        executor
+            .tasks
+            .get_one_by_id(&id.to_string())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_task_is_released_once_all_dependencies_succeed() {
+        let config = database().await;
+        let executor = executor(&config, "worker", None).await;
+
+        let first = finished(TaskState::Succeeded);
+        let second = finished(TaskState::Running);
+        let task = dependent(vec![first.id, second.id], false);
+        for task in [&first, &second, &task] {
+            executor.tasks.create_one(task).await.unwrap();
+        }
+
+        assert_eq!(
+            settle_waiting_task(&executor.tasks, &task).await.unwrap(),
+            None
+        );
+
+        executor
+            .tasks
+            .update_one(
+                &second.id.to_string(),
+                doc! { "$set": { "state": TaskState::Succeeded.to_string() } },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            settle_waiting_task(&executor.tasks, &task).await.unwrap(),
+            Some(TaskState::Pending)
+        );
+
+        let stored = stored(&executor, task.id).await;
+        assert_eq!(stored.state, TaskState::Pending);
+        assert!(stored.metadata.active);
+    }
+
+    #[tokio::test]
+    async fn test_cascading_task_dies_with_its_dependency() {
+        let config = database().await;
+        let executor = executor(&config, "worker", None).await;
+
+        let parent = finished(TaskState::Dead);
+        let cascading = dependent(vec![parent.id], true);
+        let patient = dependent(vec![parent.id], false);
+        for task in [&parent, &cascading, &patient] {
+            executor.tasks.create_one(task).await.unwrap();
+        }
+
+        settle_dependents(&executor.tasks, parent.id).await.unwrap();
+
+        let cascading = stored(&executor, cascading.id).await;
+        assert_eq!(cascading.state, TaskState::Dead);
+        assert!(!cascading.metadata.active);
+        assert!(cascading.end_time.is_some());
+
+        let patient = stored(&executor, patient.id).await;
+        assert_eq!(patient.state, TaskState::Waiting);
+        assert!(!patient.metadata.active);
+    }
+
+    #[tokio::test]
+    async fn test_failures_cascade_transitively() {
+        let config = database().await;
+        let executor = executor(&config, "worker", None).await;
+
+        let root = finished(TaskState::Cancelled);
+        let child = dependent(vec![root.id], true);
+        let grandchild = dependent(vec![child.id], true);
+        for task in [&root, &child, &grandchild] {
+            executor.tasks.create_one(task).await.unwrap();
+        }
+
+        settle_dependents(&executor.tasks, root.id).await.unwrap();
+
+        assert_eq!(stored(&executor, child.id).await.state, TaskState::Dead);
+        assert_eq!(
+            stored(&executor, grandchild.id).await.state,
+            TaskState::Dead
+        );
+    }
+}
@@ -0,0 +1,289 @@
+use osentities::task::{TaskLogContent, TaskLogEntry};
+use serde_json::Value;
+
+/// What an entry adds to the `logs` array of a task beyond its own document:
+/// the element type and its index, which is stored as a key
+const ARRAY_ELEMENT_OVERHEAD: usize = 1 + 8;
+
+/// Splits a streamed response into log entries, keeping at most `max_bytes`
+/// of it, both as received and as stored. Short lines take much more room
+/// once stored than in the response.
+#[derive(Debug, Default)]
+pub struct LogTrail {
+    max_bytes: usize,
+    received: usize,
+    stored: usize,
+    line: Vec<u8>,
+    event: Option<PendingEvent>,
+    entries: Vec<TaskLogEntry>,
+    truncated: bool,
+}
+
+/// The fields of a server-sent event read so far
+#[derive(Debug, Default)]
+struct PendingEvent {
+    event: Option<String>,
+    id: Option<String>,
+    data: Vec<String>,
+}
+
+impl LogTrail {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            ..Default::default()
+        }
+    }
+
+    /// Adds a chunk of the response, returning `false` once the cap is
+    /// reached and the rest of the response should be dropped
+    pub fn push(&mut self, chunk: &[u8], now: i64) -> bool {
+        if self.truncated {
+            return false;
+        }
+
+        let remaining = self.max_bytes.saturating_sub(self.received);
+        let kept = &chunk[..chunk.len().min(remaining)];
+        self.received += kept.len();
+
+        for byte in kept {
+            if *byte == b'\n' {
+                let line = std::mem::take(&mut self.line);
+                self.read_line(&line, now);
+                if self.truncated {
+                    return false;
+                }
+            } else {
+                self.line.push(*byte);
+            }
+        }
+
+        if kept.len() < chunk.len() {
+            self.finish_entries(now);
+            self.truncate(now);
+            return false;
+        }
+
+        true
+    }
+
+    /// The entries of the response, including a trailing line without a
+    /// newline and an event without a closing blank line
+    pub fn finish(mut self, now: i64) -> Vec<TaskLogEntry> {
+        if !self.truncated {
+            self.finish_entries(now);
+        }
+
+        self.entries
+    }
+
+    /// Keeps an entry unless it would take the stored logs over the cap, in
+    /// which case the rest of the response is dropped
+    fn push_entry(&mut self, entry: TaskLogEntry) {
+        if self.truncated {
+            return;
+        }
+
+        let size = bson::to_vec(&entry).map_or(usize::MAX, |entry| entry.len());
+        match self
+            .stored
+            .checked_add(size + ARRAY_ELEMENT_OVERHEAD)
+            .filter(|stored| *stored <= self.max_bytes)
+        {
+            Some(stored) => {
+                self.stored = stored;
+                self.entries.push(entry);
+            }
+            None => self.truncate(entry.timestamp),
+        }
+    }
+
+    fn truncate(&mut self, now: i64) {
+        if self.truncated {
+            return;
+        }
+
+        self.truncated = true;
+        self.line.clear();
+        self.event = None;
+        self.entries.push(TaskLogEntry {
+            timestamp: now,
+            content: TaskLogContent::Truncated {
+                max_bytes: self.max_bytes as u64,
+            },
+        });
+    }
+
+    fn finish_entries(&mut self, now: i64) {
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.read_line(&line, now);
+        }
+        self.dispatch_event(now);
+    }
+
+    fn read_line(&mut self, line: &[u8], now: i64) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+
+        if line.is_empty() {
+            self.dispatch_event(now);
+            return;
+        }
+
+        if let Some((field, value)) = sse_field(line) {
+            let event = self.event.get_or_insert_with(Default::default);
+            match field {
+                "event" => event.event = Some(value.to_string()),
+                "id" => event.id = Some(value.to_string()),
+                "data" => event.data.push(value.to_string()),
+                // Comments and retry hints carry no payload
+                _ => {}
+            }
+            return;
+        }
+
+        let content = match serde_json::from_str::<Value>(line) {
+            Ok(data) => TaskLogContent::Json { data },
+            Err(_) => TaskLogContent::Text {
+                data: line.to_string(),
+            },
+        };
+
+        self.push_entry(TaskLogEntry {
+            timestamp: now,
+            content,
+        });
+    }
+
+    fn dispatch_event(&mut self, now: i64) {
+        let Some(event) = self.event.take() else {
+            return;
+        };
+        if event.data.is_empty() {
+            return;
+        }
+
+        let data = event.data.join("\n");
+        let data = serde_json::from_str::<Value>(&data).unwrap_or(Value::String(data));
+
+        self.push_entry(TaskLogEntry {
+            timestamp: now,
+            content: TaskLogContent::Event {
+                event: event.event,
+                id: event.id,
+                data,
+            },
+        });
+    }
+}
+
+/// Splits a server-sent event line into its field and value, `None` if the
+/// line is not one
+fn sse_field(line: &str) -> Option<(&str, &str)> {
+    if line.starts_with(':') {
+        return Some(("", ""));
+    }
+
+    let (field, value) = line.split_once(':')?;
+    matches!(field, "event" | "id" | "data" | "retry")
+        .then(|| (field, value.strip_prefix(' ').unwrap_or(value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn contents(entries: Vec<TaskLogEntry>) -> Vec<TaskLogContent> {
+        entries.into_iter().map(|entry| entry.content).collect()
+    }
+
+    #[test]
+    fn test_ndjson_split_across_chunks() {
+        let mut trail = LogTrail::new(1024);
+
+        assert!(trail.push(b"{\"a\":1}\n{\"b\"", 1));
+        assert!(trail.push(b":2}\nplain text", 2));
+
+        assert_eq!(
+            contents(trail.finish(3)),
+            vec![
+                TaskLogContent::Json {
+                    data: json!({ "a": 1 })
+                },
+                TaskLogContent::Json {
+                    data: json!({ "b": 2 })
+                },
+                TaskLogContent::Text {
+                    data: "plain text".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_server_sent_events() {
+        let mut trail = LogTrail::new(1024);
+
+        trail.push(
+            b": keepalive\r\nevent: progress\r\nid: 7\r\ndata: {\"done\":false}\r\n\r\ndata: bye\n",
+            1,
+        );
+
+        assert_eq!(
+            contents(trail.finish(2)),
+            vec![
+                TaskLogContent::Event {
+                    event: Some("progress".to_string()),
+                    id: Some("7".to_string()),
+                    data: json!({ "done": false }),
+                },
+                TaskLogContent::Event {
+                    event: None,
+                    id: None,
+                    data: json!("bye"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_truncation() {
+        let mut trail = LogTrail::new(200);
+
+        assert!(trail.push(b"first\n", 1));
+        assert!(!trail.push(&[b'a'; 300], 2));
+
+        assert_eq!(
+            contents(trail.finish(3)),
+            vec![
+                TaskLogContent::Text {
+                    data: "first".to_string()
+                },
+                TaskLogContent::Truncated { max_bytes: 200 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_truncation_counts_stored_size() {
+        let mut trail = LogTrail::new(4096);
+
+        assert!(!trail.push(&b"a\n".repeat(1000), 1));
+
+        let entries = trail.finish(2);
+        let (marker, kept) = entries.split_last().unwrap();
+        let stored = kept
+            .iter()
+            .map(|entry| bson::to_vec(entry).unwrap().len() + ARRAY_ELEMENT_OVERHEAD)
+            .sum::<usize>();
+
+        assert!(kept.len() > 1);
+        assert!(stored <= 4096);
+        assert_eq!(
+            marker.content,
+            TaskLogContent::Truncated { max_bytes: 4096 }
+        );
+    }
+}
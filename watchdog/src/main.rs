@@ -1,5 +1,9 @@
 mod client;
 mod config;
+mod executor;
+mod log_trail;
+mod metrics;
+mod server;
 
 use crate::client::WatchdogClient;
 use anyhow::{Context, Result};
@@ -0,0 +1,149 @@
+use chrono::Utc;
+use serde::Serialize;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+/// Counters shared between the watchdog loops and its HTTP server
+#[derive(Debug, Default)]
+pub struct WatchdogMetrics {
+    claimed: AtomicU64,
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+    in_flight: AtomicU64,
+    sweeps: AtomicU64,
+    sweep_failures: AtomicU64,
+    /// When the task loop last started an iteration, in milliseconds
+    loop_heartbeat: AtomicI64,
+    /// When the rate limiter sweeper last finished a sweep, in milliseconds
+    sweep_heartbeat: AtomicI64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsSnapshot {
+    pub tasks_claimed: u64,
+    pub tasks_succeeded: u64,
+    pub tasks_failed: u64,
+    pub tasks_in_flight: u64,
+    pub rate_limiter_sweeps: u64,
+    pub rate_limiter_sweep_failures: u64,
+}
+
+impl WatchdogMetrics {
+    pub fn new() -> Self {
+        let now = Utc::now().timestamp_millis();
+
+        Self {
+            loop_heartbeat: AtomicI64::new(now),
+            sweep_heartbeat: AtomicI64::new(now),
+            ..Default::default()
+        }
+    }
+
+    pub fn loop_beat(&self) {
+        self.loop_heartbeat
+            .store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    pub fn sweep(&self) {
+        self.sweeps.fetch_add(1, Ordering::Relaxed);
+        self.sweep_heartbeat
+            .store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    /// Records a sweep that didn't go through, which leaves the heartbeat
+    /// untouched
+    pub fn sweep_failed(&self) {
+        self.sweep_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn claimed(&self, count: u64) {
+        self.claimed.fetch_add(count, Ordering::Relaxed);
+        self.in_flight.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Records a task that finished executing, `succeeded` being whether its
+    /// attempt succeeded
+    pub fn finished(&self, succeeded: Option<bool>) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        match succeeded {
+            Some(true) => self.succeeded.fetch_add(1, Ordering::Relaxed),
+            Some(false) => self.failed.fetch_add(1, Ordering::Relaxed),
+            None => 0,
+        };
+    }
+
+    /// How long ago, in seconds, the task loop and the sweeper last made
+    /// progress
+    pub fn heartbeat_ages(&self, now: i64) -> (i64, i64) {
+        let age = |heartbeat: &AtomicI64| (now - heartbeat.load(Ordering::Relaxed)).max(0) / 1_000;
+
+        (age(&self.loop_heartbeat), age(&self.sweep_heartbeat))
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            tasks_claimed: self.claimed.load(Ordering::Relaxed),
+            tasks_succeeded: self.succeeded.load(Ordering::Relaxed),
+            tasks_failed: self.failed.load(Ordering::Relaxed),
+            tasks_in_flight: self.in_flight.load(Ordering::Relaxed),
+            rate_limiter_sweeps: self.sweeps.load(Ordering::Relaxed),
+            rate_limiter_sweep_failures: self.sweep_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_counters() {
+        let metrics = WatchdogMetrics::new();
+
+        metrics.claimed(3);
+        metrics.finished(Some(true));
+        metrics.finished(Some(false));
+        metrics.sweep();
+        metrics.sweep_failed();
+
+        assert_eq!(
+            metrics.snapshot(),
+            MetricsSnapshot {
+                tasks_claimed: 3,
+                tasks_succeeded: 1,
+                tasks_failed: 1,
+                tasks_in_flight: 1,
+                rate_limiter_sweeps: 1,
+                rate_limiter_sweep_failures: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_heartbeat_ages() {
+        let metrics = WatchdogMetrics::new();
+        let now = Utc::now().timestamp_millis();
+
+        metrics
+            .sweep_heartbeat
+            .store(now - 90_000, Ordering::Relaxed);
+
+        let (loop_age, sweep_age) = metrics.heartbeat_ages(now);
+        assert!(loop_age <= 1);
+        assert_eq!(sweep_age, 90);
+    }
+
+    #[test]
+    fn test_failed_sweeps_dont_beat() {
+        let metrics = WatchdogMetrics::new();
+        let now = Utc::now().timestamp_millis();
+
+        metrics
+            .sweep_heartbeat
+            .store(now - 90_000, Ordering::Relaxed);
+        metrics.sweep_failed();
+
+        assert_eq!(metrics.heartbeat_ages(now).1, 90);
+        assert_eq!(metrics.snapshot().rate_limiter_sweeps, 0);
+    }
+}
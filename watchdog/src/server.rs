@@ -0,0 +1,109 @@
+use crate::{config::WatchdogConfig, metrics::WatchdogMetrics};
+use anyhow::Result as AnyhowResult;
+use axum::{extract::State, response::IntoResponse, routing::get, Json, Router};
+use bson::doc;
+use chrono::Utc;
+use http::StatusCode;
+use mongodb::Database;
+use redis::aio::ConnectionManager;
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+use tokio::net::TcpListener;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub config: WatchdogConfig,
+    pub metrics: Arc<WatchdogMetrics>,
+    pub redis: ConnectionManager,
+    pub db: Database,
+}
+
+#[derive(Clone)]
+pub struct Server {
+    pub state: Arc<AppState>,
+}
+
+impl Server {
+    pub async fn run(&self) -> AnyhowResult<()> {
+        let app: Router<()> = get_router().with_state(self.state.clone());
+
+        tracing::info!("Watchdog server listening on {}", self.state.config.address);
+
+        let tcp_listener = TcpListener::bind(&self.state.config.address).await?;
+
+        axum::serve(tcp_listener, app.into_make_service())
+            .await
+            .map_err(|e| anyhow::anyhow!("Server error: {}", e))
+    }
+}
+
+fn get_router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", get(get_root))
+        .route("/health/live", get(get_liveness))
+        .route("/health/ready", get(get_readiness))
+        .route("/metrics", get(get_metrics))
+        .fallback(not_found_handler)
+}
+
+async fn get_root() -> impl IntoResponse {
+    Json(json!({ "success": true }))
+}
+
+/// Fails when the task loop or the sweeper stopped making progress, e.g.
+/// because they hang on Redis or Mongo
+async fn get_liveness(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let (loop_age, sweep_age) = state.metrics.heartbeat_ages(Utc::now().timestamp_millis());
+    let threshold = state.config.liveness_threshold_secs as i64;
+    let live = loop_age <= threshold && sweep_age <= threshold;
+
+    (
+        status(live),
+        Json(json!({
+            "success": live,
+            "loopHeartbeatAgeSecs": loop_age,
+            "sweepHeartbeatAgeSecs": sweep_age,
+        })),
+    )
+}
+
+async fn get_readiness(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let timeout = Duration::from_secs(state.config.readiness_timeout_secs);
+
+    let mut redis = state.redis.clone();
+    let ping = redis::cmd("PING");
+    let (redis, mongo) = tokio::join!(
+        tokio::time::timeout(timeout, ping.query_async::<String>(&mut redis)),
+        tokio::time::timeout(timeout, state.db.run_command(doc! { "ping": 1 })),
+    );
+    let redis = matches!(redis, Ok(Ok(_)));
+    let mongo = matches!(mongo, Ok(Ok(_)));
+
+    (
+        status(redis && mongo),
+        Json(json!({
+            "success": redis && mongo,
+            "redis": redis,
+            "mongo": mongo,
+        })),
+    )
+}
+
+async fn get_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.metrics.snapshot())
+}
+
+async fn not_found_handler() -> impl IntoResponse {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "Not found", })),
+    )
+}
+
+fn status(healthy: bool) -> StatusCode {
+    if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}